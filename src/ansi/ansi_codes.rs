@@ -4,32 +4,65 @@ macro_rules! ANSI_CODE {
     };
 }
 
-pub const ESC: u8 = b'\x1b';
 pub const BS: u8 = b'\x08';
 pub const BEL: u8 = b'\x07';
-pub const ESC_START: u8 = b'[';
+pub const ENQ: u8 = b'\x05';
+pub const SO: u8 = b'\x0e';
+pub const SI: u8 = b'\x0f';
 pub const HASH: u8 = ANSI_CODE!(2, 3); // # in normal
-pub const OSC_START: u8 = ANSI_CODE!(5, 13);
-pub const OSC_END: u8 = ANSI_CODE!(5, 12);
-pub const SCROLL_REVERSE: u8 = b'M';
+pub const REVERSE_INDEX: u8 = b'M';
 
 pub const FILL_WITH_E: u8 = b'8';
+pub const DOUBLE_HEIGHT_TOP: u8 = b'3';
+pub const DOUBLE_HEIGHT_BOTTOM: u8 = b'4';
+pub const SINGLE_WIDTH: u8 = b'5';
+pub const DOUBLE_WIDTH: u8 = b'6';
+pub const SAVE_CURSOR: u8 = b'7';
+pub const RESTORE_CURSOR: u8 = b'8';
 
-pub const STRING_TERMINATOR: u8 = ANSI_CODE!(9, 12);
 pub const SGR: u8 = b'm';
 pub const CURSOR_UP: u8 = b'A';
 pub const CURSOR_DOWN: u8 = b'B';
 pub const CURSOR_FORWARD: u8 = b'C';
 pub const CURSOR_BACKWARD: u8 = b'D';
-pub const HIDE_CURSOR: u8 = b'l';
-pub const SHOW_CURSOR: u8 = b'h';
+pub const SET_MODE: u8 = b'h';
+pub const RESET_MODE: u8 = b'l';
 pub const CLEAR_LINE: u8 = b'K';
 pub const CLEAR_EOS: u8 = b'J';
 pub const DELETE_CHARACTER: u8 = b'P';
+pub const INSERT_CHARACTER: u8 = b'@';
+pub const ERASE_CHARACTER: u8 = b'X';
+pub const REPEAT_CHARACTER: u8 = b'b';
+pub const TAB_CLEAR: u8 = b'g';
+pub const SCO_SAVE_CURSOR: u8 = b's';
+pub const DEVICE_ATTRIBUTES: u8 = b'c';
+pub const DEVICE_STATUS_REPORT: u8 = b'n';
+pub const XTVERSION: u8 = b'q'; // With a '>' intermediate
+pub const SET_CURSOR_STYLE: u8 = b'q'; // DECSCUSR, with a ' ' intermediate
+pub const SCO_RESTORE_CURSOR: u8 = b'u';
+pub const CURSOR_FORWARD_TAB: u8 = b'I';
+pub const CURSOR_BACKWARD_TAB: u8 = b'Z';
 pub const CURSOR_POSITION: u8 = b'H';
 pub const HVP: u8 = ANSI_CODE!(6, 6);
 pub const CURSOR_HORIZONTAL_POSITION: u8 = b'G';
-pub const DCS: u8 = b'p'; // Device Control String
+pub const REQUEST_MODE: u8 = b'p'; // DECRQM, with a '$' intermediate
+pub const SET_SCROLLING_REGION: u8 = b'r';
+pub const INSERT_LINES: u8 = b'L';
+pub const DELETE_LINES: u8 = b'M';
+pub const SCROLL_UP: u8 = b'S';
+pub const XTSMGRAPHICS: u8 = b'S'; // With a '?' intermediate
+pub const SCROLL_DOWN: u8 = b'T';
 
 pub const NEXT_LINE: u8 = b'E';
-pub const CURSOR_DOWNWARD: u8 = b'D';
+pub const TAB_SET: u8 = b'H';
+pub const INDEX: u8 = b'D';
+pub const SINGLE_SHIFT_2: u8 = b'N';
+pub const SINGLE_SHIFT_3: u8 = b'O';
+pub const LOCKING_SHIFT_2: u8 = b'n';
+pub const LOCKING_SHIFT_3: u8 = b'o';
+
+// DCS functions
+pub const REQUEST_STATUS_STRING: u8 = b'q'; // DECRQSS, with a '$' intermediate
+pub const REQUEST_TERMCAP: u8 = b'q'; // XTGETTCAP, with a '+' intermediate
+pub const SIXEL: u8 = b'q';
+pub const TMUX_PASSTHROUGH: u8 = b't'; // ESC P tmux; ...
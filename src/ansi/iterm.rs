@@ -0,0 +1,186 @@
+//! Parser of iTerm2 inline images, sent as
+//! `OSC 1337 ; File = <arguments> : <base64 file> ST`.
+//! https://iterm2.com/documentation-images.html
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{graphics, image::Image};
+
+/// Width or height requested for an image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    /// The size of the image, or what keeps its aspect ratio
+    #[default]
+    Auto,
+    Cells(usize),
+    Pixels(usize),
+    /// Percent of the width or height of the screen
+    Percent(usize),
+}
+
+impl Dimension {
+    fn parse(value: &[u8]) -> Self {
+        let value = std::str::from_utf8(value).unwrap_or_default();
+        let number = |n: &str| n.parse().ok();
+
+        let dimension = if let Some(n) = value.strip_suffix("px") {
+            number(n).map(Self::Pixels)
+        } else if let Some(n) = value.strip_suffix('%') {
+            number(n).map(Self::Percent)
+        } else {
+            number(value).map(Self::Cells)
+        };
+        dimension.unwrap_or(Self::Auto)
+    }
+
+    /// Returns the size in pixels, `cell` is the size of a cell and `cells`
+    /// the number of cells of the screen
+    fn pixels(self, cell: usize, cells: usize) -> Option<usize> {
+        match self {
+            Self::Auto => None,
+            Self::Cells(n) => Some(n * cell),
+            Self::Pixels(n) => Some(n),
+            Self::Percent(n) => Some(cells * cell * n / 100),
+        }
+    }
+}
+
+/// A decoded image to show at the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineImage {
+    pub image: Image,
+    pub width: Dimension,
+    pub height: Dimension,
+    pub preserve_aspect_ratio: bool,
+}
+
+impl InlineImage {
+    /// Parses what follows `File=`. Files that are not inline are downloads,
+    /// which are not supported.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let colon = data.iter().position(|b| *b == b':')?;
+        let (arguments, file) = (&data[..colon], &data[colon + 1..]);
+        let mut inline = false;
+        let mut width = Dimension::Auto;
+        let mut height = Dimension::Auto;
+        let mut preserve_aspect_ratio = true;
+
+        for argument in arguments.split(|b| *b == b';') {
+            let Some(eq) = argument.iter().position(|b| *b == b'=') else {
+                continue;
+            };
+            let (key, value) = (&argument[..eq], &argument[eq + 1..]);
+            match key {
+                b"inline" => inline = value == b"1",
+                b"width" => width = Dimension::parse(value),
+                b"height" => height = Dimension::parse(value),
+                b"preserveAspectRatio" => preserve_aspect_ratio = value != b"0",
+                _ => {}
+            }
+        }
+        if !inline {
+            return None;
+        }
+
+        let image = Image::decode(&STANDARD.decode(file).ok()?, graphics::DEFAULT_QUOTA).ok()?;
+        Some(Self {
+            image,
+            width,
+            height,
+            preserve_aspect_ratio,
+        })
+    }
+
+    /// Returns the size in image pixels of a cell for the requested size,
+    /// on a screen of `columns` by `lines` cells of `cell_size` pixels. Images
+    /// without a size shrink to fit the width of the screen.
+    pub fn cell_size(
+        &self,
+        cell_size: (usize, usize),
+        columns: usize,
+        lines: usize,
+    ) -> (usize, usize) {
+        let (cell_width, cell_height) = (cell_size.0.max(1), cell_size.1.max(1));
+        let (image_width, image_height) = (self.image.width.max(1), self.image.height.max(1));
+        let screen_width = columns * cell_width;
+
+        let (width, height) = match (
+            self.width.pixels(cell_width, columns),
+            self.height.pixels(cell_height, lines),
+        ) {
+            (None, None) if image_width > screen_width => {
+                (screen_width, image_height * screen_width / image_width)
+            }
+            (None, None) => (image_width, image_height),
+            (Some(width), None) => (width, image_height * width / image_width),
+            (None, Some(height)) => (image_width * height / image_height, height),
+            // Fits in the box with the aspect ratio of the image
+            (Some(width), Some(height)) if self.preserve_aspect_ratio => {
+                if width * image_height < height * image_width {
+                    (width, image_height * width / image_width)
+                } else {
+                    (image_width * height / image_height, height)
+                }
+            }
+            (Some(width), Some(height)) => (width, height),
+        };
+
+        (
+            (image_width * cell_width / width.max(1)).max(1),
+            (image_height * cell_height / height.max(1)).max(1),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = vec![];
+        image::RgbaImage::new(width, height)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = STANDARD.encode(png(16, 8));
+        let data = format!("name=YQ==;size=1;width=50%;height=2px;inline=1:{file}");
+        let image = InlineImage::parse(data.as_bytes()).unwrap();
+        assert_eq!((image.image.width, image.image.height), (16, 8));
+        assert_eq!(image.width, Dimension::Percent(50));
+        assert_eq!(image.height, Dimension::Pixels(2));
+        assert!(image.preserve_aspect_ratio);
+
+        // Downloads are ignored
+        assert!(InlineImage::parse(format!("size=1:{file}").as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_cell_size() {
+        let image = |width, height, preserve_aspect_ratio| InlineImage {
+            image: Image {
+                width: 80,
+                height: 40,
+                rgba: vec![0; 80 * 40 * 4],
+            },
+            width,
+            height,
+            preserve_aspect_ratio,
+        };
+
+        // Cells of 8x16 show 8x16 pixels of the image
+        let auto = image(Dimension::Auto, Dimension::Auto, true);
+        assert_eq!(auto.cell_size((8, 16), 20, 10), (8, 16));
+        // Shrunk to the 5 columns of the screen
+        assert_eq!(auto.cell_size((8, 16), 5, 10), (16, 32));
+        let wide = image(Dimension::Cells(20), Dimension::Auto, true);
+        assert_eq!(wide.cell_size((8, 16), 20, 10), (4, 8));
+        let boxed = image(Dimension::Percent(100), Dimension::Pixels(20), true);
+        assert_eq!(boxed.cell_size((8, 16), 20, 10), (16, 32));
+        let stretched = image(Dimension::Percent(100), Dimension::Pixels(20), false);
+        assert_eq!(stretched.cell_size((8, 16), 20, 10), (4, 32));
+    }
+}
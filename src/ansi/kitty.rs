@@ -0,0 +1,166 @@
+//! Parser of the kitty graphics protocol commands, sent as
+//! `ESC _ G <control data> ; <payload> ESC \`.
+//! https://sw.kovidgoyal.net/kitty/graphics-protocol/
+
+use base64::{
+    alphabet,
+    engine::{general_purpose::GeneralPurpose, DecodePaddingMode, GeneralPurposeConfig},
+    Engine,
+};
+
+/// Chunks of the payload may or may not be padded
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A graphics command, the keys that are not set have their default value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphicsCommand {
+    /// `a`, transmit (`t`), transmit and display (`T`), query (`q`), put
+    /// (`p`) or delete (`d`)
+    pub action: u8,
+    /// `q`, 1 suppresses OK responses and 2 errors too
+    pub quiet: u8,
+    /// `f`, 24 for RGB, 32 for RGBA and 100 for PNG
+    pub format: u32,
+    /// `t`, direct (`d`), file (`f`), temporary file (`t`) or shared memory
+    /// (`s`)
+    pub medium: u8,
+    /// `o`, `z` for zlib
+    pub compression: Option<u8>,
+    /// `s` and `v`, size in pixels of RGB and RGBA data
+    pub width: u32,
+    pub height: u32,
+    /// `S` and `O`, part of a file or shared memory object to read
+    pub size: usize,
+    pub offset: usize,
+    /// `i`, `I` and `p`
+    pub id: u32,
+    pub number: u32,
+    pub placement_id: u32,
+    /// `m`, more chunks follow
+    pub more: bool,
+    /// `x`, `y`, `w` and `h`, part of the image to show
+    pub source: (u32, u32, u32, u32),
+    /// `c` and `r`, number of cells to show the image in
+    pub columns: u32,
+    pub rows: u32,
+    /// `C`, the cursor doesn't move when set
+    pub keep_cursor: bool,
+    /// `z`, negative values are drawn below the text
+    pub z: i32,
+    /// `U`, the image is shown where the application prints placeholders
+    pub unicode_placeholder: bool,
+    /// `d`, what to delete, uppercase also frees the images
+    pub delete: u8,
+    /// Decoded payload
+    pub payload: Vec<u8>,
+}
+
+impl Default for GraphicsCommand {
+    fn default() -> Self {
+        Self {
+            action: b't',
+            quiet: 0,
+            format: 32,
+            medium: b'd',
+            compression: None,
+            width: 0,
+            height: 0,
+            size: 0,
+            offset: 0,
+            id: 0,
+            number: 0,
+            placement_id: 0,
+            more: false,
+            source: (0, 0, 0, 0),
+            columns: 0,
+            rows: 0,
+            keep_cursor: false,
+            z: 0,
+            unicode_placeholder: false,
+            delete: b'a',
+            payload: vec![],
+        }
+    }
+}
+
+impl GraphicsCommand {
+    /// Parses the content of the APC after the `G`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (control, payload) = match data.iter().position(|b| *b == b';') {
+            Some(i) => (&data[..i], &data[i + 1..]),
+            None => (data, &[][..]),
+        };
+        let mut cmd = Self {
+            payload: BASE64.decode(payload).ok()?,
+            ..Self::default()
+        };
+
+        for pair in control.split(|b| *b == b',') {
+            let [key, b'=', value @ ..] = pair else {
+                continue;
+            };
+            let number = || std::str::from_utf8(value).ok()?.parse::<u32>().ok();
+            let number = number().unwrap_or(0);
+            let byte = value.first().copied().unwrap_or(0);
+
+            match key {
+                b'a' => cmd.action = byte,
+                b'q' => cmd.quiet = number as u8,
+                b'f' => cmd.format = number,
+                b't' => cmd.medium = byte,
+                b'o' => cmd.compression = Some(byte),
+                b's' => cmd.width = number,
+                b'v' => cmd.height = number,
+                b'S' => cmd.size = number as usize,
+                b'O' => cmd.offset = number as usize,
+                b'i' => cmd.id = number,
+                b'I' => cmd.number = number,
+                b'p' => cmd.placement_id = number,
+                b'm' => cmd.more = number == 1,
+                b'x' => cmd.source.0 = number,
+                b'y' => cmd.source.1 = number,
+                b'w' => cmd.source.2 = number,
+                b'h' => cmd.source.3 = number,
+                b'c' => cmd.columns = number,
+                b'r' => cmd.rows = number,
+                b'C' => cmd.keep_cursor = number == 1,
+                b'z' => {
+                    cmd.z = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|z| z.parse().ok())
+                        .unwrap_or(0)
+                }
+                b'U' => cmd.unicode_placeholder = number == 1,
+                b'd' => cmd.delete = byte,
+                _ => {}
+            }
+        }
+
+        Some(cmd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cmd = GraphicsCommand::parse(b"a=T,f=24,s=1,v=1,i=7,z=-1,C=1;AAEC").unwrap();
+        assert_eq!(cmd.action, b'T');
+        assert_eq!(cmd.format, 24);
+        assert_eq!((cmd.width, cmd.height), (1, 1));
+        assert_eq!(cmd.id, 7);
+        assert_eq!(cmd.z, -1);
+        assert!(cmd.keep_cursor);
+        assert_eq!(cmd.payload, [0, 1, 2]);
+
+        // Unpadded chunks are accepted
+        let cmd = GraphicsCommand::parse(b"m=1;AAE").unwrap();
+        assert!(cmd.more);
+        assert_eq!(cmd.payload, [0, 1]);
+    }
+}
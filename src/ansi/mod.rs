@@ -1,467 +1,690 @@
-use crate::color::Color;
+use std::{ffi::OsString, os::unix::ffi::OsStringExt, path::PathBuf};
 
-mod ansi_codes;
-
-fn is_csi_terminator(b: u8) -> bool {
-    (0x40..=0x7e).contains(&b)
-}
+use base64::{engine::general_purpose::STANDARD, Engine};
 
-fn is_csi_param(b: u8) -> bool {
-    (0x30..=0x3f).contains(&b)
-}
+use crate::{
+    charset::Charset,
+    clipboard::Clipboard,
+    color::{Color, PaletteColor, TermColor},
+    grid::{
+        cell::{Hyperlink, UnderlineStyle},
+        row::LineSize,
+    },
+    image::Image,
+    modes::Mode,
+    notification::{Notification, NotificationChunk},
+};
 
-fn is_csi_intermediate(b: u8) -> bool {
-    (0x20..=0x2f).contains(&b)
-}
+use self::iterm::InlineImage;
+use self::kitty::GraphicsCommand;
+use self::parser::{Action, Csi, Parser};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum CSIParserState {
-    Parameters,
-    Intermediates,
-}
+mod ansi_codes;
+pub mod iterm;
+pub mod kitty;
+mod parser;
+pub mod sixel;
 
+/// Interprets the parameters of a dispatched OSC string.
 #[derive(Debug)]
-enum CSIParserError {
-    InvalidCSI,
-}
+struct OscParser;
 
-#[derive(Debug, Default, Clone)]
-struct CSIParseResult {
-    params: Vec<u8>,
-    intermediates: Vec<u8>,
-    func: u8,
-}
+impl OscParser {
+    fn parse(params: &[Vec<u8>], bell_terminated: bool, res: &mut Vec<AnsiOutput>) {
+        let Some(command) = params.first() else {
+            return;
+        };
 
-#[derive(Debug)]
-struct CSIParser {
-    result: CSIParseResult,
-    state: CSIParserState,
-}
+        match command.as_slice() {
+            b"0" | b"2" => {
+                // The title itself can contain ';'
+                let title = params[1..].join(&b';');
+                res.push(AnsiOutput::Title(
+                    String::from_utf8_lossy(&title).into_owned(),
+                ));
+            }
+            // OSC 4 ; index ; spec [; index ; spec ...]
+            b"4" => {
+                for pair in params[1..].chunks_exact(2) {
+                    let Some(index) = parse_number(&pair[0]).filter(|i| *i < 256) else {
+                        continue;
+                    };
+                    Self::color(
+                        PaletteColor::Indexed(index as u8),
+                        &pair[1],
+                        bell_terminated,
+                        res,
+                    );
+                }
+            }
+            // OSC 8 ; params ; URI, an empty URI ends the link
+            b"8" if params.len() >= 3 => {
+                // The URI can contain ';'
+                let uri = String::from_utf8_lossy(&params[2..].join(&b';')).into_owned();
+                if uri.is_empty() {
+                    res.push(AnsiOutput::Hyperlink(None));
+                    return;
+                }
 
-impl CSIParser {
-    fn new() -> Self {
-        Self {
-            state: CSIParserState::Parameters,
-            result: CSIParseResult::default(),
+                // The params are key=value pairs separated by ':'
+                let id = params[1]
+                    .split(|b| *b == b':')
+                    .find_map(|param| param.strip_prefix(b"id="))
+                    .map(|id| String::from_utf8_lossy(id).into_owned());
+                res.push(AnsiOutput::Hyperlink(Some(Hyperlink { id, uri })));
+            }
+            // OSC 7 ; file://host/path
+            b"7" if params.len() >= 2 => {
+                let url = params[1..].join(&b';');
+                let Some(path) = url.strip_prefix(b"file://") else {
+                    return;
+                };
+                // Skip the host
+                let Some(start) = path.iter().position(|b| *b == b'/') else {
+                    return;
+                };
+                let path = percent_decode(&path[start..]);
+                res.push(AnsiOutput::WorkingDirectory(PathBuf::from(
+                    OsString::from_vec(path),
+                )));
+            }
+            // OSC 133 ; A|B|C|D [; exit status] [; options]
+            b"133" if params.len() >= 2 => {
+                let mark = match params[1].as_slice() {
+                    b"A" => SemanticMark::PromptStart,
+                    b"B" => SemanticMark::CommandStart,
+                    b"C" => SemanticMark::OutputStart,
+                    b"D" => {
+                        let status = params.get(2).and_then(|p| std::str::from_utf8(p).ok());
+                        SemanticMark::CommandEnd(status.and_then(|s| s.parse().ok()))
+                    }
+                    _ => return,
+                };
+                res.push(AnsiOutput::SemanticPrompt(mark));
+            }
+            // OSC 52 ; selection ; base64 data or ?
+            b"52" if params.len() == 3 => {
+                let Some(clipboard) = Clipboard::from_param(&params[1]) else {
+                    return;
+                };
+
+                if params[2] == b"?" {
+                    res.push(AnsiOutput::ClipboardLoad {
+                        clipboard,
+                        bell_terminated,
+                    });
+                } else if let Ok(data) = STANDARD.decode(&params[2]) {
+                    let text = String::from_utf8_lossy(&data).into_owned();
+                    res.push(AnsiOutput::ClipboardStore(clipboard, text));
+                }
+            }
+            // OSC 9 ; message, other terminals use numeric subcommands like
+            // `9;4` for progress
+            b"9" if params.len() >= 2 => {
+                if params.len() > 2 && parse_number(&params[1]).is_some() {
+                    return;
+                }
+                let body = String::from_utf8_lossy(&params[1..].join(&b';')).into_owned();
+                res.push(AnsiOutput::Notification(Notification {
+                    title: String::new(),
+                    body,
+                }));
+            }
+            // OSC 777 ; notify ; title ; body
+            b"777" if params.len() >= 3 && params[1] == b"notify" => {
+                let body = params.get(3..).unwrap_or_default().join(&b';');
+                res.push(AnsiOutput::Notification(Notification {
+                    title: String::from_utf8_lossy(&params[2]).into_owned(),
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                }));
+            }
+            // OSC 99 ; metadata ; payload
+            b"99" if params.len() >= 3 => {
+                let payload = params[2..].join(&b';');
+                if let Some(chunk) = NotificationChunk::parse(&params[1], &payload) {
+                    res.push(AnsiOutput::NotificationChunk(chunk));
+                }
+            }
+            // OSC 1337 ; File = arguments : base64 file, the arguments are
+            // separated by ';'
+            b"1337" if params.len() >= 2 => {
+                let data = params[1..].join(&b';');
+                if let Some(image) = data.strip_prefix(b"File=").and_then(InlineImage::parse) {
+                    res.push(AnsiOutput::InlineImage(image));
+                }
+            }
+            // OSC 104 [; index ...], without an index the whole palette is reset
+            b"104" => {
+                if params.len() == 1 || params[1..].iter().all(|p| p.is_empty()) {
+                    res.push(AnsiOutput::ResetPalette);
+                }
+                for index in params[1..].iter().filter_map(|p| parse_number(p)) {
+                    if index < 256 {
+                        res.push(AnsiOutput::ResetColor(PaletteColor::Indexed(index as u8)));
+                    }
+                }
+            }
+            command => {
+                let Some(number) = parse_number(command) else {
+                    return;
+                };
+
+                match number {
+                    // Each following spec applies to the next dynamic color
+                    10..=19 => {
+                        for (i, spec) in params[1..].iter().enumerate() {
+                            if let Some(color) = dynamic_color(number + i) {
+                                Self::color(color, spec, bell_terminated, res);
+                            }
+                        }
+                    }
+                    110..=119 => {
+                        if let Some(color) = dynamic_color(number - 100) {
+                            res.push(AnsiOutput::ResetColor(color));
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
     }
 
-    fn push(&mut self, b: u8) -> Option<Result<CSIParseResult, CSIParserError>> {
-        if is_csi_param(b) {
-            if self.state != CSIParserState::Parameters {
-                return Some(Err(CSIParserError::InvalidCSI));
-            }
-            self.result.params.push(b);
-        } else if is_csi_intermediate(b) {
-            self.state = CSIParserState::Intermediates;
-            self.result.intermediates.push(b);
-        } else if is_csi_terminator(b) {
-            self.result.func = b;
-            return Some(Ok(self.result.clone()));
+    /// Sets `color` to `spec`, or queries it if `spec` is `?`
+    fn color(color: PaletteColor, spec: &[u8], bell_terminated: bool, res: &mut Vec<AnsiOutput>) {
+        if spec == b"?" {
+            res.push(AnsiOutput::QueryColor {
+                color,
+                bell_terminated,
+            });
+        } else if let Some(value) = Color::from_spec(spec) {
+            res.push(AnsiOutput::SetColor(color, value));
         }
-
-        None
     }
 }
 
-struct OscParseResult {
-    title: String,
-}
-
-#[derive(Debug, PartialEq)]
-enum OscState {
-    Empty,
-    TitleStart,
-    Title,
-}
-
-#[derive(Debug)]
-enum OscParserError {}
+/// Decodes the `%XX` escapes of a URL
+fn percent_decode(url: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(url.len());
+    let mut i = 0;
 
-#[derive(Debug)]
-struct OscParser {
-    title: Vec<char>,
-    state: OscState,
-}
-
-impl OscParser {
-    fn new() -> Self {
-        Self {
-            title: Vec::new(),
-            state: OscState::Empty,
+    while i < url.len() {
+        let hex = url
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (url[i], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                res.push(b);
+                i += 1;
+            }
         }
     }
 
-    fn push(&mut self, b: char) -> Option<Result<OscParseResult, OscParserError>> {
-        let c = b as u8;
-
-        if c == ansi_codes::STRING_TERMINATOR || c == ansi_codes::BEL || c == ansi_codes::OSC_END {
-            return Some(Ok(OscParseResult {
-                title: self.title.iter().collect(),
-            }));
-        }
+    res
+}
 
-        if self.state == OscState::Title {
-            self.title.push(b);
-        }
+/// Decodes a string of hex digit pairs
+fn hex_decode(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
 
-        if c == b'2' {
-            self.state = OscState::TitleStart;
-            return None;
-        }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
 
-        if self.state == OscState::TitleStart {
-            self.state = OscState::Title;
-            return None;
-        }
+fn parse_number(param: &[u8]) -> Option<usize> {
+    std::str::from_utf8(param).ok()?.parse().ok()
+}
 
-        None
+/// Maps the number of an OSC dynamic color, the ones without a
+/// `PaletteColor` are not supported
+fn dynamic_color(number: usize) -> Option<PaletteColor> {
+    match number {
+        10 => Some(PaletteColor::Foreground),
+        11 => Some(PaletteColor::Background),
+        12 => Some(PaletteColor::Cursor),
+        17 => Some(PaletteColor::SelectionBackground),
+        19 => Some(PaletteColor::SelectionForeground),
+        _ => None,
     }
 }
 
-enum AnsiState {
-    Empty,
-    Escape,
-    Hash,
-    Csi(CSIParser),
-    Osc(OscParser),
-}
+/// Maximum number of bytes buffered for a DCS.
+const MAX_DCS_LEN: usize = 64 * 1024 * 1024;
 
 pub struct Ansi {
-    state: AnsiState,
+    parser: Parser,
+    actions: Vec<Action>,
+    /// Header and data of the DCS being received
+    dcs: Option<(Csi, Vec<u8>)>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GraphicRendition {
-    BackgroundColor(Color),
-    ForegroundColor(Color),
-    Bold,
     Reset,
-    Underline,
+    BackgroundColor(TermColor),
+    ForegroundColor(TermColor),
+    DefaultBackground,
+    DefaultForeground,
+    Bold,
+    Dim,
     Italic,
+    Underline(UnderlineStyle),
+    UnderlineColor(TermColor),
+    DefaultUnderlineColor,
+    Blink,
+    Reverse,
+    Hidden,
+    Strikethrough,
+    Overline,
+    /// Neither bold nor dim
+    NormalIntensity,
+    NotItalic,
+    NotUnderlined,
+    NotBlinking,
+    NotReversed,
+    NotHidden,
+    NotStrikethrough,
+    NotOverlined,
+}
+
+impl GraphicRendition {
+    /// Maps a single SGR parameter, the extended colors (38 and 48) are
+    /// handled separately since they span multiple parameters.
+    fn from_param(item: usize) -> Option<Self> {
+        let rendition = match item {
+            0 => Self::Reset,
+            1 => Self::Bold,
+            2 => Self::Dim,
+            3 => Self::Italic,
+            4 => Self::Underline(UnderlineStyle::Single),
+            5 | 6 => Self::Blink,
+            7 => Self::Reverse,
+            8 => Self::Hidden,
+            9 => Self::Strikethrough,
+            21 => Self::Underline(UnderlineStyle::Double),
+            22 => Self::NormalIntensity,
+            23 => Self::NotItalic,
+            24 => Self::NotUnderlined,
+            25 => Self::NotBlinking,
+            27 => Self::NotReversed,
+            28 => Self::NotHidden,
+            29 => Self::NotStrikethrough,
+            30..=37 => Self::ForegroundColor(TermColor::Indexed((item - 30) as u8)),
+            39 => Self::DefaultForeground,
+            40..=47 => Self::BackgroundColor(TermColor::Indexed((item - 40) as u8)),
+            49 => Self::DefaultBackground,
+            53 => Self::Overline,
+            55 => Self::NotOverlined,
+            59 => Self::DefaultUnderlineColor,
+            90..=97 => Self::ForegroundColor(TermColor::Indexed((item - 90 + 8) as u8)),
+            100..=107 => Self::BackgroundColor(TermColor::Indexed((item - 100 + 8) as u8)),
+            _ => return None,
+        };
+
+        Some(rendition)
+    }
 }
 
-impl From<u8> for GraphicRendition {
-    fn from(item: u8) -> Self {
-        match item {
-            30 => Self::ForegroundColor(Color::BLACK),
-            31 => Self::ForegroundColor(Color::RED),
-            32 => Self::ForegroundColor(Color::GREEN),
-            33 => Self::ForegroundColor(Color::YELLOW),
-            34 => Self::ForegroundColor(Color::BLUE),
-            35 => Self::ForegroundColor(Color::MAGENTA),
-            36 => Self::ForegroundColor(Color::CYAN),
-            37 => Self::ForegroundColor(Color::WHITE),
-
-            /*
-                        90 => Self::ForegroundGrey,
-                        91 => Self::ForegroundBrightRed,
-                        92 => Self::ForegroundBrightGreen,
-                        93 => Self::ForegroundBrightYellow,
-                        94 => Self::ForegroundBrightBlue,
-                        95 => Self::ForegroundBrightMagenta,
-                        96 => Self::ForegroundBrightCyan,
-                        97 => Self::ForegroundBrightWhite,
-            */
-            40 => Self::BackgroundColor(Color::BLACK),
-            41 => Self::BackgroundColor(Color::RED),
-            42 => Self::BackgroundColor(Color::GREEN),
-            43 => Self::BackgroundColor(Color::YELLOW),
-            44 => Self::BackgroundColor(Color::BLUE),
-            45 => Self::BackgroundColor(Color::MAGENTA),
-            46 => Self::BackgroundColor(Color::CYAN),
-            47 => Self::BackgroundColor(Color::WHITE),
-
-            /*
-                    100 => Self::BackgroundGrey,
-                    101 => Self::BackgroundBrightRed,
-                    102 => Self::BackgroundBrightGreen,
-                    103 => Self::BackgroundBrightYellow,
-                    104 => Self::BackgroundBrightBlue,
-                    105 => Self::BackgroundBrightMagenta,
-                    106 => Self::BackgroundBrightCyan,
-                    107 => Self::BackgroundBrightWhite,
-            */
-            // TODO: Why would we get a panic for an unknown sgr 38 when we run nvim?
-            _ => Self::ForegroundColor(Color::WHITE), // panic!("unknown sgr {}", item),
+/// Parses the color of an extended color SGR (38, 48, 58), in either the
+/// `38;5;n`/`38;2;r;g;b` form or the `38:5:n`/`38:2:[colorspace]:r:g:b`
+/// one. `param` is the 38/48 parameter with its sub-parameters, the
+/// semicolon form consumes the next parameters from `params`.
+fn extended_color<'a>(
+    param: &[usize],
+    params: &mut impl Iterator<Item = &'a Vec<usize>>,
+) -> Option<TermColor> {
+    let channel = |v: usize| v.min(255) as u8;
+
+    if param.len() > 1 {
+        return match param[1] {
+            5 => param.get(2).map(|i| TermColor::Indexed(channel(*i))),
+            2 => {
+                let rgb = if param.len() >= 6 {
+                    &param[3..6]
+                } else {
+                    param.get(2..5)?
+                };
+                Some(TermColor::Rgb(Color::from_rgb(
+                    channel(rgb[0]),
+                    channel(rgb[1]),
+                    channel(rgb[2]),
+                )))
+            }
+            _ => None,
+        };
+    }
+
+    match params.next()?[0] {
+        5 => params.next().map(|i| TermColor::Indexed(channel(i[0]))),
+        2 => {
+            let r = params.next()?[0];
+            let g = params.next()?[0];
+            let b = params.next()?[0];
+            Some(TermColor::Rgb(Color::from_rgb(
+                channel(r),
+                channel(g),
+                channel(b),
+            )))
         }
+        _ => None,
     }
 }
 
-fn color_8bit(item: u8) -> Color {
-    match item {
-        0 => Color::BLACK,
-        1 => Color::RED,
-        2 => Color::GREEN,
-        3 => Color::YELLOW,
-        4 => Color::BLUE,
-        5 => Color::MAGENTA,
-        6 => Color::CYAN,
-        7 => Color::WHITE,
-
-        8 => Color::GRAY,
-        9 => Color::RED,
-        10 => Color::GREEN,
-        11 => Color::YELLOW,
-        12 => Color::BLUE,
-        13 => Color::MAGENTA,
-        14 => Color::CYAN,
-        15 => Color::WHITE,
-
-        16..=231 => Color::from_rgb(
-            (item - 16) & 0b1110_0000,
-            (item - 16) & 0b0001_1100,
-            (item - 16) & 0b0000_0011,
-        ),
-        _ => panic!("unknown sgr {}", item),
+/// Parses all the graphic renditions of an SGR sequence.
+fn sgr(csi: &Csi) -> Vec<GraphicRendition> {
+    if csi.params.is_empty() {
+        return vec![GraphicRendition::Reset];
     }
+
+    let mut res = vec![];
+    let mut params = csi.params.iter();
+    while let Some(param) = params.next() {
+        let rendition = match param[0] {
+            38 => extended_color(param, &mut params).map(GraphicRendition::ForegroundColor),
+            48 => extended_color(param, &mut params).map(GraphicRendition::BackgroundColor),
+            58 => extended_color(param, &mut params).map(GraphicRendition::UnderlineColor),
+            // Styled underlines, CSI 4:n m
+            4 if param.len() > 1 => {
+                UnderlineStyle::from_param(param[1]).map(GraphicRendition::Underline)
+            }
+            p => GraphicRendition::from_param(p),
+        };
+
+        if let Some(rendition) = rendition {
+            res.push(rendition);
+        }
+    }
+
+    res
 }
 
-#[derive(Debug)]
+/// What part of the line or screen ED and EL erase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClearMode {
     ToEnd,
     ToBeginning,
     Both,
+    /// Only for ED, erases the scrollback
+    Scrollback,
 }
 
-impl From<usize> for ClearMode {
-    fn from(value: usize) -> Self {
-        match value {
-            0 => Self::ToEnd,
-            1 => Self::ToBeginning,
-            _ => Self::Both,
+impl ClearMode {
+    pub fn from_param(param: usize) -> Option<Self> {
+        match param {
+            0 => Some(Self::ToEnd),
+            1 => Some(Self::ToBeginning),
+            2 => Some(Self::Both),
+            3 => Some(Self::Scrollback),
+            _ => None,
         }
     }
 }
 
+/// FinalTerm semantic prompt marks, sent by the shell with OSC 133
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticMark {
+    PromptStart,
+    CommandStart,
+    OutputStart,
+    /// The command finished, with its exit status
+    CommandEnd(Option<i32>),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+/// Cursor style set with DECSCUSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    pub fn from_param(param: usize) -> Option<Self> {
+        let shape = match param {
+            0..=2 => CursorShape::Block,
+            3 | 4 => CursorShape::Underline,
+            5 | 6 => CursorShape::Bar,
+            _ => return None,
+        };
+
+        Some(Self {
+            shape,
+            // Odd values blink, 0 is the default blinking block
+            blinking: param == 0 || param % 2 == 1,
+        })
+    }
+
+    /// Returns the DECSCUSR parameter of the style
+    pub fn param(&self) -> usize {
+        let steady = match self.shape {
+            CursorShape::Block => 2,
+            CursorShape::Underline => 4,
+            CursorShape::Bar => 6,
+        };
+        steady - self.blinking as usize
+    }
+}
+
 #[derive(Debug)]
 pub enum AnsiOutput {
     Text(Vec<char>),
     Title(String),
     Backspace,
-    ClearToEndOfLine(ClearMode),
-    ClearToEOS,
-    ScrollDown,
+    /// EL
+    EraseInLine(ClearMode),
+    /// ED
+    EraseInDisplay(ClearMode),
+    /// Moves the cursor down, scrolling at the bottom margin
+    Index,
+    /// Moves the cursor up, scrolling at the top margin
+    ReverseIndex,
     MoveCursor(usize, usize),
     Bell,
     Sgr(GraphicRendition),
     CursorUp(usize),
     CursorDown(usize),
     MoveCursorHorizontal(usize),
-    HideCursor,
-    ShowCursor,
+    SetMode(Mode),
+    ResetMode(Mode),
+    /// DECRQM, `private` is true for DEC private modes
+    RequestMode {
+        private: bool,
+        mode: usize,
+    },
     CursorBackward(usize),
     CursorForward(usize),
     FillWithE,
     NextLine,
     DeleteCharacters(usize),
+    InsertCharacters(usize),
+    EraseCharacters(usize),
+    /// REP, repeats the last printed character
+    RepeatCharacter(usize),
+    SetColor(PaletteColor, Color),
+    /// OSC color query, answered with the same terminator as the query
+    QueryColor {
+        color: PaletteColor,
+        bell_terminated: bool,
+    },
+    ResetColor(PaletteColor),
+    /// Resets the 256 indexed colors
+    ResetPalette,
+    /// OSC 7, the working directory of the shell
+    WorkingDirectory(PathBuf),
+    SemanticPrompt(SemanticMark),
+    /// OSC 8, starts a hyperlink or ends it with `None`
+    Hyperlink(Option<Hyperlink>),
+    /// OSC 9 and OSC 777 notifications
+    Notification(Notification),
+    /// OSC 99 notifications, which can be sent in several chunks
+    NotificationChunk(NotificationChunk),
+    ClipboardStore(Clipboard, String),
+    ClipboardLoad {
+        clipboard: Clipboard,
+        bell_terminated: bool,
+    },
+    /// ENQ, answered with the answerback message
+    Enquiry,
+    /// DA1
+    PrimaryDeviceAttributes,
+    /// DA2
+    SecondaryDeviceAttributes,
+    /// DA3
+    TertiaryDeviceAttributes,
+    /// DSR 5, operating status
+    StatusReport,
+    /// DSR 6, `private` is true for DECXCPR which also reports the page
+    CursorPositionReport {
+        private: bool,
+    },
+    /// XTVERSION
+    TerminalVersion,
+    /// DECRQSS, with the setting being queried
+    RequestStatusString(Vec<u8>),
+    /// XTGETTCAP, with the names of the capabilities
+    RequestTermcap(Vec<String>),
+    /// DECSCUSR
+    SetCursorStyle(CursorStyle),
+    /// A decoded Sixel image, drawn at the cursor
+    Sixel(Image),
+    /// A kitty graphics protocol command
+    Graphics(GraphicsCommand),
+    /// An iTerm2 inline image, drawn at the cursor
+    InlineImage(InlineImage),
+    /// XTSMGRAPHICS, reads the number of color registers (item 1) or the
+    /// maximum size of a Sixel image (item 2)
+    GraphicsAttributes {
+        item: usize,
+        action: usize,
+    },
+    /// DECSWL, DECDWL and DECDHL, size of the characters of the cursor row
+    LineSize(LineSize),
+    /// SCS, designates a character set into G0 to G3
+    DesignateCharset {
+        index: usize,
+        charset: Charset,
+    },
+    /// SI, SO, LS2 and LS3, invoke G0 to G3 into GL
+    LockingShift(usize),
+    /// SS2 and SS3, invoke G2 or G3 for the next character
+    SingleShift(usize),
+    /// DECSC and SCOSC
+    SaveCursor,
+    /// DECRC and SCORC
+    RestoreCursor,
+    /// HTS
+    SetTabStop,
+    /// TBC, `all` clears every tab stop instead of the one at the cursor
+    ClearTabStop {
+        all: bool,
+    },
+    /// CHT
+    CursorForwardTab(usize),
+    /// CBT
+    CursorBackwardTab(usize),
+    /// DECSTBM, 1-based top and bottom margins, 0 for the default
+    SetScrollingRegion(usize, usize),
+    InsertLines(usize),
+    DeleteLines(usize),
+    ScrollUp(usize),
+    ScrollDown(usize),
 }
 
 impl Ansi {
     pub fn new() -> Self {
         Self {
-            state: AnsiState::Empty,
+            parser: Parser::new(),
+            actions: Vec::new(),
+            dcs: None,
         }
     }
 
-    pub fn push(&mut self, data: &[char]) -> Vec<AnsiOutput> {
+    pub fn push(&mut self, data: &[u8]) -> Vec<AnsiOutput> {
         let mut res = vec![];
         let mut text_output: Vec<char> = Vec::new();
 
         for b in data {
-            match &mut self.state {
-                AnsiState::Empty => {
-                    if *b as u8 == ansi_codes::ESC {
-                        self.state = AnsiState::Escape;
-                        continue;
-                    }
-                    if *b as u8 == ansi_codes::BS {
-                        if !text_output.is_empty() {
-                            res.push(AnsiOutput::Text(text_output.clone()));
-                            text_output.clear();
-                        }
-                        res.push(AnsiOutput::Backspace);
-                        continue;
-                    }
-                    if *b as u8 == ansi_codes::BEL {
-                        res.push(AnsiOutput::Bell);
-                        continue;
-                    }
-                    text_output.push(*b);
+            self.parser.advance(*b, &mut self.actions);
+        }
+
+        for action in std::mem::take(&mut self.actions) {
+            match action {
+                Action::Print(c) => {
+                    text_output.push(c);
+                    continue;
                 }
-                AnsiState::Escape => {
-                    if !text_output.is_empty() {
-                        res.push(AnsiOutput::Text(text_output.clone()));
-                        text_output.clear();
-                    }
-                    match *b as u8 {
-                        ansi_codes::ESC_START => {
-                            self.state = AnsiState::Csi(CSIParser::new());
-                        }
-                        ansi_codes::HASH => {
-                            self.state = AnsiState::Hash;
-                        }
-                        ansi_codes::OSC_START => {
-                            self.state = AnsiState::Osc(OscParser::new());
-                        }
-                        ansi_codes::SCROLL_REVERSE => {
-                            res.push(AnsiOutput::ScrollDown);
-                            self.state = AnsiState::Empty;
-                        }
-                        ansi_codes::CURSOR_DOWNWARD => {
-                            res.push(AnsiOutput::CursorDown(1));
-                            self.state = AnsiState::Empty;
-                        }
-                        ansi_codes::NEXT_LINE => {
-                            res.push(AnsiOutput::NextLine);
-                            self.state = AnsiState::Empty;
-                        }
-                        _ => {
-                            let first = ((*b as u8) & 0b0111_0000) >> 4;
-                            let second = (*b as u8) & 0b0000_1111;
-                            println!("unknown ansi {} {:#02x} {first}/{second}", { *b }, *b as u8);
-                            self.state = AnsiState::Empty;
-                        }
-                    }
+                // The text handling in the terminal takes care of these.
+                Action::Execute(b @ (b'\n' | b'\r' | b'\t')) => {
+                    text_output.push(b as char);
+                    continue;
                 }
-                AnsiState::Hash => {
-                    match *b as u8 {
-                        ansi_codes::FILL_WITH_E => {
-                            res.push(AnsiOutput::FillWithE);
-                        }
-                        _ => {
-                            println!("Unknown hash func {b}");
-                        }
-                    };
-
-                    self.state = AnsiState::Empty;
+                // VT and FF are treated as LF
+                Action::Execute(0x0b | 0x0c) => {
+                    text_output.push('\n');
+                    continue;
                 }
-                AnsiState::Osc(parser) => {
-                    if let Some(Ok(d)) = parser.push(*b) {
-                        res.push(AnsiOutput::Title(d.title.chars().collect()));
-                        self.state = AnsiState::Empty;
+                _ => {}
+            }
+
+            if !text_output.is_empty() {
+                res.push(AnsiOutput::Text(text_output.clone()));
+                text_output.clear();
+            }
+
+            match action {
+                Action::Execute(b) => Self::execute(b, &mut res),
+                Action::EscDispatch {
+                    intermediates,
+                    func,
+                } => Self::esc_dispatch(&intermediates, func, &mut res),
+                Action::CsiDispatch(csi) => Self::csi_dispatch(&csi, &mut res),
+                Action::OscDispatch {
+                    params,
+                    bell_terminated,
+                } => OscParser::parse(&params, bell_terminated, &mut res),
+                // The kitty graphics protocol is the only user of APC
+                Action::ApcDispatch(data) => {
+                    if let Some(cmd) = data.strip_prefix(b"G").and_then(GraphicsCommand::parse) {
+                        res.push(AnsiOutput::Graphics(cmd));
                     }
                 }
-                AnsiState::Csi(parser) => match parser.push(*b as u8) {
-                    Some(Ok(d)) => {
-                        #[allow(clippy::single_match)]
-                        match d.func {
-                            ansi_codes::SGR => {
-                                let params = parse_params(&d.params);
-                                if params.len() == 1 && params[0] == 0 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::Reset));
-                                } else if params.len() == 1 && params[0] == 1 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::Bold));
-                                } else if params.len() == 1 && params[0] == 4 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::Underline));
-                                } else if params.len() == 1 && params[0] == 3 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::Italic));
-                                } else if params.is_empty() || params[0] == 0 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::BackgroundColor(
-                                        Color::BLACK,
-                                    )));
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::ForegroundColor(
-                                        Color::WHITE,
-                                    )));
-                                } else if params.len() >= 3 && params[0] == 38 && params[1] == 5 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::ForegroundColor(
-                                        color_8bit(params[2] as u8),
-                                    )));
-                                } else if params.len() >= 3 && params[0] == 48 && params[1] == 5 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::BackgroundColor(
-                                        color_8bit(params[2] as u8),
-                                    )));
-                                } else if params.len() >= 3 && params[0] == 38 && params[1] == 2 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::ForegroundColor(
-                                        Color::from_rgb(
-                                            params[2] as u8,
-                                            params[3] as u8,
-                                            params[4] as u8,
-                                        ),
-                                    )));
-                                } else if params.len() >= 3 && params[0] == 48 && params[1] == 2 {
-                                    res.push(AnsiOutput::Sgr(GraphicRendition::BackgroundColor(
-                                        Color::from_rgb(
-                                            params[2] as u8,
-                                            params[3] as u8,
-                                            params[4] as u8,
-                                        ),
-                                    )));
-                                } else {
-                                    for param in params {
-                                        // TODO: ugly hack to only take the color for now until we
-                                        // properly handle all the graphic rendition things, like
-                                        // "bold" for example
-                                        if (30..=47).contains(&param) {
-                                            res.push(AnsiOutput::Sgr((param as u8).into()));
-                                        }
-                                    }
-                                }
-                            }
-                            ansi_codes::CLEAR_LINE => {
-                                let params = parse_params(&d.params);
-                                let mode: usize = if params.is_empty() { 0 } else { params[0] };
-                                res.push(AnsiOutput::ClearToEndOfLine(mode.into()));
-                            }
-                            ansi_codes::CLEAR_EOS => res.push(AnsiOutput::ClearToEOS),
-                            ansi_codes::CURSOR_POSITION | ansi_codes::HVP => {
-                                let params = parse_params(&d.params);
-                                let x = if params.len() <= 1 { 1 } else { params[1] };
-                                let y = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::MoveCursor(x - 1, y - 1));
-                            }
-                            ansi_codes::CURSOR_HORIZONTAL_POSITION => {
-                                let params = parse_params(&d.params);
-                                let x = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::MoveCursorHorizontal(x));
-                            }
-                            ansi_codes::CURSOR_UP => {
-                                let params = parse_params(&d.params);
-                                let amount = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::CursorUp(amount));
-                            }
-                            ansi_codes::CURSOR_DOWN => {
-                                let params = parse_params(&d.params);
-                                let amount = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::CursorDown(amount));
-                            }
-                            ansi_codes::CURSOR_FORWARD => {
-                                let params = parse_params(&d.params);
-                                let amount = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::CursorForward(amount));
-                            }
-                            ansi_codes::CURSOR_BACKWARD => {
-                                let params = parse_params(&d.params);
-                                let amount = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::CursorBackward(amount));
-                            }
-                            ansi_codes::HIDE_CURSOR => res.push(AnsiOutput::HideCursor),
-                            ansi_codes::SHOW_CURSOR => res.push(AnsiOutput::ShowCursor),
-                            ansi_codes::DELETE_CHARACTER => {
-                                let params = parse_params(&d.params);
-                                let amount = if params.is_empty() { 1 } else { params[0] };
-                                res.push(AnsiOutput::DeleteCharacters(amount));
-                            }
-                            ansi_codes::DCS => {
-                                // Just ignore DCS sequences for now
-                                // These are used by nvim but don't need special handling
-                            }
-                            _ => {
-                                let first = (d.func & 0b0111_0000) >> 4;
-                                let second = d.func & 0b0000_1111;
-                                println!(
-                                    "unknown func {} {} {first}/{second}",
-                                    d.func, d.func as char
-                                );
-                            }
+                Action::Hook(csi) => self.dcs = Some((csi, Vec::new())),
+                Action::Put(b) => {
+                    if let Some((_, data)) = &mut self.dcs {
+                        if data.len() < MAX_DCS_LEN {
+                            data.push(b);
                         }
-                        self.state = AnsiState::Empty;
                     }
-                    Some(Err(e)) => {
-                        println!("Gotta do something with this error {e:?}");
+                }
+                Action::Unhook => {
+                    if let Some((csi, data)) = self.dcs.take() {
+                        Self::dcs_dispatch(&csi, &data, &mut res);
                     }
-                    _ => {} // CSI not finished, nothing to do
-                },
+                }
+                Action::Print(_) => unreachable!(),
             }
         }
 
@@ -471,19 +694,436 @@ impl Ansi {
 
         res
     }
-}
 
-fn parse_params(params: &[u8]) -> Vec<usize> {
-    if params.is_empty() {
-        return vec![];
+    fn dcs_dispatch(csi: &Csi, data: &[u8], res: &mut Vec<AnsiOutput>) {
+        if csi.ignore {
+            return;
+        }
+
+        match (csi.intermediates.as_slice(), csi.func) {
+            // DECRQSS
+            ([b'$'], ansi_codes::REQUEST_STATUS_STRING) => {
+                res.push(AnsiOutput::RequestStatusString(data.to_vec()))
+            }
+            // XTGETTCAP, the names are hex encoded and separated by ';'
+            ([b'+'], ansi_codes::REQUEST_TERMCAP) => {
+                let names = data
+                    .split(|b| *b == b';')
+                    .filter_map(hex_decode)
+                    .filter_map(|name| String::from_utf8(name).ok())
+                    .collect();
+                res.push(AnsiOutput::RequestTermcap(names));
+            }
+            // P2 is 1 when the pixels that aren't drawn are transparent
+            ([], ansi_codes::SIXEL) => {
+                let transparent = csi.params.get(1).and_then(|p| p.first()) == Some(&1);
+                if let Some(image) = sixel::decode(data, transparent) {
+                    res.push(AnsiOutput::Sixel(image));
+                }
+            }
+            // tmux passthrough, the wrapped sequences are for us
+            ([], ansi_codes::TMUX_PASSTHROUGH) => {
+                if let Some(inner) = data.strip_prefix(b"mux;") {
+                    res.extend(Ansi::new().push(inner));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn execute(b: u8, res: &mut Vec<AnsiOutput>) {
+        match b {
+            ansi_codes::BS => res.push(AnsiOutput::Backspace),
+            ansi_codes::BEL => res.push(AnsiOutput::Bell),
+            ansi_codes::ENQ => res.push(AnsiOutput::Enquiry),
+            ansi_codes::SI => res.push(AnsiOutput::LockingShift(0)),
+            ansi_codes::SO => res.push(AnsiOutput::LockingShift(1)),
+            // 8-bit C1 controls are the same as their ESC Fe counterpart
+            0x80..=0x9f => Self::esc_dispatch(&[], b - 0x40, res),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(intermediates: &[u8], func: u8, res: &mut Vec<AnsiOutput>) {
+        match (intermediates, func) {
+            ([ansi_codes::HASH], ansi_codes::FILL_WITH_E) => res.push(AnsiOutput::FillWithE),
+            ([ansi_codes::HASH], ansi_codes::DOUBLE_HEIGHT_TOP) => {
+                res.push(AnsiOutput::LineSize(LineSize::DoubleHeightTop))
+            }
+            ([ansi_codes::HASH], ansi_codes::DOUBLE_HEIGHT_BOTTOM) => {
+                res.push(AnsiOutput::LineSize(LineSize::DoubleHeightBottom))
+            }
+            ([ansi_codes::HASH], ansi_codes::SINGLE_WIDTH) => {
+                res.push(AnsiOutput::LineSize(LineSize::Single))
+            }
+            ([ansi_codes::HASH], ansi_codes::DOUBLE_WIDTH) => {
+                res.push(AnsiOutput::LineSize(LineSize::DoubleWidth))
+            }
+            ([], ansi_codes::REVERSE_INDEX) => res.push(AnsiOutput::ReverseIndex),
+            ([], ansi_codes::INDEX) => res.push(AnsiOutput::Index),
+            ([], ansi_codes::NEXT_LINE) => res.push(AnsiOutput::NextLine),
+            ([], ansi_codes::TAB_SET) => res.push(AnsiOutput::SetTabStop),
+            ([], ansi_codes::SAVE_CURSOR) => res.push(AnsiOutput::SaveCursor),
+            ([], ansi_codes::RESTORE_CURSOR) => res.push(AnsiOutput::RestoreCursor),
+            ([], ansi_codes::LOCKING_SHIFT_2) => res.push(AnsiOutput::LockingShift(2)),
+            ([], ansi_codes::LOCKING_SHIFT_3) => res.push(AnsiOutput::LockingShift(3)),
+            ([], ansi_codes::SINGLE_SHIFT_2) => res.push(AnsiOutput::SingleShift(2)),
+            ([], ansi_codes::SINGLE_SHIFT_3) => res.push(AnsiOutput::SingleShift(3)),
+            // SCS, ESC ( ) * or + designates G0 to G3
+            ([designator @ b'('..=b'+', intermediates @ ..], func) => {
+                if let Some(charset) = Charset::from_designator(intermediates, func) {
+                    res.push(AnsiOutput::DesignateCharset {
+                        index: (designator - b'(') as usize,
+                        charset,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(csi: &Csi, res: &mut Vec<AnsiOutput>) {
+        if csi.ignore {
+            return;
+        }
+
+        match csi.func {
+            // CSI > Pp m is XTMODKEYS, not an SGR
+            ansi_codes::SGR if csi.intermediates.is_empty() => {
+                for rendition in sgr(csi) {
+                    res.push(AnsiOutput::Sgr(rendition));
+                }
+            }
+            // CSI ? K and CSI ? J are the selective DECSEL and DECSED
+            ansi_codes::CLEAR_LINE if csi.intermediates.is_empty() => {
+                if let Some(mode) = ClearMode::from_param(csi.param(0, 0)) {
+                    if mode != ClearMode::Scrollback {
+                        res.push(AnsiOutput::EraseInLine(mode));
+                    }
+                }
+            }
+            ansi_codes::CLEAR_EOS if csi.intermediates.is_empty() => {
+                if let Some(mode) = ClearMode::from_param(csi.param(0, 0)) {
+                    res.push(AnsiOutput::EraseInDisplay(mode));
+                }
+            }
+            ansi_codes::CURSOR_POSITION | ansi_codes::HVP if csi.intermediates.is_empty() => {
+                let x = csi.param(1, 1);
+                let y = csi.param(0, 1);
+                res.push(AnsiOutput::MoveCursor(x - 1, y - 1));
+            }
+            ansi_codes::CURSOR_HORIZONTAL_POSITION if csi.intermediates.is_empty() => {
+                let x = csi.param(0, 1);
+                res.push(AnsiOutput::MoveCursorHorizontal(x - 1));
+            }
+            ansi_codes::CURSOR_UP if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorUp(csi.param(0, 1)))
+            }
+            ansi_codes::CURSOR_DOWN if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorDown(csi.param(0, 1)))
+            }
+            ansi_codes::CURSOR_FORWARD if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorForward(csi.param(0, 1)))
+            }
+            ansi_codes::CURSOR_BACKWARD if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorBackward(csi.param(0, 1)))
+            }
+            ansi_codes::SET_MODE | ansi_codes::RESET_MODE => {
+                let private = match csi.intermediates.as_slice() {
+                    [] => false,
+                    [b'?'] => true,
+                    _ => return,
+                };
+                for param in &csi.params {
+                    let Some(mode) = Mode::from_param(private, param[0]) else {
+                        continue;
+                    };
+                    if csi.func == ansi_codes::SET_MODE {
+                        res.push(AnsiOutput::SetMode(mode));
+                    } else {
+                        res.push(AnsiOutput::ResetMode(mode));
+                    }
+                }
+            }
+            ansi_codes::DELETE_CHARACTER if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::DeleteCharacters(csi.param(0, 1)))
+            }
+            // CSI Ps SP @ is SL
+            ansi_codes::INSERT_CHARACTER if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::InsertCharacters(csi.param(0, 1)))
+            }
+            ansi_codes::ERASE_CHARACTER if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::EraseCharacters(csi.param(0, 1)))
+            }
+            ansi_codes::REPEAT_CHARACTER if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::RepeatCharacter(csi.param(0, 1)))
+            }
+            ansi_codes::TAB_CLEAR if csi.intermediates.is_empty() => match csi.param(0, 0) {
+                0 => res.push(AnsiOutput::ClearTabStop { all: false }),
+                3 => res.push(AnsiOutput::ClearTabStop { all: true }),
+                _ => {}
+            },
+            ansi_codes::DEVICE_ATTRIBUTES if csi.param(0, 0) == 0 => {
+                match csi.intermediates.as_slice() {
+                    [] => res.push(AnsiOutput::PrimaryDeviceAttributes),
+                    [b'>'] => res.push(AnsiOutput::SecondaryDeviceAttributes),
+                    [b'='] => res.push(AnsiOutput::TertiaryDeviceAttributes),
+                    _ => {}
+                }
+            }
+            ansi_codes::DEVICE_STATUS_REPORT => {
+                match (csi.intermediates.as_slice(), csi.param(0, 0)) {
+                    ([], 5) => res.push(AnsiOutput::StatusReport),
+                    ([], 6) => res.push(AnsiOutput::CursorPositionReport { private: false }),
+                    ([b'?'], 6) => res.push(AnsiOutput::CursorPositionReport { private: true }),
+                    _ => {}
+                }
+            }
+            ansi_codes::XTVERSION if csi.intermediates == [b'>'] && csi.param(0, 0) == 0 => {
+                res.push(AnsiOutput::TerminalVersion)
+            }
+            // CSI > u and friends are for the kitty keyboard protocol
+            ansi_codes::SCO_SAVE_CURSOR if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::SaveCursor)
+            }
+            ansi_codes::SCO_RESTORE_CURSOR if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::RestoreCursor)
+            }
+            ansi_codes::CURSOR_FORWARD_TAB if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorForwardTab(csi.param(0, 1)))
+            }
+            ansi_codes::CURSOR_BACKWARD_TAB if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::CursorBackwardTab(csi.param(0, 1)))
+            }
+            ansi_codes::SET_SCROLLING_REGION if csi.intermediates.is_empty() => {
+                let top = csi.param(0, 0);
+                let bottom = csi.param(1, 0);
+                res.push(AnsiOutput::SetScrollingRegion(top, bottom));
+            }
+            ansi_codes::INSERT_LINES if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::InsertLines(csi.param(0, 1)))
+            }
+            ansi_codes::DELETE_LINES if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::DeleteLines(csi.param(0, 1)))
+            }
+            ansi_codes::XTSMGRAPHICS if csi.intermediates == [b'?'] => {
+                res.push(AnsiOutput::GraphicsAttributes {
+                    item: csi.param(0, 0),
+                    action: csi.param(1, 0),
+                })
+            }
+            ansi_codes::SCROLL_UP if csi.intermediates.is_empty() => {
+                res.push(AnsiOutput::ScrollUp(csi.param(0, 1)))
+            }
+            // CSI Ps ; Ps ; Ps ; Ps ; Ps T is the xterm mouse highlight tracking
+            ansi_codes::SCROLL_DOWN if csi.intermediates.is_empty() && csi.params.len() <= 1 => {
+                res.push(AnsiOutput::ScrollDown(csi.param(0, 1)))
+            }
+            ansi_codes::SET_CURSOR_STYLE if csi.intermediates == [b' '] => {
+                if let Some(style) = CursorStyle::from_param(csi.param(0, 0)) {
+                    res.push(AnsiOutput::SetCursorStyle(style));
+                }
+            }
+            ansi_codes::REQUEST_MODE => match csi.intermediates.as_slice() {
+                [b'$'] => res.push(AnsiOutput::RequestMode {
+                    private: false,
+                    mode: csi.param(0, 0),
+                }),
+                [b'?', b'$'] => res.push(AnsiOutput::RequestMode {
+                    private: true,
+                    mode: csi.param(0, 0),
+                }),
+                _ => {}
+            },
+            _ => {}
+        }
     }
-    params
-        .split(|v| *v == b';')
-        .map(parse_usize_param)
-        .collect()
 }
 
-fn parse_usize_param(param: &[u8]) -> usize {
-    let str = std::str::from_utf8(param).expect("Shoud be a number");
-    str.parse().map_or(0, |v| v)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notification::Payload;
+
+    fn sgr_of(data: &[u8]) -> Vec<GraphicRendition> {
+        Ansi::new()
+            .push(data)
+            .into_iter()
+            .filter_map(|o| match o {
+                AnsiOutput::Sgr(r) => Some(r),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sgr_combined() {
+        assert_eq!(
+            sgr_of(b"\x1b[1;31;22;39m"),
+            vec![
+                GraphicRendition::Bold,
+                GraphicRendition::ForegroundColor(TermColor::Indexed(1)),
+                GraphicRendition::NormalIntensity,
+                GraphicRendition::DefaultForeground,
+            ]
+        );
+        assert_eq!(sgr_of(b"\x1b[m"), vec![GraphicRendition::Reset]);
+    }
+
+    #[test]
+    fn test_sgr_underline() {
+        assert_eq!(
+            sgr_of(b"\x1b[4:3;58:2::1:2:3;4:0;21;58;5;1;59m"),
+            vec![
+                GraphicRendition::Underline(UnderlineStyle::Curly),
+                GraphicRendition::UnderlineColor(TermColor::Rgb(Color::from_rgb(1, 2, 3))),
+                GraphicRendition::Underline(UnderlineStyle::None),
+                GraphicRendition::Underline(UnderlineStyle::Double),
+                GraphicRendition::UnderlineColor(TermColor::Indexed(1)),
+                GraphicRendition::DefaultUnderlineColor,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sgr_extended_colors() {
+        assert_eq!(
+            sgr_of(b"\x1b[38;2;1;2;3;4;48;5;232m"),
+            vec![
+                GraphicRendition::ForegroundColor(TermColor::Rgb(Color::from_rgb(1, 2, 3))),
+                GraphicRendition::Underline(UnderlineStyle::Single),
+                GraphicRendition::BackgroundColor(TermColor::Indexed(232)),
+            ]
+        );
+        assert_eq!(
+            sgr_of(b"\x1b[38:2::1:2:3;48:2:4:5:6;38:5:16m"),
+            vec![
+                GraphicRendition::ForegroundColor(TermColor::Rgb(Color::from_rgb(1, 2, 3))),
+                GraphicRendition::BackgroundColor(TermColor::Rgb(Color::from_rgb(4, 5, 6))),
+                GraphicRendition::ForegroundColor(TermColor::Indexed(16)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_colors() {
+        let res =
+            Ansi::new().push(b"\x1b]4;1;#ff0000;300;?;2;?\x07\x1b]11;?;#000\x1b\\\x1b]104\x07");
+        assert!(matches!(
+            res.as_slice(),
+            [
+                AnsiOutput::SetColor(PaletteColor::Indexed(1), _),
+                AnsiOutput::QueryColor {
+                    color: PaletteColor::Indexed(2),
+                    bell_terminated: true
+                },
+                AnsiOutput::QueryColor {
+                    color: PaletteColor::Background,
+                    bell_terminated: false
+                },
+                AnsiOutput::SetColor(PaletteColor::Cursor, _),
+                AnsiOutput::ResetPalette,
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_csi_private_erase() {
+        // DECSEL, DECSED and private forms of the other edits are ignored
+        let res = Ansi::new().push(b"\x1b[?K\x1b[?2J\x1b[?P\x1b[?X\x1b[?b\x1b[2K");
+        assert!(matches!(
+            res.as_slice(),
+            [AnsiOutput::EraseInLine(ClearMode::Both)]
+        ));
+    }
+
+    #[test]
+    fn test_csi_private_cursor() {
+        // Cursor moves and line edits with markers are other sequences
+        let res = Ansi::new().push(b"\x1b[?5L\x1b[>1A\x1b[?2;3H\x1b[ 2G\x1b[?I\x1b[?M\x1b[5A");
+        assert!(matches!(res.as_slice(), [AnsiOutput::CursorUp(5)]));
+    }
+
+    #[test]
+    fn test_osc_working_directory() {
+        let res = Ansi::new().push(b"\x1b]7;file://host/tmp/a%20b;c\x07\x1b]7;http://x/y\x07");
+        assert!(matches!(
+            res.as_slice(),
+            [AnsiOutput::WorkingDirectory(path)] if path == &PathBuf::from("/tmp/a b;c")
+        ));
+    }
+
+    #[test]
+    fn test_osc_semantic_prompt() {
+        let res =
+            Ansi::new().push(b"\x1b]133;A;aid=1\x07\x1b]133;C\x07\x1b]133;D;2\x07\x1b]133;D\x07");
+        let marks: Vec<_> = res
+            .into_iter()
+            .filter_map(|o| match o {
+                AnsiOutput::SemanticPrompt(mark) => Some(mark),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            marks,
+            [
+                SemanticMark::PromptStart,
+                SemanticMark::OutputStart,
+                SemanticMark::CommandEnd(Some(2)),
+                SemanticMark::CommandEnd(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_notifications() {
+        let res = Ansi::new().push(
+            b"\x1b]9;done; ok\x07\x1b]9;4;1;50\x07\x1b]777;notify;Build;finished\x1b\\\x1b]99;i=1:d=0;Hi\x07\x1b]99;i=1:p=body:e=1;dGhlcmU=\x07",
+        );
+        let notifications: Vec<_> = res
+            .iter()
+            .filter_map(|o| match o {
+                AnsiOutput::Notification(n) => Some(n.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            notifications,
+            [
+                Notification {
+                    title: String::new(),
+                    body: String::from("done; ok"),
+                },
+                Notification {
+                    title: String::from("Build"),
+                    body: String::from("finished"),
+                },
+            ]
+        );
+
+        let chunks: Vec<_> = res
+            .into_iter()
+            .filter_map(|o| match o {
+                AnsiOutput::NotificationChunk(chunk) => Some(chunk),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                NotificationChunk {
+                    id: Some(String::from("1")),
+                    done: false,
+                    payload: Payload::Title,
+                    text: String::from("Hi"),
+                },
+                NotificationChunk {
+                    id: Some(String::from("1")),
+                    done: true,
+                    payload: Payload::Body,
+                    text: String::from("there"),
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,667 @@
+//! Byte level VT500 parser, modeled after Paul Williams' state machine
+//! https://vt100.net/emu/dec_ansi_parser
+//!
+//! The parser consumes raw bytes coming from the pty, decodes UTF-8 across
+//! reads and turns the stream into a list of [`Action`]s that the caller
+//! interprets. It has no knowledge of what the sequences mean.
+
+/// Maximum number of parameters kept for a CSI or DCS sequence, anything
+/// after that marks the sequence as ignored.
+const MAX_PARAMS: usize = 32;
+
+/// Maximum value of a single parameter, bigger values are clamped.
+const MAX_PARAM_VALUE: usize = 65535;
+
+/// Maximum number of bytes buffered for an OSC or APC string.
+const MAX_OSC_LEN: usize = 64 * 1024 * 1024;
+
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+const DEL: u8 = 0x7f;
+
+const C1_DCS: u8 = 0x90;
+const C1_SOS: u8 = 0x98;
+const C1_CSI: u8 = 0x9b;
+const C1_ST: u8 = 0x9c;
+const C1_OSC: u8 = 0x9d;
+const C1_PM: u8 = 0x9e;
+const C1_APC: u8 = 0x9f;
+
+/// A control sequence, either a CSI or the header of a DCS.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Csi {
+    /// Parameters, each one being the list of its `:` separated
+    /// sub-parameters. Missing parameters are 0.
+    pub params: Vec<Vec<usize>>,
+    /// Private markers (`<=>?`) and intermediate bytes, in order.
+    pub intermediates: Vec<u8>,
+    /// Set when the sequence was malformed or overflowed.
+    pub ignore: bool,
+    /// The final byte.
+    pub func: u8,
+}
+
+impl Csi {
+    /// Returns the first sub-parameter of the parameter at `index`, or
+    /// `default` if it is missing or 0.
+    pub fn param(&self, index: usize, default: usize) -> usize {
+        match self.params.get(index).and_then(|p| p.first()) {
+            Some(0) | None => default,
+            Some(v) => *v,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A printable character.
+    Print(char),
+    /// A C0 or C1 control function.
+    Execute(u8),
+    CsiDispatch(Csi),
+    EscDispatch {
+        intermediates: Vec<u8>,
+        func: u8,
+    },
+    /// An OSC string split on `;`.
+    OscDispatch {
+        params: Vec<Vec<u8>>,
+        bell_terminated: bool,
+    },
+    /// An APC string, used by the kitty graphics protocol.
+    ApcDispatch(Vec<u8>),
+    /// Start of a DCS, the following `Put`s are its data.
+    Hook(Csi),
+    Put(u8),
+    /// End of the current DCS.
+    Unhook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    /// An ESC inside a tmux passthrough, either doubled or starting the ST
+    DcsTmuxEscape,
+    DcsIgnore,
+    OscString,
+    ApcString,
+    SosPmString,
+}
+
+/// Incremental UTF-8 decoder.
+#[derive(Debug, Default)]
+struct Utf8Decoder {
+    codepoint: u32,
+    remaining: u8,
+    lower: u8,
+    upper: u8,
+}
+
+enum Utf8Result {
+    Char(char),
+    Pending,
+    /// The byte is not a valid continuation, the pending sequence is
+    /// replaced by U+FFFD and the byte has to be processed again.
+    Invalid,
+}
+
+impl Utf8Decoder {
+    fn in_progress(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Starts a new sequence from a lead byte, returns false if `b` is not a
+    /// valid lead byte.
+    fn start(&mut self, b: u8) -> bool {
+        let (remaining, mask, lower, upper) = match b {
+            0xc2..=0xdf => (1, 0x1f, 0x80, 0xbf),
+            0xe0 => (2, 0x0f, 0xa0, 0xbf),
+            0xed => (2, 0x0f, 0x80, 0x9f),
+            0xe1..=0xef => (2, 0x0f, 0x80, 0xbf),
+            0xf0 => (3, 0x07, 0x90, 0xbf),
+            0xf4 => (3, 0x07, 0x80, 0x8f),
+            0xf1..=0xf3 => (3, 0x07, 0x80, 0xbf),
+            _ => return false,
+        };
+        self.codepoint = (b & mask) as u32;
+        self.remaining = remaining;
+        self.lower = lower;
+        self.upper = upper;
+        true
+    }
+
+    fn push(&mut self, b: u8) -> Utf8Result {
+        if b < self.lower || b > self.upper {
+            self.remaining = 0;
+            return Utf8Result::Invalid;
+        }
+
+        self.codepoint = (self.codepoint << 6) | (b & 0x3f) as u32;
+        self.remaining -= 1;
+        self.lower = 0x80;
+        self.upper = 0xbf;
+
+        if self.remaining == 0 {
+            Utf8Result::Char(char::from_u32(self.codepoint).unwrap_or(char::REPLACEMENT_CHARACTER))
+        } else {
+            Utf8Result::Pending
+        }
+    }
+
+    fn reset(&mut self) {
+        self.remaining = 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    utf8: Utf8Decoder,
+    params: Vec<Vec<usize>>,
+    intermediates: Vec<u8>,
+    ignore: bool,
+    osc: Vec<u8>,
+    apc: Vec<u8>,
+    /// Whether the current DCS is a tmux passthrough, in which ESC is doubled
+    tmux: bool,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            utf8: Utf8Decoder::default(),
+            params: Vec::new(),
+            intermediates: Vec::new(),
+            ignore: false,
+            osc: Vec::new(),
+            apc: Vec::new(),
+            tmux: false,
+        }
+    }
+
+    /// Feeds one byte to the state machine, pushing the resulting actions
+    /// to `actions`.
+    pub fn advance(&mut self, b: u8, actions: &mut Vec<Action>) {
+        if self.state == State::Ground {
+            self.ground(b, actions);
+            return;
+        }
+
+        if self.anywhere(b, actions) {
+            return;
+        }
+
+        match self.state {
+            State::Ground => unreachable!(),
+            State::Escape => self.escape(b, actions),
+            State::EscapeIntermediate => self.escape_intermediate(b, actions),
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate | State::CsiIgnore => {
+                self.csi(b, actions)
+            }
+            State::DcsEntry | State::DcsParam | State::DcsIntermediate | State::DcsIgnore => {
+                self.dcs(b, actions)
+            }
+            State::DcsPassthrough => match b {
+                DEL => {}
+                _ => actions.push(Action::Put(b)),
+            },
+            // The ESC started the ST
+            State::DcsTmuxEscape => {
+                actions.push(Action::Unhook);
+                self.enter_escape();
+                self.escape(b, actions);
+            }
+            State::OscString => match b {
+                BEL => {
+                    self.osc_dispatch(true, actions);
+                    self.state = State::Ground;
+                }
+                0x00..=0x1f => {}
+                _ => {
+                    if self.osc.len() < MAX_OSC_LEN {
+                        self.osc.push(b);
+                    }
+                }
+            },
+            State::ApcString => match b {
+                0x00..=0x1f => {}
+                _ => {
+                    if self.apc.len() < MAX_OSC_LEN {
+                        self.apc.push(b);
+                    }
+                }
+            },
+            State::SosPmString => {}
+        }
+    }
+
+    /// Transitions that can happen from any state but the ground one.
+    /// Returns true if the byte was consumed.
+    fn anywhere(&mut self, b: u8, actions: &mut Vec<Action>) -> bool {
+        match b {
+            CAN | SUB => {
+                // Strings are aborted without being dispatched.
+                self.osc.clear();
+                self.apc.clear();
+                actions.push(Action::Execute(b));
+                self.state = State::Ground;
+            }
+            ESC if self.state == State::DcsPassthrough && self.tmux => {
+                self.state = State::DcsTmuxEscape;
+            }
+            ESC if self.state == State::DcsTmuxEscape => {
+                actions.push(Action::Put(ESC));
+                self.state = State::DcsPassthrough;
+            }
+            ESC => {
+                self.exit_string(actions);
+                self.enter_escape();
+            }
+            // 8-bit C1 controls, strings accept them as data since they
+            // are valid UTF-8 continuation bytes.
+            0x80..=0x9f if !self.in_string() => self.c1(b, actions),
+            _ => return false,
+        }
+        true
+    }
+
+    fn in_string(&self) -> bool {
+        matches!(
+            self.state,
+            State::OscString
+                | State::DcsPassthrough
+                | State::DcsTmuxEscape
+                | State::ApcString
+                | State::SosPmString
+        )
+    }
+
+    /// Dispatches the pending OSC, DCS or APC when leaving a string state.
+    fn exit_string(&mut self, actions: &mut Vec<Action>) {
+        match self.state {
+            State::OscString => self.osc_dispatch(false, actions),
+            State::DcsPassthrough | State::DcsTmuxEscape => actions.push(Action::Unhook),
+            State::ApcString => actions.push(Action::ApcDispatch(std::mem::take(&mut self.apc))),
+            _ => {}
+        }
+    }
+
+    fn ground(&mut self, b: u8, actions: &mut Vec<Action>) {
+        if self.utf8.in_progress() {
+            match self.utf8.push(b) {
+                Utf8Result::Char(c) => actions.push(Action::Print(c)),
+                Utf8Result::Pending => {}
+                Utf8Result::Invalid => {
+                    actions.push(Action::Print(char::REPLACEMENT_CHARACTER));
+                    self.advance(b, actions);
+                }
+            }
+            return;
+        }
+
+        match b {
+            ESC => self.enter_escape(),
+            0x00..=0x1f => actions.push(Action::Execute(b)),
+            DEL => {}
+            0x20..=0x7e => actions.push(Action::Print(b as char)),
+            0x80..=0x9f => self.c1(b, actions),
+            _ => {
+                if !self.utf8.start(b) {
+                    actions.push(Action::Print(char::REPLACEMENT_CHARACTER));
+                }
+            }
+        }
+    }
+
+    /// Handles an 8-bit C1 control, either introducing a sequence or being
+    /// executed.
+    fn c1(&mut self, b: u8, actions: &mut Vec<Action>) {
+        self.utf8.reset();
+        match b {
+            C1_CSI => self.enter_csi(),
+            C1_DCS => self.enter_dcs(),
+            C1_OSC => self.enter_osc(),
+            C1_APC => self.enter_apc(),
+            C1_SOS | C1_PM => self.state = State::SosPmString,
+            C1_ST => self.state = State::Ground,
+            _ => {
+                actions.push(Action::Execute(b));
+                self.state = State::Ground;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.params.clear();
+        self.intermediates.clear();
+        self.ignore = false;
+    }
+
+    fn enter_escape(&mut self) {
+        self.utf8.reset();
+        self.clear();
+        self.state = State::Escape;
+    }
+
+    fn enter_csi(&mut self) {
+        self.clear();
+        self.state = State::CsiEntry;
+    }
+
+    fn enter_dcs(&mut self) {
+        self.clear();
+        self.state = State::DcsEntry;
+    }
+
+    fn enter_osc(&mut self) {
+        self.osc.clear();
+        self.state = State::OscString;
+    }
+
+    fn enter_apc(&mut self) {
+        self.apc.clear();
+        self.state = State::ApcString;
+    }
+
+    fn collect(&mut self, b: u8) {
+        self.intermediates.push(b);
+    }
+
+    fn param(&mut self, b: u8) {
+        if self.params.is_empty() {
+            self.params.push(vec![0]);
+        }
+
+        match b {
+            b';' => {
+                if self.params.len() == MAX_PARAMS {
+                    self.ignore = true;
+                } else {
+                    self.params.push(vec![0]);
+                }
+            }
+            b':' => {
+                let param = self.params.last_mut().unwrap();
+                if param.len() == MAX_PARAMS {
+                    self.ignore = true;
+                } else {
+                    param.push(0);
+                }
+            }
+            _ => {
+                let value = self.params.last_mut().unwrap().last_mut().unwrap();
+                *value = (*value * 10 + (b - b'0') as usize).min(MAX_PARAM_VALUE);
+            }
+        }
+    }
+
+    fn sequence(&mut self, func: u8) -> Csi {
+        Csi {
+            params: std::mem::take(&mut self.params),
+            intermediates: std::mem::take(&mut self.intermediates),
+            ignore: self.ignore,
+            func,
+        }
+    }
+
+    fn escape(&mut self, b: u8, actions: &mut Vec<Action>) {
+        match b {
+            0x00..=0x1f => actions.push(Action::Execute(b)),
+            0x20..=0x2f => {
+                self.collect(b);
+                self.state = State::EscapeIntermediate;
+            }
+            b'[' => self.enter_csi(),
+            b'P' => self.enter_dcs(),
+            b']' => self.enter_osc(),
+            b'_' => self.enter_apc(),
+            b'X' | b'^' => self.state = State::SosPmString,
+            0x30..=0x7e => {
+                actions.push(Action::EscDispatch {
+                    intermediates: std::mem::take(&mut self.intermediates),
+                    func: b,
+                });
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape_intermediate(&mut self, b: u8, actions: &mut Vec<Action>) {
+        match b {
+            0x00..=0x1f => actions.push(Action::Execute(b)),
+            0x20..=0x2f => self.collect(b),
+            0x30..=0x7e => {
+                actions.push(Action::EscDispatch {
+                    intermediates: std::mem::take(&mut self.intermediates),
+                    func: b,
+                });
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, b: u8, actions: &mut Vec<Action>) {
+        match (self.state, b) {
+            (_, 0x00..=0x1f) => actions.push(Action::Execute(b)),
+            (State::CsiIgnore, 0x40..=0x7e) => self.state = State::Ground,
+            (State::CsiIgnore, _) => {}
+            (State::CsiEntry, 0x3c..=0x3f) => {
+                self.collect(b);
+                self.state = State::CsiParam;
+            }
+            (State::CsiEntry | State::CsiParam, b'0'..=b'9' | b':' | b';') => {
+                self.param(b);
+                self.state = State::CsiParam;
+            }
+            (State::CsiParam, 0x3c..=0x3f) => self.state = State::CsiIgnore,
+            (State::CsiIntermediate, 0x30..=0x3f) => self.state = State::CsiIgnore,
+            (_, 0x20..=0x2f) => {
+                self.collect(b);
+                self.state = State::CsiIntermediate;
+            }
+            (_, 0x40..=0x7e) => {
+                let csi = self.sequence(b);
+                actions.push(Action::CsiDispatch(csi));
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn dcs(&mut self, b: u8, actions: &mut Vec<Action>) {
+        match (self.state, b) {
+            (_, 0x00..=0x1f) => {}
+            (State::DcsIgnore, _) => {}
+            (State::DcsEntry, 0x3c..=0x3f) => {
+                self.collect(b);
+                self.state = State::DcsParam;
+            }
+            (State::DcsEntry | State::DcsParam, b'0'..=b'9' | b':' | b';') => {
+                self.param(b);
+                self.state = State::DcsParam;
+            }
+            (State::DcsParam, 0x3c..=0x3f) => self.state = State::DcsIgnore,
+            (State::DcsIntermediate, 0x30..=0x3f) => self.state = State::DcsIgnore,
+            (_, 0x20..=0x2f) => {
+                self.collect(b);
+                self.state = State::DcsIntermediate;
+            }
+            (_, 0x40..=0x7e) => {
+                let csi = self.sequence(b);
+                // `ESC P tmux;` wraps sequences meant for the outer terminal
+                self.tmux = b == b't' && csi.params.is_empty() && csi.intermediates.is_empty();
+                actions.push(Action::Hook(csi));
+                self.state = State::DcsPassthrough;
+            }
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, bell_terminated: bool, actions: &mut Vec<Action>) {
+        let params = self.osc.split(|b| *b == b';').map(|p| p.to_vec()).collect();
+        self.osc.clear();
+        actions.push(Action::OscDispatch {
+            params,
+            bell_terminated,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(parser: &mut Parser, data: &[u8]) -> Vec<Action> {
+        let mut actions = vec![];
+        for b in data {
+            parser.advance(*b, &mut actions);
+        }
+        actions
+    }
+
+    #[test]
+    fn test_utf8_split_across_reads() {
+        let mut p = Parser::new();
+        let bytes = "é€".as_bytes();
+        let mut actions = parse(&mut p, &bytes[..1]);
+        assert!(actions.is_empty());
+        actions.extend(parse(&mut p, &bytes[1..4]));
+        actions.extend(parse(&mut p, &bytes[4..]));
+        assert_eq!(actions, vec![Action::Print('é'), Action::Print('€')]);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\xe2\x82a");
+        assert_eq!(
+            actions,
+            vec![
+                Action::Print(char::REPLACEMENT_CHARACTER),
+                Action::Print('a')
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csi() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1b[?1;38:2:1:2:3h\x9b5A");
+        assert_eq!(
+            actions,
+            vec![
+                Action::CsiDispatch(Csi {
+                    params: vec![vec![1], vec![38, 2, 1, 2, 3]],
+                    intermediates: vec![b'?'],
+                    ignore: false,
+                    func: b'h',
+                }),
+                Action::CsiDispatch(Csi {
+                    params: vec![vec![5]],
+                    intermediates: vec![],
+                    ignore: false,
+                    func: b'A',
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1b]2;t\xc3\xa9\x07\x1b]0;x\x1b\\");
+        assert_eq!(
+            actions,
+            vec![
+                Action::OscDispatch {
+                    params: vec![b"2".to_vec(), "té".as_bytes().to_vec()],
+                    bell_terminated: true,
+                },
+                Action::OscDispatch {
+                    params: vec![b"0".to_vec(), b"x".to_vec()],
+                    bell_terminated: false,
+                },
+                Action::EscDispatch {
+                    intermediates: vec![],
+                    func: b'\\',
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_can_aborts() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1b[12\x18a\x1b]2;abc\x1ab");
+        assert_eq!(
+            actions,
+            vec![
+                Action::Execute(CAN),
+                Action::Print('a'),
+                Action::Execute(SUB),
+                Action::Print('b'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dcs() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1bP1$qm\x1b\\");
+        assert_eq!(
+            actions,
+            vec![
+                Action::Hook(Csi {
+                    params: vec![vec![1]],
+                    intermediates: vec![b'$'],
+                    ignore: false,
+                    func: b'q',
+                }),
+                Action::Put(b'm'),
+                Action::Unhook,
+                Action::EscDispatch {
+                    intermediates: vec![],
+                    func: b'\\',
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apc() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1b_Ga=q;AAAA\x1b\\\x1b^ignored\x1b\\");
+        assert_eq!(actions[0], Action::ApcDispatch(b"Ga=q;AAAA".to_vec()));
+        assert_eq!(actions.len(), 3);
+    }
+
+    #[test]
+    fn test_tmux_passthrough() {
+        let mut p = Parser::new();
+        let actions = parse(&mut p, b"\x1bPtmux;\x1b\x1b]2;x\x07\x1b\\");
+        let data: Vec<u8> = actions
+            .iter()
+            .filter_map(|a| match a {
+                Action::Put(b) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(data, b"mux;\x1b]2;x\x07");
+        assert_eq!(actions[actions.len() - 2], Action::Unhook);
+    }
+}
@@ -0,0 +1,263 @@
+//! Sixel decoder, turns the data of a `DCS P1 ; P2 ; P3 q ... ST` into an
+//! image.
+//! https://vt100.net/docs/vt3xx-gp/chapter14.html
+
+use crate::image::Image;
+
+/// Number of color registers, reported by XTSMGRAPHICS
+pub const COLOR_REGISTERS: usize = 256;
+
+/// Maximum width and height of an image, pixels past it are dropped
+pub const MAX_SIZE: usize = 4096;
+
+/// The VT340 default color registers, in percents
+const DEFAULT_COLORS: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+struct Decoder {
+    colors: Vec<[u8; 3]>,
+    color: usize,
+    x: usize,
+    /// Top of the current band of six rows
+    y: usize,
+    /// RGBA pixels, rows only grow as far as they are drawn to
+    rows: Vec<Vec<u32>>,
+    /// Size set by the raster attributes
+    raster: (usize, usize),
+}
+
+impl Decoder {
+    fn new() -> Self {
+        let mut colors = vec![[0; 3]; COLOR_REGISTERS];
+        for (color, default) in colors.iter_mut().zip(DEFAULT_COLORS) {
+            *color = default.map(percent);
+        }
+
+        Self {
+            colors,
+            color: 0,
+            x: 0,
+            y: 0,
+            rows: vec![],
+            raster: (0, 0),
+        }
+    }
+
+    /// Draws a sixel `repeat` times at the current position
+    fn sixel(&mut self, bits: u8, repeat: usize) {
+        let [r, g, b] = self.colors[self.color];
+        let pixel = u32::from_be_bytes([r, g, b, 0xff]);
+        let end = (self.x + repeat).min(MAX_SIZE);
+
+        for i in 0..6 {
+            let y = self.y + i;
+            if bits & (1 << i) == 0 || y >= MAX_SIZE {
+                continue;
+            }
+            if self.rows.len() <= y {
+                self.rows.resize(y + 1, vec![]);
+            }
+            let row = &mut self.rows[y];
+            if row.len() < end {
+                row.resize(end, 0);
+            }
+            row[self.x.min(end)..end].fill(pixel);
+        }
+
+        self.x += repeat;
+    }
+
+    fn define_color(&mut self, params: &[usize]) {
+        let [register, system, x, y, z] = params[..5] else {
+            return;
+        };
+
+        let color = match system {
+            // Hue, lightness and saturation, hue 0 is blue
+            1 => hls_to_rgb((x + 240) % 360, y.min(100), z.min(100)),
+            2 => [x, y, z].map(|c| percent(c.min(100) as u8)),
+            _ => return,
+        };
+        self.colors[register % COLOR_REGISTERS] = color;
+    }
+
+    fn finish(self, transparent: bool) -> Option<Image> {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .unwrap_or(0)
+            .max(self.raster.0.min(MAX_SIZE));
+        let drawn = self
+            .rows
+            .iter()
+            .rposition(|row| row.iter().any(|p| *p != 0))
+            .map_or(0, |y| y + 1);
+        let height = drawn.max(self.raster.1.min(MAX_SIZE));
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        // Pixels that were never drawn have the color of register 0, unless
+        // the background is transparent
+        let [r, g, b] = self.colors[0];
+        let background = if transparent {
+            0
+        } else {
+            u32::from_be_bytes([r, g, b, 0xff])
+        };
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let row = self.rows.get(y).map_or(&[][..], |row| row.as_slice());
+            for x in 0..width {
+                let pixel = match row.get(x) {
+                    Some(p) if *p != 0 => *p,
+                    _ => background,
+                };
+                rgba.extend_from_slice(&pixel.to_be_bytes());
+            }
+        }
+
+        Some(Image {
+            width,
+            height,
+            rgba,
+        })
+    }
+}
+
+/// Decodes sixel data, `transparent` is set when P2 is 1
+pub fn decode(data: &[u8], transparent: bool) -> Option<Image> {
+    let mut decoder = Decoder::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let b = data[i];
+        i += 1;
+
+        match b {
+            // Raster attributes, Pan ; Pad ; Ph ; Pv
+            b'"' => {
+                let params = parse_params(data, &mut i);
+                if let [_, _, width, height, ..] = params[..] {
+                    decoder.raster = (width, height);
+                }
+            }
+            b'#' => {
+                let params = parse_params(data, &mut i);
+                match params[..] {
+                    [register] => decoder.color = register % COLOR_REGISTERS,
+                    [register, ..] if params.len() >= 5 => {
+                        decoder.define_color(&params);
+                        decoder.color = register % COLOR_REGISTERS;
+                    }
+                    _ => {}
+                }
+            }
+            b'!' => {
+                let repeat = parse_params(data, &mut i).first().copied().unwrap_or(1);
+                if let Some(b @ 0x3f..=0x7e) = data.get(i) {
+                    decoder.sixel(b - 0x3f, repeat.max(1));
+                    i += 1;
+                }
+            }
+            b'$' => decoder.x = 0,
+            b'-' => {
+                decoder.x = 0;
+                decoder.y += 6;
+            }
+            0x3f..=0x7e => decoder.sixel(b - 0x3f, 1),
+            _ => {}
+        }
+    }
+
+    decoder.finish(transparent)
+}
+
+/// Parses the `;` separated numbers starting at `i`, missing ones are 0
+fn parse_params(data: &[u8], i: &mut usize) -> Vec<usize> {
+    let mut params = vec![0];
+
+    while let Some(b) = data.get(*i) {
+        match b {
+            b'0'..=b'9' => {
+                let param = params.last_mut().unwrap();
+                *param = (*param * 10 + (b - b'0') as usize).min(u16::MAX as usize);
+            }
+            b';' => params.push(0),
+            _ => break,
+        }
+        *i += 1;
+    }
+
+    params
+}
+
+fn percent(value: u8) -> u8 {
+    (value as usize * 255 / 100) as u8
+}
+
+/// Converts a color with a hue in degrees, and lightness and saturation in
+/// percents
+fn hls_to_rgb(hue: usize, lightness: usize, saturation: usize) -> [u8; 3] {
+    let l = lightness as f32 / 100.0;
+    let s = saturation as f32 / 100.0;
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = hue as f32 / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as usize {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+
+    [r, g, b].map(|v| ((v + m) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // A red 2x6 column, then a green pixel on the next band
+        let image = decode(b"\"1;1;3;8#1;2;100;0;0#1!2~-#2;2;0;100;0@", true).unwrap();
+        assert_eq!((image.width, image.height), (3, 8));
+
+        let pixel = |x: usize, y: usize| &image.rgba[(y * 3 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(1, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(0, 6), [0, 255, 0, 255]);
+        assert_eq!(pixel(1, 6), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hls() {
+        // Hue 120 is red in sixel
+        let image = decode(b"#1;1;120;50;100#1~", false).unwrap();
+        assert_eq!(&image.rgba[..4], [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Character sets designated into G0 to G3, which translate the printable
+//! ASCII characters. https://vt100.net/docs/vt220-rm/chapter2.html
+
+/// Characters replaced by the national sets
+const NATIONAL_POSITIONS: [char; 12] =
+    ['#', '@', '[', '\\', ']', '^', '_', '`', '{', '|', '}', '~'];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// Line drawing characters, used by curses for boxes
+    DecSpecialGraphics,
+    /// ASCII with a pound sign instead of `#`
+    Uk,
+    Dutch,
+    Finnish,
+    French,
+    FrenchCanadian,
+    German,
+    Italian,
+    NorwegianDanish,
+    Portuguese,
+    Spanish,
+    Swedish,
+    Swiss,
+}
+
+impl Charset {
+    /// Maps the final bytes of a designation, `intermediates` are the ones
+    /// after the `(`, `)`, `*` or `+`
+    pub fn from_designator(intermediates: &[u8], func: u8) -> Option<Self> {
+        let charset = match (intermediates, func) {
+            ([], b'B') => Self::Ascii,
+            ([], b'0') => Self::DecSpecialGraphics,
+            ([], b'A') => Self::Uk,
+            ([], b'4') => Self::Dutch,
+            ([], b'C' | b'5') => Self::Finnish,
+            ([], b'R' | b'f') => Self::French,
+            ([], b'Q' | b'9') => Self::FrenchCanadian,
+            ([], b'K') => Self::German,
+            ([], b'Y') => Self::Italian,
+            ([], b'E' | b'6' | b'`') => Self::NorwegianDanish,
+            ([b'%'], b'6') => Self::Portuguese,
+            ([], b'Z') => Self::Spanish,
+            ([], b'H' | b'7') => Self::Swedish,
+            ([], b'=') => Self::Swiss,
+            _ => return None,
+        };
+
+        Some(charset)
+    }
+
+    /// Whether this is a national replacement character set, only used when
+    /// DECNRCM is set
+    pub fn is_national(self) -> bool {
+        !matches!(self, Self::Ascii | Self::DecSpecialGraphics | Self::Uk)
+    }
+
+    /// Returns the character that `c` stands for in this set
+    pub fn map(self, c: char) -> char {
+        let national = match self {
+            Self::Ascii => return c,
+            Self::DecSpecialGraphics => return dec_special_graphics(c),
+            Self::Uk => "£@[\\]^_`{|}~",
+            Self::Dutch => "£¾ĳ½|^_`¨ƒ¼´",
+            Self::Finnish => "#@ÄÖÅÜ_éäöåü",
+            Self::French => "£à°ç§^_`éùè¨",
+            Self::FrenchCanadian => "#àâçêî_ôéùèû",
+            Self::German => "#§ÄÖÜ^_`äöüß",
+            Self::Italian => "£§°çé^_ùàòèì",
+            Self::NorwegianDanish => "#ÄÆØÅÜ_äæøåü",
+            Self::Portuguese => "#@ÃÇÕ^_`ãçõ~",
+            Self::Spanish => "£§¡Ñ¿^_`°ñç~",
+            Self::Swedish => "#ÉÄÖÅÜ_éäöåü",
+            Self::Swiss => "ùàéçêîèôäöüû",
+        };
+
+        match NATIONAL_POSITIONS.iter().position(|p| *p == c) {
+            Some(i) => national.chars().nth(i).unwrap_or(c),
+            None => c,
+        }
+    }
+}
+
+fn dec_special_graphics(c: char) -> char {
+    match c {
+        '_' => ' ',
+        '`' => '◆',
+        'a' => '▒',
+        'b' => '␉',
+        'c' => '␌',
+        'd' => '␍',
+        'e' => '␊',
+        'f' => '°',
+        'g' => '±',
+        'h' => '␤',
+        'i' => '␋',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        c => c,
+    }
+}
+
+/// The designated sets and which of them are invoked
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Charsets {
+    /// G0 to G3
+    pub sets: [Charset; 4],
+    /// Set invoked into GL by a locking shift
+    pub gl: usize,
+    /// Set used for the next character only, by SS2 or SS3
+    pub single_shift: Option<usize>,
+}
+
+impl Charsets {
+    /// Translates a printed character with the invoked set
+    pub fn translate(&mut self, c: char) -> char {
+        let set = self.single_shift.take().unwrap_or(self.gl);
+        if c.is_ascii_graphic() {
+            self.sets[set].map(c)
+        } else {
+            c
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        let mut charsets = Charsets::default();
+        charsets.sets[1] = Charset::DecSpecialGraphics;
+        charsets.sets[2] = Charset::from_designator(&[], b'K').unwrap();
+        assert_eq!(charsets.translate('q'), 'q');
+
+        charsets.gl = 1;
+        let line: String = "lqkxa".chars().map(|c| charsets.translate(c)).collect();
+        assert_eq!(line, "┌─┐│▒");
+
+        // A single shift only applies to the next character
+        charsets.single_shift = Some(2);
+        assert_eq!(charsets.translate('{'), 'ä');
+        assert_eq!(charsets.translate('{'), 'π');
+        assert_eq!(Charset::Uk.map('#'), '£');
+        assert_eq!(Charset::Portuguese.map('['), 'Ã');
+    }
+}
@@ -0,0 +1,65 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// A clipboard an application can access with OSC 52
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clipboard {
+    Clipboard,
+    /// The primary selection
+    Primary,
+}
+
+impl Clipboard {
+    /// Maps the selection parameter of OSC 52, the first supported
+    /// selection is used and an empty parameter means the clipboard
+    pub fn from_param(param: &[u8]) -> Option<Self> {
+        if param.is_empty() {
+            return Some(Self::Clipboard);
+        }
+
+        param.iter().find_map(|c| match c {
+            b'c' => Some(Self::Clipboard),
+            b'p' | b's' => Some(Self::Primary),
+            _ => None,
+        })
+    }
+
+    fn param(self) -> char {
+        match self {
+            Self::Clipboard => 'c',
+            Self::Primary => 'p',
+        }
+    }
+}
+
+/// A clipboard request from the application, handled by the GUI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardEvent {
+    Store(Clipboard, String),
+    /// The contents of the clipboard are sent back with `reply`
+    Load {
+        clipboard: Clipboard,
+        bell_terminated: bool,
+    },
+}
+
+impl ClipboardEvent {
+    /// Returns the answer to a load request with the clipboard contents
+    pub fn reply(&self, text: &str) -> Option<Vec<u8>> {
+        let Self::Load {
+            clipboard,
+            bell_terminated,
+        } = self
+        else {
+            return None;
+        };
+
+        let terminator = if *bell_terminated { "\x07" } else { "\x1b\\" };
+        let reply = format!(
+            "\x1b]52;{};{}{terminator}",
+            clipboard.param(),
+            STANDARD.encode(text)
+        );
+
+        Some(reply.into_bytes())
+    }
+}
@@ -32,6 +32,195 @@ impl Color {
     pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
         Self([r, g, b])
     }
+
+    /// Returns the faint version of the color, used for SGR 2
+    pub const fn dim(self) -> Self {
+        Self::from_rgb(
+            (self.0[0] as u16 * 2 / 3) as u8,
+            (self.0[1] as u16 * 2 / 3) as u8,
+            (self.0[2] as u16 * 2 / 3) as u8,
+        )
+    }
+
+    /// Parses an X11 color specification as used by the OSC color sequences:
+    /// `rgb:r/g/b` with 1 to 4 hex digits per channel, or `#rgb` with 1 to 4
+    /// hex digits per channel
+    pub fn from_spec(spec: &[u8]) -> Option<Self> {
+        let spec = std::str::from_utf8(spec).ok()?;
+
+        // Scales a channel of 1 to 4 hex digits to 8 bits
+        let channel = |hex: &str| -> Option<u8> {
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let max = (1u32 << (4 * hex.len())) - 1;
+            let v = u32::from_str_radix(hex, 16).ok()?;
+            Some((v * 255 / max) as u8)
+        };
+
+        if let Some(rgb) = spec.strip_prefix("rgb:") {
+            let mut channels = rgb.split('/').map(channel);
+            let color = Self::from_rgb(channels.next()??, channels.next()??, channels.next()??);
+            return channels.next().is_none().then_some(color);
+        }
+
+        let hex = spec.strip_prefix('#')?;
+        if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+            return None;
+        }
+        // In the # form the digits are the most significant ones
+        let n = hex.len() / 3;
+        let high = |i: usize| {
+            let v = u8::from_str_radix(&hex[i * n..i * n + n.min(2)], 16).ok()?;
+            Some(if n == 1 { v << 4 } else { v })
+        };
+
+        Some(Self::from_rgb(high(0)?, high(1)?, high(2)?))
+    }
+
+    /// Formats the color as `rgb:rrrr/gggg/bbbb`, the format of the replies to
+    /// OSC color queries
+    pub fn to_spec(self) -> String {
+        let [r, g, b] = self.0;
+        format!(
+            "rgb:{:04x}/{:04x}/{:04x}",
+            r as u16 * 257,
+            g as u16 * 257,
+            b as u16 * 257
+        )
+    }
+}
+
+/// A color of a cell, resolved against the palette when drawing so that
+/// palette changes apply to text already on screen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TermColor {
+    /// The default foreground or background color
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(Color),
+}
+
+/// A color of the palette that applications can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteColor {
+    Indexed(u8),
+    Foreground,
+    Background,
+    Cursor,
+    SelectionBackground,
+    SelectionForeground,
+}
+
+/// The colors used to draw the terminal
+#[derive(Debug, Clone)]
+pub struct Palette {
+    indexed: [Color; 256],
+    foreground: Color,
+    background: Color,
+    cursor: Color,
+    selection_background: Color,
+    selection_foreground: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            indexed: std::array::from_fn(|i| Self::default_color(PaletteColor::Indexed(i as u8))),
+            foreground: Self::default_color(PaletteColor::Foreground),
+            background: Self::default_color(PaletteColor::Background),
+            cursor: Self::default_color(PaletteColor::Cursor),
+            selection_background: Self::default_color(PaletteColor::SelectionBackground),
+            selection_foreground: Self::default_color(PaletteColor::SelectionForeground),
+        }
+    }
+}
+
+impl Palette {
+    fn default_color(color: PaletteColor) -> Color {
+        match color {
+            PaletteColor::Indexed(i) => default_indexed(i),
+            PaletteColor::Foreground => Color::WHITE,
+            PaletteColor::Background => Color::BLACK,
+            PaletteColor::Cursor => Color::from_rgb(255, 255, 255),
+            PaletteColor::SelectionBackground => Color::from_rgb(128, 128, 255),
+            PaletteColor::SelectionForeground => Color::WHITE,
+        }
+    }
+
+    pub fn get(&self, color: PaletteColor) -> Color {
+        match color {
+            PaletteColor::Indexed(i) => self.indexed[i as usize],
+            PaletteColor::Foreground => self.foreground,
+            PaletteColor::Background => self.background,
+            PaletteColor::Cursor => self.cursor,
+            PaletteColor::SelectionBackground => self.selection_background,
+            PaletteColor::SelectionForeground => self.selection_foreground,
+        }
+    }
+
+    pub fn set(&mut self, color: PaletteColor, value: Color) {
+        let slot = match color {
+            PaletteColor::Indexed(i) => &mut self.indexed[i as usize],
+            PaletteColor::Foreground => &mut self.foreground,
+            PaletteColor::Background => &mut self.background,
+            PaletteColor::Cursor => &mut self.cursor,
+            PaletteColor::SelectionBackground => &mut self.selection_background,
+            PaletteColor::SelectionForeground => &mut self.selection_foreground,
+        };
+        *slot = value;
+    }
+
+    /// Restores the default value of `color`
+    pub fn reset(&mut self, color: PaletteColor) {
+        self.set(color, Self::default_color(color));
+    }
+
+    /// Returns the color to draw a cell color with, `default` is used for
+    /// `TermColor::Default`
+    pub fn resolve(&self, color: TermColor, default: PaletteColor) -> Color {
+        match color {
+            TermColor::Default => self.get(default),
+            TermColor::Indexed(i) => self.indexed[i as usize],
+            TermColor::Rgb(c) => c,
+        }
+    }
+}
+
+/// The default colors of the 256 color palette
+fn default_indexed(item: u8) -> Color {
+    match item {
+        0 => Color::BLACK,
+        1 => Color::RED,
+        2 => Color::GREEN,
+        3 => Color::YELLOW,
+        4 => Color::BLUE,
+        5 => Color::MAGENTA,
+        6 => Color::CYAN,
+        7 => Color::WHITE,
+
+        8 => Color::GRAY,
+        9 => Color::RED,
+        10 => Color::GREEN,
+        11 => Color::YELLOW,
+        12 => Color::BLUE,
+        13 => Color::MAGENTA,
+        14 => Color::CYAN,
+        15 => Color::WHITE,
+
+        // 6x6x6 color cube
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let i = item - 16;
+            Color::from_rgb(level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        // Grayscale ramp
+        232..=255 => {
+            let v = 8 + (item - 232) * 10;
+            Color::from_rgb(v, v, v)
+        }
+    }
 }
 
 impl From<Color> for Color32 {
@@ -39,3 +228,32 @@ impl From<Color> for Color32 {
         Self::from_rgb(c.0[0], c.0[1], c.0[2])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_palette() {
+        let palette = Palette::default();
+        let indexed = |i| palette.get(PaletteColor::Indexed(i));
+        assert_eq!(indexed(1), Color::RED);
+        assert_eq!(indexed(16), Color::from_rgb(0, 0, 0));
+        assert_eq!(indexed(232), Color::from_rgb(8, 8, 8));
+    }
+
+    #[test]
+    fn test_color_spec() {
+        let rgb = Color::from_rgb(0x12, 0x34, 0xff);
+        assert_eq!(Color::from_spec(b"rgb:1212/3434/ffff"), Some(rgb));
+        assert_eq!(Color::from_spec(b"rgb:12/34/f"), Some(rgb));
+        assert_eq!(Color::from_spec(b"#1234ff"), Some(rgb));
+        assert_eq!(
+            Color::from_spec(b"#123456fff"),
+            Some(Color::from_rgb(0x12, 0x45, 0xff))
+        );
+        assert_eq!(Color::from_spec(b"rgb:12/34"), None);
+        assert_eq!(Color::from_spec(b"red"), None);
+        assert_eq!(rgb.to_spec(), "rgb:1212/3434/ffff");
+    }
+}
@@ -0,0 +1,445 @@
+//! Images transmitted with the kitty graphics protocol, and the loading of
+//! their data from the different transmission media.
+//! https://sw.kovidgoyal.net/kitty/graphics-protocol/
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    os::{fd::FromRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{ansi::kitty::GraphicsCommand, image::Image};
+
+/// Memory used by the stored images before the oldest ones are dropped, the
+/// same as kitty
+pub const DEFAULT_QUOTA: usize = 320 * 1024 * 1024;
+
+/// Maximum width and height of a transmitted image
+pub const MAX_SIZE: usize = 10000;
+
+/// Character printed by applications where an image placed with `U=1` is
+/// shown
+pub const PLACEHOLDER: char = '\u{10EEEE}';
+
+/// Combining characters following a placeholder, their index in this list is
+/// the row, column, or most significant byte of the image id
+const DIACRITICS: [u32; 297] = [
+    0x0305, 0x030D, 0x030E, 0x0310, 0x0312, 0x033D, 0x033E, 0x033F, 0x0346, 0x034A, 0x034B, 0x034C,
+    0x0350, 0x0351, 0x0352, 0x0357, 0x035B, 0x0363, 0x0364, 0x0365, 0x0366, 0x0367, 0x0368, 0x0369,
+    0x036A, 0x036B, 0x036C, 0x036D, 0x036E, 0x036F, 0x0483, 0x0484, 0x0485, 0x0486, 0x0487, 0x0592,
+    0x0593, 0x0594, 0x0595, 0x0597, 0x0598, 0x0599, 0x059C, 0x059D, 0x059E, 0x059F, 0x05A0, 0x05A1,
+    0x05A8, 0x05A9, 0x05AB, 0x05AC, 0x05AF, 0x05C4, 0x0610, 0x0611, 0x0612, 0x0613, 0x0614, 0x0615,
+    0x0616, 0x0617, 0x0657, 0x0658, 0x0659, 0x065A, 0x065B, 0x065D, 0x065E, 0x06D6, 0x06D7, 0x06D8,
+    0x06D9, 0x06DA, 0x06DB, 0x06DC, 0x06DF, 0x06E0, 0x06E1, 0x06E2, 0x06E4, 0x06E7, 0x06E8, 0x06EB,
+    0x06EC, 0x0730, 0x0732, 0x0733, 0x0735, 0x0736, 0x073A, 0x073D, 0x073F, 0x0740, 0x0741, 0x0743,
+    0x0745, 0x0747, 0x0749, 0x074A, 0x07EB, 0x07EC, 0x07ED, 0x07EE, 0x07EF, 0x07F0, 0x07F1, 0x07F3,
+    0x0816, 0x0817, 0x0818, 0x0819, 0x081B, 0x081C, 0x081D, 0x081E, 0x081F, 0x0820, 0x0821, 0x0822,
+    0x0823, 0x0825, 0x0826, 0x0827, 0x0829, 0x082A, 0x082B, 0x082C, 0x082D, 0x0951, 0x0953, 0x0954,
+    0x0F82, 0x0F83, 0x0F86, 0x0F87, 0x135D, 0x135E, 0x135F, 0x17DD, 0x193A, 0x1A17, 0x1A75, 0x1A76,
+    0x1A77, 0x1A78, 0x1A79, 0x1A7A, 0x1A7B, 0x1A7C, 0x1B6B, 0x1B6D, 0x1B6E, 0x1B6F, 0x1B70, 0x1B71,
+    0x1B72, 0x1B73, 0x1CD0, 0x1CD1, 0x1CD2, 0x1CDA, 0x1CDB, 0x1CE0, 0x1DC0, 0x1DC1, 0x1DC3, 0x1DC4,
+    0x1DC5, 0x1DC6, 0x1DC7, 0x1DC8, 0x1DC9, 0x1DCB, 0x1DCC, 0x1DD1, 0x1DD2, 0x1DD3, 0x1DD4, 0x1DD5,
+    0x1DD6, 0x1DD7, 0x1DD8, 0x1DD9, 0x1DDA, 0x1DDB, 0x1DDC, 0x1DDD, 0x1DDE, 0x1DDF, 0x1DE0, 0x1DE1,
+    0x1DE2, 0x1DE3, 0x1DE4, 0x1DE5, 0x1DE6, 0x1DFE, 0x20D0, 0x20D1, 0x20D4, 0x20D5, 0x20D6, 0x20D7,
+    0x20DB, 0x20DC, 0x20E1, 0x20E7, 0x20E9, 0x20F0, 0x2CEF, 0x2CF0, 0x2CF1, 0x2DE0, 0x2DE1, 0x2DE2,
+    0x2DE3, 0x2DE4, 0x2DE5, 0x2DE6, 0x2DE7, 0x2DE8, 0x2DE9, 0x2DEA, 0x2DEB, 0x2DEC, 0x2DED, 0x2DEE,
+    0x2DEF, 0x2DF0, 0x2DF1, 0x2DF2, 0x2DF3, 0x2DF4, 0x2DF5, 0x2DF6, 0x2DF7, 0x2DF8, 0x2DF9, 0x2DFA,
+    0x2DFB, 0x2DFC, 0x2DFD, 0x2DFE, 0x2DFF, 0xA66F, 0xA67C, 0xA67D, 0xA6F0, 0xA6F1, 0xA8E0, 0xA8E1,
+    0xA8E2, 0xA8E3, 0xA8E4, 0xA8E5, 0xA8E6, 0xA8E7, 0xA8E8, 0xA8E9, 0xA8EA, 0xA8EB, 0xA8EC, 0xA8ED,
+    0xA8EE, 0xA8EF, 0xA8F0, 0xA8F1, 0xAAB0, 0xAAB2, 0xAAB3, 0xAAB7, 0xAAB8, 0xAABE, 0xAABF, 0xAAC1,
+    0xFE20, 0xFE21, 0xFE22, 0xFE23, 0xFE24, 0xFE25, 0xFE26, 0x10A0F, 0x10A38, 0x1D185, 0x1D186,
+    0x1D187, 0x1D188, 0x1D189, 0x1D1AA, 0x1D1AB, 0x1D1AC, 0x1D1AD, 0x1D242, 0x1D243, 0x1D244,
+];
+
+/// Returns the number encoded by a diacritic following a placeholder
+pub fn diacritic_index(c: char) -> Option<u32> {
+    DIACRITICS.binary_search(&(c as u32)).ok().map(|i| i as u32)
+}
+
+#[derive(Debug)]
+struct StoredImage {
+    image: Arc<Image>,
+    number: u32,
+    /// Order of transmission, the oldest images are dropped first
+    age: u64,
+}
+
+/// The images transmitted by the application, which can be placed several
+/// times
+#[derive(Debug, Default)]
+pub struct KittyImages {
+    images: HashMap<u32, StoredImage>,
+    /// Ids of the images by number, the newest image wins
+    numbers: HashMap<u32, u32>,
+    /// Transmission whose chunks are still arriving
+    pending: Option<GraphicsCommand>,
+    /// Size in cells of the placements shown with placeholders, by image id
+    virtual_placements: HashMap<u32, (u32, u32)>,
+    used: usize,
+    age: u64,
+    next_id: u32,
+}
+
+impl KittyImages {
+    /// Adds a chunk of a transmission, returns the whole command once its
+    /// last chunk arrived
+    pub fn chunk(&mut self, cmd: GraphicsCommand, quota: usize) -> Option<GraphicsCommand> {
+        let Some(mut pending) = self.pending.take() else {
+            if cmd.more {
+                self.pending = Some(cmd);
+                return None;
+            }
+            return Some(cmd);
+        };
+
+        pending.payload.extend_from_slice(&cmd.payload);
+        if !cmd.more {
+            return Some(pending);
+        }
+        // A transmission that can't fit in the quota is dropped
+        if pending.payload.len() <= quota {
+            self.pending = Some(pending);
+        }
+        None
+    }
+
+    /// Stores an image, dropping the oldest ones to stay under `quota`.
+    /// Returns the id of the image, one is chosen if `id` is 0.
+    pub fn insert(&mut self, id: u32, number: u32, image: Image, quota: usize) -> u32 {
+        let id = match id {
+            0 => self.free_id(),
+            id => id,
+        };
+        self.remove(id);
+
+        let size = image.rgba.len();
+        while self.used + size > quota {
+            let Some(oldest) = self
+                .images
+                .iter()
+                .min_by_key(|(_, i)| i.age)
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            self.remove(oldest);
+        }
+
+        self.used += size;
+        self.age += 1;
+        if number != 0 {
+            self.numbers.insert(number, id);
+        }
+        self.images.insert(
+            id,
+            StoredImage {
+                image: Arc::new(image),
+                number,
+                age: self.age,
+            },
+        );
+        id
+    }
+
+    fn free_id(&mut self) -> u32 {
+        loop {
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            if !self.images.contains_key(&self.next_id) {
+                return self.next_id;
+            }
+        }
+    }
+
+    /// Returns the id and the image with an id, or a number if `id` is 0
+    pub fn get(&self, id: u32, number: u32) -> Option<(u32, Arc<Image>)> {
+        let id = match id {
+            0 => *self.numbers.get(&number)?,
+            id => id,
+        };
+
+        self.images.get(&id).map(|i| (id, i.image.clone()))
+    }
+
+    /// Returns the id of the newest image with a number
+    pub fn id_of_number(&self, number: u32) -> Option<u32> {
+        self.numbers.get(&number).copied()
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(image) = self.images.remove(&id) {
+            self.used -= image.image.rgba.len();
+            if self.numbers.get(&image.number) == Some(&id) {
+                self.numbers.remove(&image.number);
+            }
+        }
+        self.virtual_placements.remove(&id);
+    }
+
+    /// Records the size in cells of an image shown with placeholders
+    pub fn set_virtual_placement(&mut self, id: u32, columns: u32, rows: u32) {
+        self.virtual_placements.insert(id, (columns, rows));
+    }
+
+    pub fn virtual_placement(&self, id: u32) -> Option<(u32, u32)> {
+        self.virtual_placements.get(&id).copied()
+    }
+}
+
+/// Reads the data of a transmission and decodes it into an image. The error
+/// is the message sent back to the application.
+pub fn load(cmd: &GraphicsCommand, quota: usize) -> Result<Image, &'static str> {
+    // Raw pixels are never more than 4 bytes each, and no image is larger
+    // than the quota
+    let limit = match cmd.format {
+        24 | 32 => (cmd.width as u64 * cmd.height as u64 * 4).min(quota as u64),
+        _ => quota as u64,
+    };
+    let read_limit = if cmd.compression.is_some() {
+        quota as u64
+    } else {
+        limit
+    };
+
+    let data = match cmd.medium {
+        b'd' => cmd.payload.clone(),
+        b'f' => read_file(&cmd.payload, cmd, false, read_limit)?,
+        b't' => read_file(&cmd.payload, cmd, true, read_limit)?,
+        b's' => read_shared_memory(&cmd.payload, cmd, read_limit)?,
+        _ => return Err("EINVAL:unknown transmission medium"),
+    };
+
+    let data = match cmd.compression {
+        None => data,
+        Some(b'z') => {
+            let mut inflated = vec![];
+            flate2::read::ZlibDecoder::new(data.as_slice())
+                .take(limit)
+                .read_to_end(&mut inflated)
+                .map_err(|_| "EINVAL:invalid zlib data")?;
+            inflated
+        }
+        Some(_) => return Err("EINVAL:unknown compression"),
+    };
+
+    let channels = match cmd.format {
+        100 => return Image::decode(&data, quota).map_err(|_| "EBADPNG:invalid PNG data"),
+        24 => 3,
+        32 => 4,
+        _ => return Err("EINVAL:unknown format"),
+    };
+
+    let (width, height) = (cmd.width as usize, cmd.height as usize);
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err("EINVAL:invalid image size");
+    }
+    let Some(pixels) = data.get(..width * height * channels) else {
+        return Err("ENODATA:insufficient image data");
+    };
+
+    let rgba = match channels {
+        3 => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        _ => pixels.to_vec(),
+    };
+    Ok(Image {
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Reads a file named by the payload. Temporary files are deleted, as long as
+/// they look like they were made for this purpose. Only regular files outside
+/// of /proc, /sys and /dev are read, except for /dev/shm.
+fn read_file(
+    path: &[u8],
+    cmd: &GraphicsCommand,
+    temporary: bool,
+    limit: u64,
+) -> Result<Vec<u8>, &'static str> {
+    let path = std::str::from_utf8(path).map_err(|_| "EINVAL:invalid file name")?;
+    let path = Path::new(path);
+    let real_path = path
+        .canonicalize()
+        .map_err(|_| "EBADF:could not open the file")?;
+    let forbidden = ["/proc", "/sys", "/dev"]
+        .iter()
+        .any(|dir| real_path.starts_with(dir));
+    if forbidden && !real_path.starts_with("/dev/shm") {
+        return Err("EPERM:file in a forbidden directory");
+    }
+
+    // Opened without blocking in case the file was swapped for a FIFO
+    let mut file = File::options()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&real_path)
+        .map_err(|_| "EBADF:could not open the file")?;
+    if !file.metadata().is_ok_and(|m| m.is_file()) {
+        return Err("EBADF:not a regular file");
+    }
+    let data = read_range(&mut file, cmd.offset, cmd.size, limit);
+
+    // Checked on the real path, `..` and links can't lead out of the
+    // temporary directories
+    let temp_dir = std::env::temp_dir().canonicalize();
+    let in_temp_dir = temp_dir
+        .iter()
+        .map(|dir| dir.as_path())
+        .chain([Path::new("/dev/shm")])
+        .any(|dir| real_path.starts_with(dir));
+    let name = real_path.file_name().unwrap_or_default().to_string_lossy();
+    if temporary && in_temp_dir && name.contains("tty-graphics-protocol") {
+        let _ = std::fs::remove_file(&real_path);
+    }
+
+    data.map_err(|_| "EBADF:could not read the file")
+}
+
+/// Reads a POSIX shared memory object named by the payload, and unlinks it
+fn read_shared_memory(
+    name: &[u8],
+    cmd: &GraphicsCommand,
+    limit: u64,
+) -> Result<Vec<u8>, &'static str> {
+    let name = CString::new(name).map_err(|_| "EINVAL:invalid shared memory name")?;
+
+    let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+    if fd < 0 {
+        return Err("EBADF:could not open the shared memory");
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    unsafe { libc::shm_unlink(name.as_ptr()) };
+
+    read_range(&mut file, cmd.offset, cmd.size, limit)
+        .map_err(|_| "EBADF:could not read the shared memory")
+}
+
+/// Reads `size` bytes from `offset`, or up to the end if `size` is 0, but no
+/// more than `limit`
+fn read_range(file: &mut File, offset: usize, size: usize, limit: u64) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset as u64))?;
+
+    let limit = match size {
+        0 => limit,
+        size => limit.min(size as u64),
+    };
+    let mut data = vec![];
+    file.take(limit).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rgb(width: u32, height: u32) -> GraphicsCommand {
+        GraphicsCommand {
+            format: 24,
+            width,
+            height,
+            payload: vec![0; (width * height * 3) as usize],
+            ..GraphicsCommand::default()
+        }
+    }
+
+    #[test]
+    fn test_quota() {
+        let mut images = KittyImages::default();
+        let a = images.insert(0, 1, load(&rgb(2, 2), DEFAULT_QUOTA).unwrap(), 32);
+        let b = images.insert(0, 1, load(&rgb(2, 2), DEFAULT_QUOTA).unwrap(), 32);
+        assert_ne!(a, b);
+        assert_eq!(images.get(0, 1).map(|(id, _)| id), Some(b));
+
+        // The oldest image makes room for the new one
+        images.insert(7, 0, load(&rgb(2, 2), DEFAULT_QUOTA).unwrap(), 32);
+        assert!(images.get(a, 0).is_none());
+        assert!(images.get(b, 0).is_some());
+        assert_eq!(load(&rgb(2, 2), DEFAULT_QUOTA).unwrap().rgba[3], 0xff);
+    }
+
+    #[test]
+    fn test_load() {
+        let mut cmd = rgb(2, 2);
+        cmd.payload.pop();
+        assert_eq!(
+            load(&cmd, DEFAULT_QUOTA),
+            Err("ENODATA:insufficient image data")
+        );
+
+        let path = std::env::temp_dir().join("turm-tty-graphics-protocol-test");
+        std::fs::write(&path, [1, 2, 3, 4, 5]).unwrap();
+        let cmd = GraphicsCommand {
+            format: 32,
+            medium: b't',
+            width: 1,
+            height: 1,
+            offset: 1,
+            payload: path.to_str().unwrap().as_bytes().to_vec(),
+            ..GraphicsCommand::default()
+        };
+        assert_eq!(load(&cmd, DEFAULT_QUOTA).unwrap().rgba, [2, 3, 4, 5]);
+        assert!(!path.exists());
+
+        // Only files really in a temporary directory are deleted
+        let kept = Path::new(env!("CARGO_MANIFEST_DIR")).join("turm-tty-graphics-protocol-test");
+        std::fs::write(&kept, [1, 2, 3, 4, 5]).unwrap();
+        let temp_dir = std::env::temp_dir();
+        let up = "../".repeat(temp_dir.components().count() - 1);
+        let path = temp_dir.join(up).join(kept.strip_prefix("/").unwrap());
+        let cmd = GraphicsCommand {
+            payload: path.to_str().unwrap().as_bytes().to_vec(),
+            ..cmd.clone()
+        };
+        assert_eq!(load(&cmd, DEFAULT_QUOTA).unwrap().rgba, [2, 3, 4, 5]);
+        assert!(kept.exists());
+        std::fs::remove_file(&kept).unwrap();
+
+        // Devices and the files of the kernel are never read
+        for path in ["/dev/zero", "/proc/self/environ"] {
+            let cmd = GraphicsCommand {
+                payload: path.as_bytes().to_vec(),
+                ..cmd.clone()
+            };
+            assert_eq!(
+                load(&cmd, DEFAULT_QUOTA),
+                Err("EPERM:file in a forbidden directory")
+            );
+        }
+
+        // Decompression stops at the size of the image
+        let mut compressed = flate2::write::ZlibEncoder::new(vec![], Default::default());
+        std::io::Write::write_all(&mut compressed, &[7; 1024]).unwrap();
+        let cmd = GraphicsCommand {
+            compression: Some(b'z'),
+            payload: compressed.finish().unwrap(),
+            ..rgb(2, 2)
+        };
+        assert_eq!(load(&cmd, DEFAULT_QUOTA).unwrap().rgba.len(), 16);
+
+        // PNG images are no larger than raw ones
+        let mut png = std::io::Cursor::new(vec![]);
+        image::RgbaImage::new(MAX_SIZE as u32 + 1, 1)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let cmd = GraphicsCommand {
+            format: 100,
+            payload: png.into_inner(),
+            ..GraphicsCommand::default()
+        };
+        assert_eq!(load(&cmd, DEFAULT_QUOTA), Err("EBADPNG:invalid PNG data"));
+    }
+
+    #[test]
+    fn test_diacritics() {
+        assert_eq!(diacritic_index('\u{305}'), Some(0));
+        assert_eq!(diacritic_index('\u{30d}'), Some(1));
+        assert_eq!(diacritic_index('a'), None);
+    }
+}
@@ -1,39 +1,202 @@
-use crate::color::Color;
+use crate::{
+    color::{Color, Palette, PaletteColor, TermColor},
+    graphics::PLACEHOLDER,
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// Maps the sub-parameter of SGR 4 (`CSI 4:n m`)
+    pub fn from_param(param: usize) -> Option<Self> {
+        let style = match param {
+            0 => Self::None,
+            1 => Self::Single,
+            2 => Self::Double,
+            3 => Self::Curly,
+            4 => Self::Dotted,
+            5 => Self::Dashed,
+            _ => return None,
+        };
+
+        Some(style)
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Style {
-    pub fg: Color,
-    pub bg: Color,
+    pub fg: TermColor,
+    pub bg: TermColor,
     pub bold: bool,
-    pub underline: bool,
+    pub dim: bool,
     pub italics: bool,
+    pub underline: UnderlineStyle,
+    /// Color of the underline, the foreground color is used if not set
+    pub underline_color: Option<TermColor>,
+    pub blink: bool,
+    pub reverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+    pub overline: bool,
+}
+
+impl Style {
+    /// Returns the foreground and background colors to draw with, taking
+    /// the palette, reverse video, dim and hidden into account.
+    /// `reverse_video` is the screen wide reverse video mode (DECSCNM).
+    pub fn colors(&self, palette: &Palette, reverse_video: bool) -> (Color, Color) {
+        let fg = palette.resolve(self.fg, PaletteColor::Foreground);
+        let bg = palette.resolve(self.bg, PaletteColor::Background);
+        let (mut fg, bg) = if self.reverse != reverse_video {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        };
+
+        if self.dim {
+            fg = fg.dim();
+        }
+
+        if self.hidden {
+            fg = bg;
+        }
+
+        (fg, bg)
+    }
+
+    /// Returns the SGR parameters that select this style, starting with a
+    /// reset
+    pub fn sgr(&self) -> String {
+        let mut params = vec![String::from("0")];
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italics, "3"),
+            (self.blink, "5"),
+            (self.reverse, "7"),
+            (self.hidden, "8"),
+            (self.strikethrough, "9"),
+            (self.overline, "53"),
+        ];
+        params.extend(flags.iter().filter(|f| f.0).map(|f| f.1.to_string()));
+
+        if self.underline != UnderlineStyle::None {
+            params.push(format!("4:{}", self.underline as usize));
+        }
+
+        params.extend(color_sgr(self.fg, 30, 90, 38));
+        params.extend(color_sgr(self.bg, 40, 100, 48));
+        if let Some(color) = self.underline_color {
+            params.extend(color_sgr(color, 58, 58, 58));
+        }
+
+        params.join(";")
+    }
+}
+
+/// Returns the SGR parameter of a color, the first 16 indexed colors use
+/// `base` and `bright` unless they are the same as `extended`
+fn color_sgr(color: TermColor, base: u8, bright: u8, extended: u8) -> Option<String> {
+    let param = match color {
+        TermColor::Default => return None,
+        TermColor::Indexed(i) if i < 8 && base != extended => format!("{}", base + i),
+        TermColor::Indexed(i) if i < 16 && bright != extended => format!("{}", bright + i - 8),
+        TermColor::Indexed(i) => format!("{extended}:5:{i}"),
+        TermColor::Rgb(Color([r, g, b])) => format!("{extended}:2::{r}:{g}:{b}"),
+    };
+
+    Some(param)
 }
 
 impl Default for Style {
     fn default() -> Self {
         Self {
-            fg: Color::WHITE,
-            bg: Color::BLACK,
+            fg: TermColor::Default,
+            bg: TermColor::Default,
             bold: false,
-            underline: false,
+            dim: false,
             italics: false,
+            underline: UnderlineStyle::None,
+            underline_color: None,
+            blink: false,
+            reverse: false,
+            hidden: false,
+            strikethrough: false,
+            overline: false,
         }
     }
 }
 
+/// How many columns the character of a cell takes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CellWidth {
+    #[default]
+    Normal,
+    /// First cell of a double width character
+    Wide,
+    /// Cell covered by the double width character on its left
+    Spacer,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Cell {
     pub c: Option<char>,
+    /// Index in its grid of the grapheme cluster of the cell when it has more
+    /// than one codepoint, `c` is then its first codepoint
+    pub grapheme: Option<u32>,
     pub style: Style,
+    /// A horizontal tab started in this empty cell, it is copied as a tab
+    pub tab: bool,
+    /// Index of the hyperlink of the cell in its grid
+    pub hyperlink: Option<u32>,
+    /// Piece of an image covering the cell
+    pub image: Option<ImageCell>,
+    pub width: CellWidth,
+}
+
+/// The cell at column `x` and row `y` of an image stored in the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageCell {
+    pub id: u32,
+    pub x: u16,
+    pub y: u16,
+}
+
+/// An OSC 8 hyperlink
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hyperlink {
+    /// The `id=` parameter, cells with the same id and URI are the same link
+    pub id: Option<String>,
+    pub uri: String,
 }
 
 impl Cell {
     pub fn new() -> Self {
         Self {
             c: None,
+            grapheme: None,
             style: Default::default(),
+            tab: false,
+            hyperlink: None,
+            image: None,
+            width: CellWidth::Normal,
         }
     }
+
+    /// Whether the GUIs draw the text of the cell on its own rather than with
+    /// the text of its row: double width characters and grapheme clusters of
+    /// more than one codepoint, except image placeholders
+    pub fn is_drawn_alone(&self) -> bool {
+        self.c != Some(PLACEHOLDER) && (self.width == CellWidth::Wide || self.grapheme.is_some())
+    }
 }
 
 impl Default for Cell {
@@ -0,0 +1,77 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// Size under which a table is never cleaned up
+const MIN_LIMIT: usize = 256;
+
+/// Values shared by cells, stored once per grid and referenced by id. The
+/// values no cell uses anymore are dropped when the table grows too large.
+#[derive(Debug)]
+pub struct Interner<T> {
+    values: HashMap<u32, T>,
+    ids: HashMap<T, u32>,
+    next_id: u32,
+    /// Size at which the unused values are dropped, twice what was left the
+    /// last time
+    limit: usize,
+}
+
+impl<T: Clone + Eq + Hash> Interner<T> {
+    pub fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            limit: MIN_LIMIT,
+        }
+    }
+
+    /// Returns the id of `value`, adding it if needed. `used` returns the ids
+    /// still referenced by cells, called before adding to a full table.
+    pub fn intern(&mut self, value: T, used: impl FnOnce() -> HashSet<u32>) -> u32 {
+        if let Some(id) = self.ids.get(&value) {
+            return *id;
+        }
+
+        if self.values.len() >= self.limit {
+            let used = used();
+            self.values.retain(|id, _| used.contains(id));
+            self.ids.retain(|_, id| used.contains(id));
+            self.limit = (2 * self.values.len()).max(MIN_LIMIT);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.values.insert(id, value.clone());
+        self.ids.insert(value, id);
+        id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        self.values.get(&id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let first = interner.intern(0, HashSet::new);
+        assert_eq!(interner.intern(0, HashSet::new), first);
+
+        // Only the used values survive a full table
+        for i in 1..MIN_LIMIT {
+            interner.intern(i, HashSet::new);
+        }
+        let id = interner.intern(MIN_LIMIT, || HashSet::from([first]));
+        assert_eq!(interner.get(first), Some(&0));
+        assert_eq!(interner.get(first + 1), None);
+        assert_eq!(interner.get(id), Some(&MIN_LIMIT));
+        assert_eq!(interner.intern(MIN_LIMIT, HashSet::new), id);
+    }
+}
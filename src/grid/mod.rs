@@ -1,13 +1,22 @@
 use std::{
+    borrow::Cow,
     cmp::min,
+    collections::{HashMap, HashSet},
     fmt::Display,
-    ops::{Index, IndexMut},
+    ops::{Index, IndexMut, RangeInclusive},
 };
 
-use self::cell::{Cell, Style};
-use self::row::Row;
+use self::cell::ImageCell;
+use self::cell::{Cell, CellWidth, Hyperlink, Style};
+use self::interner::Interner;
+use self::row::{LineSize, Row, Zone};
+use crate::{
+    graphics::PLACEHOLDER,
+    image::{self, Placement},
+};
 
 pub mod cell;
+mod interner;
 pub mod row;
 
 #[derive(Debug)]
@@ -17,8 +26,22 @@ pub struct Grid {
     scrolldown: Vec<Row>,
     index: usize,
     columns: usize,
+    /// False for the alternate screen, rows scrolled off the top are dropped
+    has_scrollback: bool,
+    /// Hyperlinks of the cells, by `Cell::hyperlink`
+    hyperlinks: Interner<Hyperlink>,
+    /// Grapheme clusters of more than one codepoint, by `Cell::grapheme`
+    graphemes: Interner<String>,
+    /// Images of the cells, by `ImageCell::id`
+    images: HashMap<u32, Placement>,
+    /// Number of images at which those without cells are dropped, twice what
+    /// was left the last time
+    image_limit: usize,
 }
 
+/// Number of images under which they are never dropped
+const MIN_IMAGE_LIMIT: usize = 16;
+
 impl Grid {
     pub fn new(columns: usize, lines: usize) -> Self {
         let mut rows = Vec::with_capacity(lines);
@@ -30,6 +53,223 @@ impl Grid {
             scrollback: vec![],
             scrolldown: vec![],
             columns,
+            has_scrollback: true,
+            hyperlinks: Interner::new(),
+            graphemes: Interner::new(),
+            images: HashMap::new(),
+            image_limit: MIN_IMAGE_LIMIT,
+        }
+    }
+
+    /// Creates a grid for the alternate screen, it has no scrollback
+    pub fn new_alternate(columns: usize, lines: usize) -> Self {
+        Self {
+            has_scrollback: false,
+            ..Self::new(columns, lines)
+        }
+    }
+
+    /// Resets all the cells of the visible screen
+    pub fn clear(&mut self) {
+        for row in &mut self.rows {
+            *row = Row::new(self.columns);
+        }
+    }
+
+    /// Returns the id to store in the cells of `link`. The links no cell
+    /// shows anymore may be dropped.
+    pub fn intern_hyperlink(&mut self, link: Hyperlink) -> u32 {
+        let rows = self
+            .scrollback
+            .iter()
+            .chain(&self.rows)
+            .chain(&self.scrolldown);
+        self.hyperlinks.intern(link, || {
+            rows.flat_map(|row| row.inner.iter().filter_map(|cell| cell.hyperlink))
+                .collect()
+        })
+    }
+
+    /// Returns a link shown by a cell, or the last one interned
+    pub fn hyperlink(&self, id: u32) -> &Hyperlink {
+        self.hyperlinks
+            .get(id)
+            .expect("hyperlinks of the cells are kept")
+    }
+
+    /// Returns the id to store in the cells showing `grapheme`. The clusters
+    /// no cell shows anymore may be dropped.
+    pub fn intern_grapheme(&mut self, grapheme: String) -> u32 {
+        let rows = self
+            .scrollback
+            .iter()
+            .chain(&self.rows)
+            .chain(&self.scrolldown);
+        self.graphemes.intern(grapheme, || {
+            rows.flat_map(|row| row.inner.iter().filter_map(|cell| cell.grapheme))
+                .collect()
+        })
+    }
+
+    /// Returns the grapheme cluster shown by a cell, `None` if it is empty
+    pub fn cell_text(&self, cell: &Cell) -> Option<Cow<'_, str>> {
+        match (cell.c, cell.grapheme) {
+            (None, _) => None,
+            (Some(c), Some(id)) => match self.graphemes.get(id) {
+                Some(grapheme) => Some(Cow::Borrowed(grapheme)),
+                None => Some(Cow::Owned(c.to_string())),
+            },
+            (Some(c), None) => Some(Cow::Owned(c.to_string())),
+        }
+    }
+
+    /// Stores an image and returns the id to store in its cells. The images no
+    /// cell shows anymore are dropped when there are too many.
+    pub fn add_image(&mut self, placement: Placement) -> u32 {
+        if self.images.len() >= self.image_limit {
+            let used: HashSet<u32> = self
+                .history()
+                .flat_map(|row| row.inner.iter().filter_map(|cell| cell.image))
+                .map(|image| image.id)
+                .collect();
+            self.images.retain(|id, _| used.contains(id));
+            self.image_limit = (2 * self.images.len()).max(MIN_IMAGE_LIMIT);
+        }
+
+        let id = image::next_id();
+        self.images.insert(id, placement);
+        id
+    }
+
+    pub fn image(&self, id: u32) -> Option<&Placement> {
+        self.images.get(&id)
+    }
+
+    /// Returns the ids of the stored images matching `f`
+    pub fn image_ids(&self, f: impl Fn(&Placement) -> bool) -> HashSet<u32> {
+        self.images
+            .iter()
+            .filter(|(_, placement)| f(placement))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Returns the ids of the images on the visible screen with a cell for
+    /// which `f` returns true, it gets the row and column of the cell
+    pub fn screen_image_ids(&self, f: impl Fn(usize, usize, &Placement) -> bool) -> HashSet<u32> {
+        let mut ids = HashSet::new();
+
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, cell) in row.inner.iter().enumerate() {
+                let Some(ImageCell { id, .. }) = cell.image else {
+                    continue;
+                };
+                if self.images.get(&id).is_some_and(|p| f(y, x, p)) {
+                    ids.insert(id);
+                }
+            }
+        }
+
+        ids
+    }
+
+    /// Removes images and all their cells, including the ones in the
+    /// scrollback
+    pub fn remove_images(&mut self, ids: &HashSet<u32>) {
+        let rows = self
+            .scrollback
+            .iter_mut()
+            .chain(self.rows.iter_mut())
+            .chain(self.scrolldown.iter_mut());
+        for row in rows {
+            for cell in &mut row.inner {
+                if cell.image.is_some_and(|image| ids.contains(&image.id)) {
+                    cell.image = None;
+                }
+            }
+        }
+
+        self.images.retain(|id, _| !ids.contains(id));
+    }
+
+    /// Returns all the rows from the oldest one, including the scrollback and
+    /// the rows below the viewport
+    fn history(&self) -> impl DoubleEndedIterator<Item = &Row> {
+        self.scrollback
+            .iter()
+            .chain(&self.rows)
+            .chain(self.scrolldown.iter().rev())
+    }
+
+    /// Returns the text of the last command output marked by the shell
+    pub fn last_command_output(&self) -> Option<String> {
+        let mut rows: Vec<_> = self
+            .history()
+            .rev()
+            .skip_while(|row| row.zone != Zone::Output)
+            .take_while(|row| row.zone == Zone::Output)
+            .map(|row| row.text(self))
+            .collect();
+        if rows.is_empty() {
+            return None;
+        }
+
+        rows.reverse();
+        // The row the cursor moved to after the output is empty
+        Some(rows.join("\n").trim_end_matches('\n').to_string())
+    }
+
+    /// Moves the viewport until a prompt is at the top, towards the
+    /// scrollback if `previous`
+    pub fn scroll_to_prompt(&mut self, previous: bool) {
+        loop {
+            let history = if previous {
+                &self.scrollback
+            } else {
+                &self.scrolldown
+            };
+            if history.is_empty() {
+                return;
+            }
+
+            if previous {
+                self.scroll_down(1, false);
+            } else {
+                self.scroll_up(1, false);
+            }
+
+            if self.rows[0].prompt.is_some() {
+                return;
+            }
+        }
+    }
+
+    /// Finds the last prompt before the row `line` of the screen, in the
+    /// screen or the scrollback
+    pub fn last_prompt_mut(&mut self, line: usize) -> Option<&mut row::PromptMark> {
+        self.rows[..=line]
+            .iter_mut()
+            .rev()
+            .chain(self.scrollback.iter_mut().rev())
+            .find_map(|row| row.prompt.as_mut())
+    }
+
+    /// Drops all the rows of the scrollback
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+    }
+
+    /// Moves the visible rows up to the last non empty one into the
+    /// scrollback, leaving blank rows behind
+    pub fn push_to_scrollback(&mut self) {
+        let used = self.rows.iter().rposition(|row| {
+            row.inner
+                .iter()
+                .any(|cell| cell.c.is_some() || cell.image.is_some())
+        });
+
+        if let Some(last) = used {
+            self.scroll_region_up(0..=self.rows.len() - 1, last + 1);
         }
     }
 
@@ -47,7 +287,9 @@ impl Grid {
             for i in 1..len {
                 self.rows.swap(i - 1, i);
             }
-            self.scrollback.push(self.rows[len - 1].clone());
+            if self.has_scrollback {
+                self.scrollback.push(self.rows[len - 1].clone());
+            }
             self.rows[len - 1] = self.scrolldown.pop().unwrap();
         }
     }
@@ -68,6 +310,37 @@ impl Grid {
         }
     }
 
+    /// Scrolls the rows of `region` up by `count`, blank rows come in at the
+    /// bottom. Rows only go to the scrollback when the whole screen scrolls.
+    pub fn scroll_region_up(&mut self, region: RangeInclusive<usize>, count: usize) {
+        let (top, bottom) = (*region.start(), *region.end());
+        let count = count.min(bottom + 1 - top);
+
+        if top == 0 && bottom + 1 == self.rows.len() {
+            for _ in 0..count {
+                self.scroll_up(1, true);
+            }
+            return;
+        }
+
+        self.rows[top..=bottom].rotate_left(count);
+        for row in &mut self.rows[bottom + 1 - count..=bottom] {
+            *row = Row::new(self.columns);
+        }
+    }
+
+    /// Scrolls the rows of `region` down by `count`, blank rows come in at
+    /// the top.
+    pub fn scroll_region_down(&mut self, region: RangeInclusive<usize>, count: usize) {
+        let (top, bottom) = (*region.start(), *region.end());
+        let count = count.min(bottom + 1 - top);
+
+        self.rows[top..=bottom].rotate_right(count);
+        for row in &mut self.rows[top..top + count] {
+            *row = Row::new(self.columns);
+        }
+    }
+
     /// Returns the different style sections to render.
     pub fn sections(&self) -> Sections {
         let mut res = vec![];
@@ -91,10 +364,16 @@ impl Grid {
                     len = 0;
                     current_style = col.style;
                 }
-                if let Some(c) = col.c {
-                    whole_text.push(c);
-                    len += c.len_utf8();
-                    total_len += c.len_utf8();
+                // Placeholders of kitty images are covered by the image and
+                // double width characters and clusters are drawn on their
+                // own, so that each cell is one character of the text
+                let text = self
+                    .cell_text(col)
+                    .filter(|_| col.c != Some(PLACEHOLDER) && !col.is_drawn_alone());
+                if let Some(text) = text {
+                    whole_text.extend(text.chars());
+                    len += text.len();
+                    total_len += text.len();
                 } else {
                     whole_text.push(' ');
                     len += 1;
@@ -133,38 +412,88 @@ impl Grid {
         let mut current_row = Row::new(new_columns);
         let mut current_column_index = 0;
 
-        // Flatten all cells from existing rows into a single vector
-        let all_cells: Vec<Cell> = self.rows.iter().flat_map(|r| r.inner.clone()).collect();
-
         let mut advance = false;
         // Wrap cells into new rows based on the new column width
-        for cell in all_cells {
-            if advance && cell.c.is_none() {
-                continue;
-            } else {
-                advance = false;
-            }
-
-            // If we arrived at the end of the row
-            if current_column_index == new_columns {
-                new_rows.push(current_row);
+        'rows: for row in &self.rows {
+            // Double size rows aren't reflowed, their cells are cut or padded
+            if row.size != LineSize::Single {
+                if current_column_index > 0 {
+                    new_rows.push(current_row);
+                }
+                let mut kept = row.clone();
+                kept.inner.resize(new_columns, Cell::default());
+                if kept
+                    .inner
+                    .last()
+                    .is_some_and(|c| c.width == CellWidth::Wide)
+                {
+                    kept.inner[new_columns - 1] = Cell::default();
+                }
+                new_rows.push(kept);
                 current_row = Row::new(new_columns);
                 current_column_index = 0;
+                advance = false;
+                if new_rows.len() >= new_lines {
+                    break;
+                }
+                continue;
             }
 
-            if cell.c.is_some() {
-                current_row[current_column_index] = cell;
-                current_column_index += 1;
-            } else {
-                new_rows.push(current_row);
-                current_row = Row::new(new_columns);
-                current_column_index = 0;
-                advance = true;
+            // The row starting here keeps the shell integration marks
+            if current_column_index == 0 {
+                current_row.zone = row.zone;
+                current_row.prompt = row.prompt;
             }
 
-            // Break out of the loop early if we've filled up the new_lines
-            if new_rows.len() == new_lines {
-                break;
+            for cell in &row.inner {
+                let mut cell = *cell;
+                // Spacers are added back after their double width character
+                if cell.width == CellWidth::Spacer {
+                    continue;
+                }
+                if advance && cell.c.is_none() {
+                    continue;
+                } else {
+                    advance = false;
+                }
+
+                let width = if cell.width == CellWidth::Wide && new_columns >= 2 {
+                    2
+                } else {
+                    cell.width = CellWidth::Normal;
+                    1
+                };
+
+                // If the character doesn't fit in the rest of the row
+                if current_column_index + width > new_columns {
+                    new_rows.push(current_row);
+                    current_row = Row::new(new_columns);
+                    current_row.zone = row.zone;
+                    current_column_index = 0;
+                }
+
+                if cell.c.is_some() {
+                    current_row[current_column_index] = cell;
+                    if width == 2 {
+                        current_row[current_column_index + 1] = Cell {
+                            style: cell.style,
+                            hyperlink: cell.hyperlink,
+                            width: CellWidth::Spacer,
+                            ..Cell::new()
+                        };
+                    }
+                    current_column_index += width;
+                } else {
+                    new_rows.push(current_row);
+                    current_row = Row::new(new_columns);
+                    current_column_index = 0;
+                    advance = true;
+                }
+
+                // Break out of the loop early if we've filled up the new_lines
+                if new_rows.len() == new_lines {
+                    break 'rows;
+                }
             }
         }
 
@@ -192,11 +521,11 @@ impl Grid {
         for row in v {
             write!(f, "|")?;
             for cell in &row.inner {
-                if let Some(c) = cell.c {
-                    if c == '\t' {
+                if let Some(text) = self.cell_text(cell) {
+                    if text == "\t" {
                         write!(f, " ")?;
                     } else {
-                        write!(f, "{}", c)?;
+                        write!(f, "{}", text)?;
                     }
                 }
             }
@@ -272,6 +601,8 @@ impl Display for Grid {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::image::Image;
+    use std::sync::Arc;
 
     #[test]
     fn test_scroll_up() {
@@ -282,6 +613,150 @@ mod test {
         assert!(g[0][0].c == Some('a'));
     }
 
+    #[test]
+    fn test_alternate_has_no_scrollback() {
+        let mut g = Grid::new_alternate(2, 2);
+        g[0][0].c = Some('a');
+        g.scroll_up(1, true);
+        assert!(g.scrollback.is_empty());
+        g.scroll_down(1, false);
+        assert!(g[0][0].c.is_none());
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut g = Grid::new(1, 4);
+        for (i, c) in "abcd".chars().enumerate() {
+            g[i][0].c = Some(c);
+        }
+
+        g.scroll_region_up(1..=2, 1);
+        assert_eq!(g[0][0].c, Some('a'));
+        assert_eq!(g[1][0].c, Some('c'));
+        assert!(g[2][0].c.is_none());
+        assert_eq!(g[3][0].c, Some('d'));
+        assert!(g.scrollback.is_empty());
+
+        g.scroll_region_down(0..=3, 2);
+        assert!(g[1][0].c.is_none());
+        assert_eq!(g[2][0].c, Some('a'));
+        assert_eq!(g[3][0].c, Some('c'));
+    }
+
+    #[test]
+    fn test_push_to_scrollback() {
+        let mut g = Grid::new(2, 3);
+        g[0][0].c = Some('a');
+        g[1][1].c = Some('b');
+
+        g.push_to_scrollback();
+        assert_eq!(g.scrollback.len(), 2);
+        assert!(g.rows.iter().all(|r| r.inner.iter().all(|c| c.c.is_none())));
+
+        g.clear_scrollback();
+        assert!(g.scrollback.is_empty());
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let mut g = Grid::new(2, 2);
+        let link = |uri: &str| Hyperlink {
+            id: None,
+            uri: String::from(uri),
+        };
+
+        let a = g.intern_hyperlink(link("a"));
+        let b = g.intern_hyperlink(link("b"));
+        assert_ne!(a, b);
+        assert_eq!(g.intern_hyperlink(link("a")), a);
+        assert_eq!(g.hyperlink(b).uri, "b");
+
+        // Links scroll into the scrollback with their cells
+        g[0][0].hyperlink = Some(a);
+        g.scroll_up(1, true);
+        assert_eq!(g.scrollback[0][0].hyperlink, Some(a));
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut g = Grid::new(2, 2);
+        let used = g.intern_grapheme(String::from("e\u{301}"));
+        g[0][0].c = Some('e');
+        g[0][0].grapheme = Some(used);
+
+        // The clusters of the cells survive the table filling up, the others
+        // are dropped
+        let unused = g.intern_grapheme(String::from("a\u{301}"));
+        for i in 0..1000 {
+            g.intern_grapheme(format!("{i}\u{301}"));
+        }
+        assert_eq!(g.cell_text(&g[0][0]).unwrap(), "e\u{301}");
+        let cell = Cell {
+            c: Some('a'),
+            grapheme: Some(unused),
+            ..Cell::new()
+        };
+        assert_eq!(g.cell_text(&cell).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_images() {
+        let mut g = Grid::new(2, 2);
+        let placement = || Placement {
+            image: Arc::new(Image {
+                width: 1,
+                height: 1,
+                rgba: vec![0; 4],
+            }),
+            cell_size: (1, 1),
+            z: 0,
+            kitty: None,
+        };
+
+        let a = g.add_image(placement());
+        g[0][0].image = Some(ImageCell { id: a, x: 0, y: 0 });
+        g.scroll_up(1, true);
+        let b = g.add_image(placement());
+        assert!(g.image(a).is_some());
+
+        // Images without cells are dropped once there are too many
+        g.scrollback[0][0].image = None;
+        for _ in 0..MIN_IMAGE_LIMIT {
+            g.add_image(placement());
+        }
+        assert!(g.image(a).is_none());
+        assert!(g.image(b).is_none());
+
+        let c = g.add_image(placement());
+        g[1][1].image = Some(ImageCell { id: c, x: 0, y: 0 });
+        g.scroll_up(1, true);
+        assert_eq!(g.screen_image_ids(|_, _, _| true), HashSet::from([c]));
+        g.remove_images(&HashSet::from([c]));
+        assert!(g.image(c).is_none());
+        assert!(g[0][1].image.is_none());
+    }
+
+    #[test]
+    fn test_semantic_zones() {
+        let mut g = Grid::new(3, 3);
+        g[0].prompt = Some(row::PromptMark::default());
+        g[0].zone = Zone::Prompt;
+        for (y, c) in [(1, 'a'), (2, 'b')] {
+            g[y].zone = Zone::Output;
+            g[y][0].c = Some(c);
+        }
+        assert_eq!(g.last_command_output().as_deref(), Some("a\nb"));
+
+        g.scroll_up(3, true);
+        g.scroll_to_prompt(true);
+        assert!(g[0].prompt.is_some());
+        g.scroll_to_prompt(false);
+        assert!(g.scrolldown.is_empty());
+
+        g.last_prompt_mut(2).unwrap().exit_status = Some(1);
+        assert_eq!(g.scrollback[0].prompt.unwrap().exit_status, Some(1));
+    }
+
     #[test]
     fn test_resize() {
         let mut g = Grid::new(2, 2);
@@ -294,6 +769,32 @@ mod test {
         assert!(g[1][0].c == Some('b'));
     }
 
+    #[test]
+    fn test_resize_keeps_row_marks() {
+        let mut g = Grid::new(4, 3);
+        g[0].prompt = Some(row::PromptMark::default());
+        g[0].zone = Zone::Prompt;
+        for (x, c) in "$ ls".chars().enumerate() {
+            g[0][x].c = Some(c);
+        }
+        g[1].zone = Zone::Output;
+        g[1][0].c = Some('a');
+        g[2].size = LineSize::DoubleWidth;
+        for (x, c) in "big".chars().enumerate() {
+            g[2][x].c = Some(c);
+        }
+
+        g.resize(2, 4);
+
+        // The prompt wraps on two rows, only the first is marked
+        assert!(g[0].prompt.is_some() && g[0].zone == Zone::Prompt);
+        assert!(g[1].prompt.is_none() && g[1].zone == Zone::Prompt);
+        assert_eq!(g[2].zone, Zone::Output);
+        // Double width rows are cut instead of reflowed
+        assert_eq!(g[3].size, LineSize::DoubleWidth);
+        assert_eq!(g[3].text(&g), "bi");
+    }
+
     #[test]
     fn test_resize_with_empty() {
         let mut g = Grid::new(2, 2);
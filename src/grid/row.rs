@@ -3,11 +3,52 @@ use std::{
     slice::Iter,
 };
 
-use crate::grid::cell::Cell;
+use crate::grid::{
+    cell::{Cell, CellWidth},
+    Grid,
+};
+
+/// Semantic zone of a row, marked by the shell with OSC 133
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    #[default]
+    Unknown,
+    Prompt,
+    Command,
+    Output,
+}
+
+/// Marks the row where a prompt starts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PromptMark {
+    /// Exit status of the command run from this prompt, once it finished
+    pub exit_status: Option<i32>,
+}
+
+/// Size of the characters of a row, set with DECSWL, DECDWL and DECDHL
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineSize {
+    #[default]
+    Single,
+    DoubleWidth,
+    /// Top half of double height characters, which are also double width
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+impl LineSize {
+    /// Whether the row only uses the left half of its cells
+    pub fn is_double_width(self) -> bool {
+        self != Self::Single
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Row {
     pub inner: Vec<Cell>,
+    pub zone: Zone,
+    pub prompt: Option<PromptMark>,
+    pub size: LineSize,
 }
 
 impl Row {
@@ -16,12 +57,64 @@ impl Row {
 
         inner.resize(columns, Cell::default());
 
-        Self { inner }
+        Self {
+            inner,
+            zone: Zone::Unknown,
+            prompt: None,
+            size: LineSize::Single,
+        }
+    }
+
+    /// Returns the text of the row without the trailing blanks
+    pub fn text(&self, grid: &Grid) -> String {
+        let mut text = String::new();
+        let mut in_tab = false;
+
+        for cell in &self.inner {
+            if cell.width == CellWidth::Spacer {
+                continue;
+            }
+            if cell.tab {
+                text.push('\t');
+                in_tab = true;
+            } else if let Some(grapheme) = grid.cell_text(cell) {
+                text.push_str(&grapheme);
+                in_tab = false;
+            } else if !in_tab {
+                // The cells skipped by a tab are part of it
+                text.push(' ');
+            }
+        }
+
+        text.trim_end().to_string()
+    }
+
+    /// Blanks the halves of double width characters that lost their other
+    /// half, after characters were erased or shifted
+    pub fn fix_wide_chars(&mut self) {
+        for x in 0..self.inner.len() {
+            let orphan = match self.inner[x].width {
+                CellWidth::Normal => false,
+                CellWidth::Wide => self
+                    .inner
+                    .get(x + 1)
+                    .is_none_or(|next| next.width != CellWidth::Spacer),
+                CellWidth::Spacer => x == 0 || self.inner[x - 1].width != CellWidth::Wide,
+            };
+            if orphan {
+                self.inner[x].c = None;
+                self.inner[x].grapheme = None;
+                self.inner[x].width = CellWidth::Normal;
+            }
+        }
     }
 
     pub fn reset(&mut self) {
         for cell in &mut self.inner {
             cell.c = None;
+            cell.grapheme = None;
+            cell.image = None;
+            cell.width = CellWidth::Normal;
         }
     }
 }
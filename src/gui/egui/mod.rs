@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     ops::DerefMut,
     os::fd::{AsRawFd, OwnedFd},
     sync::{Arc, Mutex},
@@ -6,12 +7,24 @@ use std::{
 };
 
 use crate::{
-    ansi::Ansi, font, gui::Gui, terminal_gui_input::TerminalGuiInput, terminal_io::TerminalIO,
+    ansi::Ansi,
+    clipboard::{Clipboard, ClipboardEvent},
+    color::PaletteColor,
+    font,
+    grid::{
+        cell::{CellWidth, Style, UnderlineStyle},
+        row::LineSize,
+    },
+    gui::{Gui, ImagePiece, UnderlineRun},
+    mouse::{MouseAction, MouseButton, MouseEvent},
+    terminal_gui_input::TerminalGuiInput,
+    terminal_io::TerminalIO,
     turm::Turm,
 };
 
 use egui::{
-    text::LayoutSection, Color32, FontFamily, FontId, Frame, Margin, Rect, Stroke, ViewportCommand,
+    pos2, text::LayoutSection, Color32, ColorImage, FontFamily, FontId, Frame, Margin, Mesh,
+    Painter, Pos2, Rect, Shape, Stroke, TextureHandle, TextureId, TextureOptions, ViewportCommand,
 };
 
 pub struct EguiImpl {
@@ -21,6 +34,10 @@ pub struct EguiImpl {
     h: usize,
     fd: OwnedFd,
     font_size: f32,
+    /// Textures of the images on the screen, by image id
+    textures: HashMap<u32, TextureHandle>,
+    /// Cell under the pointer, motion is only reported when it changes
+    mouse_cell: Option<(usize, usize)>,
 }
 
 impl Gui for EguiImpl {
@@ -39,6 +56,8 @@ impl Gui for EguiImpl {
             w: cols,
             h: rows,
             font_size: 12.0,
+            textures: HashMap::new(),
+            mouse_cell: None,
         }
     }
 
@@ -49,11 +68,12 @@ impl Gui for EguiImpl {
 
         let turm = self.turm.clone();
         let fd = self.fd.try_clone().unwrap();
+        let input = self.terminal_gui_input.clone();
 
         // Thread that reads output from the shell and sends it to the gui
         thread::spawn(move || {
             let ansi = Ansi::new();
-            let mut terminal_io = TerminalIO::new(ansi, fd, turm);
+            let mut terminal_io = TerminalIO::new(ansi, fd, turm, input);
             terminal_io.start_io(|| {
                 rs.request_repaint();
             });
@@ -70,6 +90,160 @@ impl Gui for EguiImpl {
 }
 
 impl EguiImpl {
+    /// Time in seconds during which blinking text is shown or hidden
+    const BLINK_INTERVAL: f64 = 0.5;
+
+    /// Draws a character in `cell`, scaled for the size of its row. egui
+    /// can't scale text on one axis only, so the glyphs are laid out and
+    /// their quads stretched.
+    fn draw_scaled_char(
+        painter: &Painter,
+        text: &str,
+        font_id: FontId,
+        color: Color32,
+        cell: Rect,
+        size: LineSize,
+    ) {
+        let (scale_x, scale_y, shift) = crate::gui::line_scale(size);
+        let top = pos2(cell.left(), cell.top() - shift * cell.height());
+
+        let galley = painter.layout_no_wrap(text.to_string(), font_id, color);
+        let [texture_width, texture_height] = painter.fonts(|f| f.font_image_size());
+        let texel = |[x, y]: [u16; 2]| {
+            pos2(
+                x as f32 / texture_width as f32,
+                y as f32 / texture_height as f32,
+            )
+        };
+
+        let mut mesh = Mesh::with_texture(TextureId::default());
+        for glyph in galley.rows.iter().flat_map(|row| &row.glyphs) {
+            let uv = glyph.uv_rect;
+            if uv.is_nothing() {
+                continue;
+            }
+            let min = glyph.pos + uv.offset;
+            let rect = Rect::from_min_size(
+                pos2(top.x + min.x * scale_x, top.y + min.y * scale_y),
+                egui::vec2(uv.size.x * scale_x, uv.size.y * scale_y),
+            );
+            mesh.add_rect_with_uv(
+                rect,
+                Rect::from_min_max(texel(uv.min), texel(uv.max)),
+                color,
+            );
+        }
+
+        // Only the top or bottom half of double height characters shows
+        let clip = painter.clip_rect();
+        painter
+            .with_clip_rect(Rect::from_x_y_ranges(clip.x_range(), cell.y_range()))
+            .add(Shape::mesh(mesh));
+    }
+
+    /// Draws the piece of an image shown by a cell, `origin` is the top left
+    /// corner of the grid
+    fn draw_image_piece(
+        textures: &mut HashMap<u32, TextureHandle>,
+        ctx: &egui::Context,
+        painter: &Painter,
+        piece: &ImagePiece,
+        origin: Pos2,
+        width: f32,
+        height: f32,
+    ) {
+        let image = &piece.placement.image;
+        let texture = textures.entry(piece.id).or_insert_with(|| {
+            let pixels =
+                ColorImage::from_rgba_unmultiplied([image.width, image.height], &image.rgba);
+            ctx.load_texture(
+                format!("image-{}", piece.id),
+                pixels,
+                TextureOptions::LINEAR,
+            )
+        });
+
+        let (cell_width, cell_height) = piece.placement.cell_size;
+        let min = pos2(
+            origin.x + piece.column as f32 * width,
+            origin.y + piece.row as f32 * height,
+        );
+        let size = egui::vec2(
+            piece.width as f32 * width / cell_width as f32,
+            piece.height as f32 * height / cell_height as f32,
+        );
+        let uv = Rect::from_min_size(
+            pos2(
+                piece.x as f32 / image.width as f32,
+                piece.y as f32 / image.height as f32,
+            ),
+            egui::vec2(
+                piece.width as f32 / image.width as f32,
+                piece.height as f32 / image.height as f32,
+            ),
+        );
+        painter.image(
+            texture.id(),
+            Rect::from_min_size(min, size),
+            uv,
+            Color32::WHITE,
+        );
+    }
+
+    /// Draws an underline at the bottom of its cells, `origin` is the top left
+    /// corner of the grid
+    fn draw_underline(
+        painter: &Painter,
+        run: &UnderlineRun,
+        origin: Pos2,
+        width: f32,
+        height: f32,
+    ) {
+        let left = origin.x + run.start as f32 * width;
+        let right = origin.x + run.end as f32 * width;
+        let y = origin.y + (run.row + 1) as f32 * height - 1.5;
+        let stroke = Stroke::new(1.0, run.color);
+
+        match run.style {
+            UnderlineStyle::None => {}
+            UnderlineStyle::Single => {
+                painter.hline(left..=right, y, stroke);
+            }
+            UnderlineStyle::Double => {
+                painter.hline(left..=right, y, stroke);
+                painter.hline(left..=right, y - 2.0, stroke);
+            }
+            UnderlineStyle::Curly => {
+                // One wave per cell
+                let steps = (run.end - run.start) * 8;
+                let points = (0..=steps)
+                    .map(|i| {
+                        let x = left + i as f32 * width / 8.0;
+                        let phase = (x - left) / width * std::f32::consts::TAU;
+                        pos2(x, y - 1.5 * phase.sin())
+                    })
+                    .collect();
+                painter.add(Shape::line(points, stroke));
+            }
+            UnderlineStyle::Dotted => {
+                painter.add(Shape::dotted_line(
+                    &[pos2(left, y), pos2(right, y)],
+                    run.color,
+                    3.0,
+                    0.5,
+                ));
+            }
+            UnderlineStyle::Dashed => {
+                painter.add(Shape::dashed_line(
+                    &[pos2(left, y), pos2(right, y)],
+                    stroke,
+                    4.0,
+                    2.0,
+                ));
+            }
+        }
+    }
+
     fn with_creation_context(self, cc: &eframe::CreationContext<'_>) -> Self {
         cc.egui_ctx.set_fonts(font::load());
         self
@@ -138,27 +312,37 @@ impl eframe::App for EguiImpl {
 
             let mut turm1 = self.turm.lock().unwrap();
             let turm = turm1.deref_mut();
+            turm.cell_size = (width as usize, height as usize);
 
-            if turm.title.is_empty() {
-                ctx.send_viewport_cmd(ViewportCommand::Title(String::from("💩 Turm 💩")));
-            } else {
-                ctx.send_viewport_cmd(ViewportCommand::Title(
-                    "💩 ".to_owned() + &turm.title.clone() + " 💩",
-                ));
-            }
+            ctx.send_viewport_cmd(ViewportCommand::Title(crate::gui::window_title(turm)));
 
             if w != self.w || h != self.h {
-                turm.grid.resize(w, h);
-                turm.columns = w;
-                turm.lines = h;
+                turm.resize(w, h);
                 self.w = w;
                 self.h = h;
 
                 crate::gui::resize(self.fd.as_raw_fd(), self.w, self.h, self.font_size, width);
             }
 
+            // egui can neither read the clipboard nor use the primary
+            // selection
+            for event in turm.take_clipboard_events() {
+                if let ClipboardEvent::Store(Clipboard::Clipboard, text) = event {
+                    ctx.copy_text(text);
+                }
+            }
+
+            // egui can't send notifications itself
+            let notifications = turm.take_notifications();
+            if !(turm.notify_only_unfocused && ctx.input(|i| i.focused)) {
+                for notification in &notifications {
+                    crate::gui::notify_send(notification);
+                }
+            }
+
             ui.input(|input_state| {
-                self.terminal_gui_input.write_input_to_terminal(input_state);
+                self.terminal_gui_input
+                    .write_input_to_terminal(input_state, &turm.modes);
             });
 
             let font_id = FontId {
@@ -170,7 +354,42 @@ impl eframe::App for EguiImpl {
                 family: FontFamily::Monospace,
             };
 
+            let blink_on =
+                ctx.input(|i| i.time) % (2.0 * Self::BLINK_INTERVAL) < Self::BLINK_INTERVAL;
             let sections = turm.grid.sections();
+            if sections.sections.iter().any(|s| s.style.blink) {
+                ctx.request_repaint_after(std::time::Duration::from_secs_f64(Self::BLINK_INTERVAL));
+            }
+
+            // Fill the background, so that images with a negative z-index
+            // can be drawn below the text
+            let origin = ui.cursor().min;
+            let (_, background) = Style::default().colors(&turm.palette, turm.modes.reverse_video);
+            ui.painter().rect_filled(
+                Rect::from_min_size(
+                    origin,
+                    egui::vec2(turm.columns as f32 * width, turm.lines as f32 * height),
+                ),
+                0.0,
+                background,
+            );
+
+            // Each cell shows its piece of an image
+            let pieces = crate::gui::image_pieces(turm);
+            self.textures
+                .retain(|id, _| pieces.iter().any(|p| p.id == *id));
+            for piece in pieces.iter().filter(|p| p.placement.z < 0) {
+                Self::draw_image_piece(
+                    &mut self.textures,
+                    ctx,
+                    ui.painter(),
+                    piece,
+                    origin,
+                    width,
+                    height,
+                );
+            }
+
             let job = egui::text::LayoutJob {
                 text: sections.text,
                 sections: sections
@@ -183,16 +402,35 @@ impl eframe::App for EguiImpl {
                             font_id.clone()
                         };
 
-                        let underline = Stroke {
-                            color: section.style.fg.into(),
-                            width: if section.style.underline { 4.0 } else { 0.0 },
+                        // Blinking text is hidden during the off phase
+                        let (mut fg, bg) = section
+                            .style
+                            .colors(&turm.palette, turm.modes.reverse_video);
+                        if section.style.blink && !blink_on {
+                            fg = bg;
+                        }
+
+                        let strikethrough = Stroke {
+                            color: fg.into(),
+                            width: if section.style.strikethrough {
+                                1.0
+                            } else {
+                                0.0
+                            },
+                        };
+
+                        // The background was already filled
+                        let background = if bg == background {
+                            Color32::TRANSPARENT
+                        } else {
+                            bg.into()
                         };
 
                         let tf = egui::text::TextFormat {
                             font_id: fid,
-                            color: section.style.fg.into(),
-                            background: section.style.bg.into(),
-                            underline,
+                            color: fg.into(),
+                            background,
+                            strikethrough,
                             italics: section.style.italics,
                             line_height: Some(16.0),
                             ..Default::default()
@@ -211,27 +449,248 @@ impl eframe::App for EguiImpl {
 
             let res = ui.label(job);
 
-            if turm.show_cursor {
+            for run in crate::gui::underline_runs(turm) {
+                Self::draw_underline(ui.painter(), &run, res.rect.left_top(), width, height);
+            }
+
+            // egui has no overline, draw it on top of the text
+            for y in 0..turm.lines {
+                for x in 0..turm.columns {
+                    let style = turm.grid[y][x].style;
+                    if !style.overline {
+                        continue;
+                    }
+                    let left = x as f32 * width + res.rect.left();
+                    let top = y as f32 * height + res.rect.top();
+                    ui.painter().hline(
+                        left..=left + width,
+                        top,
+                        Stroke::new(1.0, style.colors(&turm.palette, turm.modes.reverse_video).0),
+                    );
+                }
+            }
+
+            for (row, column, cell, text) in crate::gui::lone_cells(turm) {
+                let (mut fg, bg) = cell.style.colors(&turm.palette, turm.modes.reverse_video);
+                if cell.style.blink && !blink_on {
+                    fg = bg;
+                }
+                let font_id = if cell.style.bold {
+                    bold_font_id.clone()
+                } else {
+                    font_id.clone()
+                };
+                ui.painter().text(
+                    pos2(
+                        res.rect.left() + column as f32 * width,
+                        res.rect.top() + row as f32 * height,
+                    ),
+                    egui::Align2::LEFT_TOP,
+                    text,
+                    font_id,
+                    fg.into(),
+                );
+            }
+
+            // Double width and double height rows are drawn again over their
+            // text, scaled
+            for (row, size) in crate::gui::scaled_rows(turm) {
+                let top = res.rect.top() + row as f32 * height;
+                ui.painter().rect_filled(
+                    Rect::from_min_size(
+                        pos2(res.rect.left(), top),
+                        egui::vec2(turm.columns as f32 * width, height),
+                    ),
+                    0.0,
+                    background,
+                );
+
+                for column in 0..turm.columns / 2 {
+                    let cell = turm.grid[row][column];
+                    let (fg, bg) = cell.style.colors(&turm.palette, turm.modes.reverse_video);
+                    if bg != background {
+                        ui.painter().rect_filled(
+                            Rect::from_min_size(
+                                pos2(res.rect.left() + 2.0 * column as f32 * width, top),
+                                egui::vec2(2.0 * width, height),
+                            ),
+                            0.0,
+                            bg,
+                        );
+                    }
+                    if let Some(text) = turm.grid.cell_text(&cell) {
+                        Self::draw_scaled_char(
+                            ui.painter(),
+                            &text,
+                            font_id.clone(),
+                            fg.into(),
+                            Rect::from_min_size(
+                                pos2(res.rect.left() + 2.0 * column as f32 * width, top),
+                                egui::vec2(2.0 * width, height),
+                            ),
+                            size,
+                        );
+                    }
+                }
+            }
+
+            // Images with a positive z-index are drawn over the text
+            for piece in pieces.iter().filter(|p| p.placement.z >= 0) {
+                Self::draw_image_piece(
+                    &mut self.textures,
+                    ctx,
+                    ui.painter(),
+                    piece,
+                    res.rect.left_top(),
+                    width,
+                    height,
+                );
+            }
+
+            // Gutter marks on the prompts of the shell
+            for (y, color) in crate::gui::prompt_marks(turm) {
+                let top = res.rect.top() + y as f32 * height;
+                let rect = Rect::from_min_size(pos2(res.rect.left(), top), egui::vec2(2.0, height));
+                ui.painter().rect_filled(rect, 0.0, color);
+            }
+
+            // Underline the hovered hyperlink, Ctrl+click opens it
+            let (hover, clicked) = ctx.input(|i| {
+                let clicked = i.pointer.primary_clicked() && i.modifiers.ctrl;
+                (i.pointer.hover_pos(), clicked)
+            });
+            let hovered_link = hover.and_then(|pos| {
+                let x = ((pos.x - res.rect.left()) / width) as usize;
+                let y = ((pos.y - res.rect.top()) / height) as usize;
+                crate::gui::hyperlink_at(turm, x, y)
+            });
+            if let Some(link) = hovered_link {
+                for run in crate::gui::hyperlink_runs(turm, link) {
+                    Self::draw_underline(ui.painter(), &run, res.rect.left_top(), width, height);
+                }
+                if clicked {
+                    crate::gui::open_hyperlink(&turm.grid.hyperlink(link).uri);
+                }
+            }
+
+            // Mouse events go to applications that track the mouse, unless
+            // shift is held
+            let events = ctx.input(|i| {
+                let held = if i.pointer.primary_down() {
+                    Some(MouseButton::Left)
+                } else if i.pointer.middle_down() {
+                    Some(MouseButton::Middle)
+                } else if i.pointer.secondary_down() {
+                    Some(MouseButton::Right)
+                } else {
+                    None
+                };
+                let cell = |pos: Pos2| {
+                    (
+                        ((pos.x - res.rect.left()) / width).max(0.0) as usize,
+                        ((pos.y - res.rect.top()) / height).max(0.0) as usize,
+                    )
+                };
+
+                let mut events = vec![];
+                for event in &i.events {
+                    let (action, button, pos) = match event {
+                        egui::Event::PointerButton {
+                            pos,
+                            button,
+                            pressed,
+                            ..
+                        } => {
+                            let button = match button {
+                                egui::PointerButton::Primary => MouseButton::Left,
+                                egui::PointerButton::Middle => MouseButton::Middle,
+                                egui::PointerButton::Secondary => MouseButton::Right,
+                                _ => continue,
+                            };
+                            let action = if *pressed {
+                                MouseAction::Press
+                            } else {
+                                MouseAction::Release
+                            };
+                            (action, Some(button), *pos)
+                        }
+                        egui::Event::PointerMoved(pos) => (MouseAction::Motion, held, *pos),
+                        egui::Event::MouseWheel { delta, .. } => {
+                            let Some(pos) = i.pointer.hover_pos() else {
+                                continue;
+                            };
+                            let button = if delta.y > 0.0 {
+                                MouseButton::WheelUp
+                            } else {
+                                MouseButton::WheelDown
+                            };
+                            (MouseAction::Press, Some(button), pos)
+                        }
+                        _ => continue,
+                    };
+                    let (column, line) = cell(pos);
+                    events.push(MouseEvent {
+                        action,
+                        button,
+                        column: column.min(turm.columns.saturating_sub(1)),
+                        line: line.min(turm.lines.saturating_sub(1)),
+                        shift: i.modifiers.shift,
+                        alt: i.modifiers.alt,
+                        ctrl: i.modifiers.ctrl,
+                    });
+                }
+                events
+            });
+            for event in events {
+                let cell = Some((event.column, event.line));
+                if event.action == MouseAction::Motion && self.mouse_cell == cell {
+                    continue;
+                }
+                self.mouse_cell = cell;
+                if !event.shift {
+                    self.terminal_gui_input
+                        .write_mouse_to_terminal(&event, &turm.modes);
+                }
+            }
+
+            if turm.modes.show_cursor {
                 let painter = ui.painter();
+                // The cursor is as wide as the characters of its row
+                let line_size = if turm.cursor.pos.y < turm.lines {
+                    turm.grid[turm.cursor.pos.y].size
+                } else {
+                    LineSize::Single
+                };
+                let (scale, _, _) = crate::gui::line_scale(line_size);
                 let pos = egui::pos2(
-                    (turm.cursor.pos.x as f32) * width + res.rect.left(),
+                    (turm.cursor.pos.x as f32) * width * scale + res.rect.left(),
                     (turm.cursor.pos.y as f32) * height + res.rect.top() - 1.0,
                 );
-                let size = egui::vec2(width, height);
-                painter.rect_filled(Rect::from_min_size(pos, size), 0.0, Color32::WHITE);
+                // It covers both cells of a double width character
+                let wide = turm.cursor.pos.y < turm.lines
+                    && turm.cursor.pos.x < turm.columns
+                    && turm.grid[turm.cursor.pos.y][turm.cursor.pos.x].width == CellWidth::Wide;
+                let size = egui::vec2(width * scale * if wide { 2.0 } else { 1.0 }, height);
+                painter.rect_filled(
+                    Rect::from_min_size(pos, size),
+                    0.0,
+                    turm.palette.get(PaletteColor::Cursor),
+                );
 
                 // Get character at cursor position and draw it in black on top of the cursor
                 if turm.cursor.pos.y < turm.lines && turm.cursor.pos.x < turm.columns {
-                    if let Some(c) = turm.grid[turm.cursor.pos.y][turm.cursor.pos.x].c {
-                        painter.text(
-                            pos,
-                            egui::Align2::LEFT_TOP,
-                            c.to_string(),
+                    let cell = &turm.grid[turm.cursor.pos.y][turm.cursor.pos.x];
+                    if let Some(text) = turm.grid.cell_text(cell) {
+                        Self::draw_scaled_char(
+                            painter,
+                            &text,
                             FontId {
                                 size: self.font_size,
                                 family: FontFamily::Monospace,
                             },
-                            Color32::BLACK,
+                            turm.palette.get(PaletteColor::Background).into(),
+                            Rect::from_min_size(pos, size),
+                            line_size,
                         );
                     }
                 }
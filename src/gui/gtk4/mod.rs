@@ -1,12 +1,31 @@
 use std::{
+    collections::HashMap,
     ops::DerefMut,
     os::fd::{AsRawFd, OwnedFd},
+    rc::Rc,
     sync::{Arc, Mutex},
     thread,
 };
 
 use crate::{
-    ansi::Ansi, gui::Gui, terminal_gui_input::TerminalGuiInput, terminal_io::TerminalIO, turm::Turm,
+    ansi::{Ansi, CursorShape},
+    clipboard::{Clipboard, ClipboardEvent},
+    color::{Color, PaletteColor},
+    grid::{
+        cell::{CellWidth, Style, UnderlineStyle},
+        row::LineSize,
+    },
+    gui::{
+        selection::{Position, Selection},
+        Gui, UnderlineRun,
+    },
+    image::Image,
+    modes::MouseTracking,
+    mouse::{MouseAction, MouseButton, MouseEvent},
+    notification::Notification,
+    terminal_gui_input::TerminalGuiInput,
+    terminal_io::TerminalIO,
+    turm::Turm,
 };
 
 use egui;
@@ -16,101 +35,267 @@ use gtk4 as gtk;
 use pango;
 use pangocairo;
 
-// Terminal selection
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Position {
-    x: usize,
-    y: usize,
-}
+/// Time during which blinking text is shown or hidden
+const BLINK_INTERVAL_US: i64 = 500_000;
+
+/// Reports a mouse event at a cell to the application if it tracks the
+/// mouse, returns whether it does. Holding shift keeps the mouse for the
+/// terminal.
+fn report_mouse(
+    input: &TerminalGuiInput,
+    turm: &Mutex<Turm>,
+    action: MouseAction,
+    button: Option<MouseButton>,
+    position: Position,
+    state: gtk::gdk::ModifierType,
+) -> bool {
+    let terminal = turm.lock().unwrap();
+    if terminal.modes.mouse_tracking == MouseTracking::None
+        || state.contains(gtk::gdk::ModifierType::SHIFT_MASK)
+    {
+        return false;
+    }
 
-#[derive(Clone, Debug)]
-struct Selection {
-    start: Position,
-    end: Position,
-    active: bool,
+    let event = MouseEvent {
+        action,
+        button,
+        column: position.x.min(terminal.columns.saturating_sub(1)),
+        line: position.y.min(terminal.lines.saturating_sub(1)),
+        shift: false,
+        alt: state.contains(gtk::gdk::ModifierType::ALT_MASK),
+        ctrl: state.contains(gtk::gdk::ModifierType::CONTROL_MASK),
+    };
+    input.write_mouse_to_terminal(&event, &terminal.modes);
+    true
 }
 
-impl Selection {
-    fn new() -> Self {
-        Self {
-            start: Position { x: 0, y: 0 },
-            end: Position { x: 0, y: 0 },
-            active: false,
+/// Writes to or reads from the clipboard for an application, `input` sends
+/// the contents of the clipboard
+fn handle_clipboard_event(
+    widget: &impl IsA<gtk::Widget>,
+    input: &TerminalGuiInput,
+    event: ClipboardEvent,
+) {
+    let clipboard = |c| match c {
+        Clipboard::Clipboard => widget.clipboard(),
+        Clipboard::Primary => widget.primary_clipboard(),
+    };
+
+    match event {
+        ClipboardEvent::Store(c, text) => clipboard(c).set_text(&text),
+        ClipboardEvent::Load { clipboard: c, .. } => {
+            let input = input.clone();
+            clipboard(c).read_text_async(None::<&gtk::gio::Cancellable>, move |text| {
+                let text = text.ok().flatten().unwrap_or_default();
+                if let Some(reply) = event.reply(&text) {
+                    input.write(reply);
+                }
+            });
         }
     }
+}
 
-    fn clear(&mut self) {
-        self.active = false;
+/// Handles the clipboard events, notifications and bell of the application
+fn handle_requests(window: &gtk::Window, turm: &Mutex<Turm>, input: &TerminalGuiInput) {
+    let mut terminal = turm.lock().unwrap();
+    let clipboard_events = terminal.take_clipboard_events();
+    let notifications = terminal.take_notifications();
+    let notify = !(terminal.notify_only_unfocused && window.is_active());
+    let bell = terminal.take_bell();
+    drop(terminal);
+
+    for event in clipboard_events {
+        handle_clipboard_event(window, input, event);
     }
-
-    fn is_position_selected(&self, x: usize, y: usize) -> bool {
-        if !self.active {
-            return false;
-        }
-
-        let (start, end) = self.normalized();
-
-        if y < start.y || y > end.y {
-            return false;
-        }
-
-        if y == start.y && y == end.y {
-            return x >= start.x && x < end.x;
-        } else if y == start.y {
-            return x >= start.x;
-        } else if y == end.y {
-            return x < end.x;
+    if notify {
+        if let Some(app) = window.application() {
+            for notification in &notifications {
+                send_notification(&app, notification);
+            }
         }
-
-        true
     }
+    if bell {
+        window.error_bell();
+    }
+}
 
-    fn normalized(&self) -> (Position, Position) {
-        if self.start.y > self.end.y || (self.start.y == self.end.y && self.start.x > self.end.x) {
-            (self.end, self.start)
-        } else {
-            (self.start, self.end)
-        }
+/// Shows a notification through the desktop, or with `notify-send` when the
+/// application isn't on the session bus
+fn send_notification(app: &gtk::Application, notification: &Notification) {
+    if app.dbus_connection().is_none() {
+        crate::gui::notify_send(notification);
+        return;
     }
 
-    fn get_selected_text(&self, terminal: &Turm) -> String {
-        if !self.active {
-            return String::new();
-        }
+    let n = gtk::gio::Notification::new(notification.summary());
+    n.set_body(Some(&notification.body));
+    app.send_notification(None, &n);
+}
 
-        let (start, end) = self.normalized();
-        let mut result = String::new();
+/// Converts an image to a cairo surface, which has premultiplied alpha
+fn image_surface(image: &Image) -> gtk::cairo::ImageSurface {
+    let data: Vec<u8> = image
+        .rgba
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let a = p[3] as u32;
+            let [r, g, b] = [p[0], p[1], p[2]].map(|c| c as u32 * a / 255);
+            ((a << 24) | (r << 16) | (g << 8) | b).to_ne_bytes()
+        })
+        .collect();
+
+    gtk::cairo::ImageSurface::create_for_data(
+        data,
+        gtk::cairo::Format::ARgb32,
+        image.width as i32,
+        image.height as i32,
+        image.width as i32 * 4,
+    )
+    .expect("Failed to create an image surface")
+}
 
-        for y in start.y..=end.y {
-            if y >= terminal.lines {
-                break;
-            }
+/// Draws a character in the current color at a cell, scaled for the size of
+/// its row. Scaled characters cover two columns.
+fn draw_scaled_char(
+    cr: &gtk::cairo::Context,
+    font_desc: &pango::FontDescription,
+    text: &str,
+    bold: bool,
+    (column, row): (usize, usize),
+    size: LineSize,
+    (char_width, char_height): (f32, f32),
+) {
+    let (scale_x, scale_y, shift) = crate::gui::line_scale(size);
+    let x = column as f64 * (char_width * scale_x) as f64;
+    let y = row as f64 * char_height as f64;
+
+    // Only the top or bottom half of double height characters shows, the
+    // clip is wide enough for double width characters
+    let _ = cr.save();
+    cr.rectangle(
+        x,
+        y,
+        2.0 * (char_width * scale_x) as f64,
+        char_height as f64,
+    );
+    cr.clip();
+    cr.translate(x, y - (shift * char_height) as f64);
+    cr.scale(scale_x as f64, scale_y as f64);
+
+    let layout = pangocairo::create_layout(cr);
+    layout.set_font_description(Some(font_desc));
+    if bold {
+        let attrs = pango::AttrList::new();
+        attrs.insert(pango::AttrInt::new_weight(pango::Weight::Bold));
+        layout.set_attributes(Some(&attrs));
+    }
+    layout.set_text(text);
+    cr.move_to(0.0, 0.0);
+    pangocairo::show_layout(cr, &layout);
+    let _ = cr.restore();
+}
 
-            let start_x = if y == start.y { start.x } else { 0 };
-            let end_x = if y == end.y {
-                std::cmp::min(end.x, terminal.columns)
-            } else {
-                terminal.columns
-            };
+/// Draws the piece of an image shown by a cell, scaled to the cell size
+fn draw_image_piece(
+    cr: &gtk::cairo::Context,
+    surfaces: &mut HashMap<u32, gtk::cairo::ImageSurface>,
+    piece: &crate::gui::ImagePiece,
+    char_width: f32,
+    char_height: f32,
+) {
+    let surface = surfaces
+        .entry(piece.id)
+        .or_insert_with(|| image_surface(&piece.placement.image));
+    let (cell_width, cell_height) = piece.placement.cell_size;
+    let scale_x = char_width as f64 / cell_width as f64;
+    let scale_y = char_height as f64 / cell_height as f64;
+
+    let _ = cr.save();
+    cr.rectangle(
+        piece.column as f64 * char_width as f64,
+        piece.row as f64 * char_height as f64,
+        piece.width as f64 * scale_x,
+        piece.height as f64 * scale_y,
+    );
+    cr.clip();
+    cr.translate(
+        piece.column as f64 * char_width as f64 - piece.x as f64 * scale_x,
+        piece.row as f64 * char_height as f64 - piece.y as f64 * scale_y,
+    );
+    cr.scale(scale_x, scale_y);
+    let _ = cr.set_source_surface(&*surface, 0.0, 0.0);
+    let _ = cr.paint();
+    let _ = cr.restore();
+}
 
-            for x in start_x..end_x {
-                if x >= terminal.columns {
-                    break;
-                }
+fn set_source_color(cr: &gtk::cairo::Context, color: Color) {
+    cr.set_source_rgb(
+        color.0[0] as f64 / 255.0,
+        color.0[1] as f64 / 255.0,
+        color.0[2] as f64 / 255.0,
+    );
+}
 
-                if let Some(c) = terminal.grid[y][x].c {
-                    result.push(c);
-                }
-            }
+/// Draws an underline at the bottom of its cells
+fn draw_underline(cr: &gtk::cairo::Context, run: &UnderlineRun, char_width: f64, char_height: f64) {
+    let left = run.start as f64 * char_width;
+    let right = run.end as f64 * char_width;
+    let y = (run.row + 1) as f64 * char_height - 1.5;
 
-            // Add newline if not the last line
-            if y < end.y {
-                result.push('\n');
+    set_source_color(cr, run.color);
+    cr.set_line_width(1.0);
+
+    match run.style {
+        UnderlineStyle::None => return,
+        UnderlineStyle::Single => {
+            cr.move_to(left, y);
+            cr.line_to(right, y);
+        }
+        UnderlineStyle::Double => {
+            cr.move_to(left, y);
+            cr.line_to(right, y);
+            cr.move_to(left, y - 2.0);
+            cr.line_to(right, y - 2.0);
+        }
+        UnderlineStyle::Curly => {
+            // One wave per cell
+            cr.move_to(left, y);
+            let mut x = left;
+            while x < right {
+                let half = char_width / 2.0;
+                cr.curve_to(
+                    x + half / 2.0,
+                    y - 2.0,
+                    x + half / 2.0,
+                    y - 2.0,
+                    x + half,
+                    y,
+                );
+                cr.curve_to(
+                    x + half * 1.5,
+                    y + 2.0,
+                    x + half * 1.5,
+                    y + 2.0,
+                    x + char_width,
+                    y,
+                );
+                x += char_width;
             }
         }
-
-        result
+        UnderlineStyle::Dotted => {
+            cr.set_dash(&[1.0, 2.0], 0.0);
+            cr.move_to(left, y);
+            cr.line_to(right, y);
+        }
+        UnderlineStyle::Dashed => {
+            cr.set_dash(&[4.0, 2.0], 0.0);
+            cr.move_to(left, y);
+            cr.line_to(right, y);
+        }
     }
+
+    let _ = cr.stroke();
+    cr.set_dash(&[], 0.0);
 }
 
 pub struct Gtk4Impl {
@@ -158,19 +343,39 @@ impl Gui for Gtk4Impl {
         // Create a drawing area for terminal content
         let drawing_area = gtk::DrawingArea::new();
 
-        // Setup drawing area redraw
+        // Setup drawing area redraw, the requests of the application are
+        // handled here since nothing is drawn while the window is hidden
         let da = drawing_area.clone();
+        let turm_requests = turm_clone.clone();
+        let input_requests = terminal_gui_input.clone();
         rx.attach(None, move |_| {
+            let window = da
+                .root()
+                .and_then(|root| root.downcast::<gtk::Window>().ok());
+            if let Some(window) = window {
+                handle_requests(&window, &turm_requests, &input_requests);
+            }
             da.queue_draw();
             glib::ControlFlow::Continue
         });
 
+        // Redraw periodically for blinking text
+        let da = drawing_area.clone();
+        glib::timeout_add_local(
+            std::time::Duration::from_micros(BLINK_INTERVAL_US as u64),
+            move || {
+                da.queue_draw();
+                glib::ControlFlow::Continue
+            },
+        );
+
         // Thread that reads output from the shell and sends it to the gui
         let turm_io = turm_clone.clone();
         let fd_io = fd_clone.try_clone().unwrap();
+        let input_io = terminal_gui_input.clone();
         thread::spawn(move || {
             let ansi = Ansi::new();
-            let mut terminal_io = TerminalIO::new(ansi, fd_io, turm_io);
+            let mut terminal_io = TerminalIO::new(ansi, fd_io, turm_io, input_io);
             terminal_io.start_io(|| {
                 // Signal that the terminal has updated
                 let _ = tx.send(());
@@ -194,17 +399,34 @@ impl Gui for Gtk4Impl {
             let fd_clone = fd_clone.try_clone().unwrap();
             let selection_for_draw = selection.clone();
 
+            // Cell under the pointer and the size of a cell, used to
+            // underline and open hyperlinks
+            let pointer = Rc::new(std::cell::Cell::new(None::<Position>));
+            let cell_size = Rc::new(std::cell::Cell::new((1.0f64, 1.0f64)));
+            let pointer_draw = pointer.clone();
+            let cell_size_draw = cell_size.clone();
+
+            // Surfaces of the images on the screen, by image id
+            let mut surfaces: HashMap<u32, gtk::cairo::ImageSurface> = HashMap::new();
+
             let window_clone = window.clone();
             // Setup drawing callback
             drawing_area.set_draw_func(move |_, cr, width, height| {
+                let mut turm_lock = turm.lock().unwrap();
+                let terminal = turm_lock.deref_mut();
+
                 // Fill the background
-                cr.set_source_rgb(46.0 / 255.0, 51.0 / 255.0, 63.0 / 255.0);
+                let (_, background) =
+                    Style::default().colors(&terminal.palette, terminal.modes.reverse_video);
+                cr.set_source_rgb(
+                    background.0[0] as f64 / 255.0,
+                    background.0[1] as f64 / 255.0,
+                    background.0[2] as f64 / 255.0,
+                );
                 cr.rectangle(0.0, 0.0, width as f64, height as f64);
                 let _ = cr.fill();
-
-                let mut turm_lock = turm.lock().unwrap();
-                let terminal = turm_lock.deref_mut();
                 let selection_lock = selection_for_draw.lock().unwrap();
+                let selection = terminal.palette.get(PaletteColor::SelectionBackground);
 
                 // Calculate character dimensions
                 let mut font_desc = pango::FontDescription::new();
@@ -218,35 +440,45 @@ impl Gui for Gtk4Impl {
                 let (_, logical_rect) = layout.pixel_extents();
                 let char_width = logical_rect.width() as f32;
                 let char_height = logical_rect.height() as f32;
+                cell_size_draw.set((char_width as f64, char_height as f64));
 
                 // Calculate terminal size based on window size
                 let w = (width as f32 / char_width) as usize;
                 let h = (height as f32 / char_height) as usize;
 
+                terminal.cell_size = (char_width as usize, char_height as usize);
+
                 // Resize the terminal if needed
                 if w != terminal.columns || h != terminal.lines {
-                    terminal.grid.resize(w, h);
-                    terminal.columns = w;
-                    terminal.lines = h;
+                    terminal.resize(w, h);
 
                     crate::gui::resize(fd_clone.as_raw_fd(), w, h, font_size, char_width);
                 }
 
                 // Set the window title
-                if terminal.title.is_empty() {
-                    window_clone.set_title(Some("💩 Turm 💩"));
-                } else {
-                    window_clone.set_title(Some(&format!("💩 {} 💩", terminal.title)));
+                window_clone.set_title(Some(&crate::gui::window_title(terminal)));
+
+                // Each cell shows its piece of an image, those with a negative
+                // z-index are drawn below the text
+                let pieces = crate::gui::image_pieces(terminal);
+                surfaces.retain(|id, _| pieces.iter().any(|p| p.id == *id));
+                for piece in pieces.iter().filter(|p| p.placement.z < 0) {
+                    draw_image_piece(cr, &mut surfaces, piece, char_width, char_height);
                 }
 
                 // Render terminal content
                 let sections = terminal.grid.sections();
+                let blink_on = glib::monotonic_time() / BLINK_INTERVAL_US % 2 == 0;
                 let mut current_row = 0;
                 let mut current_col = 0;
                 for section in &sections.sections {
-                    // Set colors
-                    let fg = section.style.fg;
-                    let bg = section.style.bg;
+                    // Set colors, blinking text is hidden during the off phase
+                    let (mut fg, bg) = section
+                        .style
+                        .colors(&terminal.palette, terminal.modes.reverse_video);
+                    if section.style.blink && !blink_on {
+                        fg = bg;
+                    }
 
                     // Set foreground color based on the section style
                     cr.set_source_rgb(
@@ -262,8 +494,8 @@ impl Gui for Gtk4Impl {
                     let x = (current_col as f32 * char_width) as f64;
                     let y = (current_row as f32 * char_height) as f64;
 
-                    // Draw background if needed
-                    if bg.0[0] != 0 || bg.0[1] != 0 || bg.0[2] != 0 {
+                    // Draw background if it differs from the one already filled
+                    if bg != background {
                         // Set background color
                         cr.set_source_rgb(
                             bg.0[0] as f64 / 255.0,
@@ -317,8 +549,12 @@ impl Gui for Gtk4Impl {
                         let attr = pango::AttrInt::new_style(pango::Style::Italic);
                         attr_list.insert(attr);
                     }
-                    if section.style.underline {
-                        let attr = pango::AttrInt::new_underline(pango::Underline::Single);
+                    if section.style.strikethrough {
+                        let attr = pango::AttrInt::new_strikethrough(true);
+                        attr_list.insert(attr);
+                    }
+                    if section.style.overline {
+                        let attr = pango::AttrInt::new_overline(pango::Overline::Single);
                         attr_list.insert(attr);
                     }
 
@@ -390,7 +626,7 @@ impl Gui for Gtk4Impl {
                                         current_chunk_row,
                                     ) {
                                         // Draw selection highlight
-                                        cr.set_source_rgb(0.5, 0.5, 1.0); // Selection color (light blue)
+                                        set_source_color(cr, selection);
                                         cr.rectangle(
                                             char_x,
                                             current_y,
@@ -437,7 +673,7 @@ impl Gui for Gtk4Impl {
                                 let char_x = current_x + (i + col) as f64 * char_width as f64;
                                 if selection_lock.is_position_selected(col + i, row) {
                                     // Draw selection highlight
-                                    cr.set_source_rgb(0.5, 0.5, 1.0); // Selection color (light blue)
+                                    set_source_color(cr, selection);
                                     cr.rectangle(
                                         char_x,
                                         current_y,
@@ -467,36 +703,153 @@ impl Gui for Gtk4Impl {
                     current_col = last_line_chars % terminal.columns;
                 }
 
+                for (row, column, cell, text) in crate::gui::lone_cells(terminal) {
+                    let (mut fg, bg) = cell
+                        .style
+                        .colors(&terminal.palette, terminal.modes.reverse_video);
+                    if cell.style.blink && !blink_on {
+                        fg = bg;
+                    }
+                    set_source_color(cr, fg);
+
+                    let attr_list = pango::AttrList::new();
+                    if cell.style.bold {
+                        attr_list.insert(pango::AttrInt::new_weight(pango::Weight::Bold));
+                    }
+                    if cell.style.italics {
+                        attr_list.insert(pango::AttrInt::new_style(pango::Style::Italic));
+                    }
+                    let layout = pangocairo::create_layout(cr);
+                    layout.set_font_description(Some(&font_desc));
+                    layout.set_attributes(Some(&attr_list));
+                    layout.set_text(&text);
+                    cr.move_to(
+                        column as f64 * char_width as f64,
+                        row as f64 * char_height as f64,
+                    );
+                    pangocairo::show_layout(cr, &layout);
+                }
+
+                for run in crate::gui::underline_runs(terminal) {
+                    draw_underline(cr, &run, char_width as f64, char_height as f64);
+                }
+
+                // Double width and double height rows are drawn again over
+                // their text, scaled
+                for (row, size) in crate::gui::scaled_rows(terminal) {
+                    let y = row as f64 * char_height as f64;
+                    set_source_color(cr, background);
+                    cr.rectangle(
+                        0.0,
+                        y,
+                        terminal.columns as f64 * char_width as f64,
+                        char_height as f64,
+                    );
+                    let _ = cr.fill();
+
+                    for column in 0..terminal.columns / 2 {
+                        let cell = terminal.grid[row][column];
+                        let (fg, bg) = cell
+                            .style
+                            .colors(&terminal.palette, terminal.modes.reverse_video);
+                        let x = 2.0 * column as f64 * char_width as f64;
+                        if bg != background {
+                            set_source_color(cr, bg);
+                            cr.rectangle(x, y, 2.0 * char_width as f64, char_height as f64);
+                            let _ = cr.fill();
+                        }
+                        if let Some(text) = terminal.grid.cell_text(&cell) {
+                            set_source_color(cr, fg);
+                            draw_scaled_char(
+                                cr,
+                                &font_desc,
+                                &text,
+                                cell.style.bold,
+                                (column, row),
+                                size,
+                                (char_width, char_height),
+                            );
+                        }
+                    }
+                }
+
+                // Images with a positive z-index are drawn over the text
+                for piece in pieces.iter().filter(|p| p.placement.z >= 0) {
+                    draw_image_piece(cr, &mut surfaces, piece, char_width, char_height);
+                }
+
+                // Gutter marks on the prompts of the shell
+                for (y, color) in crate::gui::prompt_marks(terminal) {
+                    set_source_color(cr, color);
+                    cr.rectangle(0.0, y as f64 * char_height as f64, 2.0, char_height as f64);
+                    let _ = cr.fill();
+                }
+
+                let hovered_link = pointer_draw
+                    .get()
+                    .and_then(|p| crate::gui::hyperlink_at(terminal, p.x, p.y));
+                if let Some(link) = hovered_link {
+                    for run in crate::gui::hyperlink_runs(terminal, link) {
+                        draw_underline(cr, &run, char_width as f64, char_height as f64);
+                    }
+                }
+
                 // Draw cursor if visible
-                if terminal.show_cursor {
-                    let cursor_x = terminal.cursor.pos.x as f32 * char_width;
+                if terminal.modes.show_cursor {
+                    // The cursor is as wide as the characters of its row
+                    let size = if terminal.cursor.pos.y < terminal.lines {
+                        terminal.grid[terminal.cursor.pos.y].size
+                    } else {
+                        LineSize::Single
+                    };
+                    let (scale, _, _) = crate::gui::line_scale(size);
+                    let cursor_x = terminal.cursor.pos.x as f32 * char_width * scale;
                     let cursor_y = terminal.cursor.pos.y as f32 * char_height;
 
+                    // It covers both cells of a double width character
+                    let mut cursor_width = char_width * scale;
+                    if terminal.cursor.pos.y < terminal.lines
+                        && terminal.cursor.pos.x < terminal.columns
+                        && terminal.grid[terminal.cursor.pos.y][terminal.cursor.pos.x].width
+                            == CellWidth::Wide
+                    {
+                        cursor_width *= 2.0;
+                    }
+
                     // Draw cursor rectangle
-                    cr.set_source_rgb(1.0, 1.0, 1.0); // White
+                    let shape = terminal.cursor.style.shape;
+                    let (w, h) = match shape {
+                        CursorShape::Block => (cursor_width as f64, char_height as f64),
+                        CursorShape::Underline => (cursor_width as f64, 2.0),
+                        CursorShape::Bar => (2.0, char_height as f64),
+                    };
+                    set_source_color(cr, terminal.palette.get(PaletteColor::Cursor));
                     cr.rectangle(
                         cursor_x as f64,
-                        cursor_y as f64,
-                        char_width as f64,
-                        char_height as f64,
+                        cursor_y as f64 + char_height as f64 - h,
+                        w,
+                        h,
                     );
                     let _ = cr.fill();
 
                     // Draw character at cursor position in black
-                    if terminal.cursor.pos.y < terminal.lines
+                    if shape == CursorShape::Block
+                        && terminal.cursor.pos.y < terminal.lines
                         && terminal.cursor.pos.x < terminal.columns
                     {
-                        if let Some(c) =
-                            terminal.grid[terminal.cursor.pos.y][terminal.cursor.pos.x].c
-                        {
-                            cr.set_source_rgb(0.0, 0.0, 0.0); // Black
-
-                            let cursor_layout = pangocairo::create_layout(cr);
-                            cursor_layout.set_font_description(Some(&font_desc));
-                            cursor_layout.set_text(&c.to_string());
-
-                            cr.move_to(cursor_x as f64, cursor_y as f64);
-                            pangocairo::show_layout(cr, &cursor_layout);
+                        let cell = &terminal.grid[terminal.cursor.pos.y][terminal.cursor.pos.x];
+                        if let Some(text) = terminal.grid.cell_text(cell) {
+                            set_source_color(cr, terminal.palette.get(PaletteColor::Background));
+
+                            draw_scaled_char(
+                                cr,
+                                &font_desc,
+                                &text,
+                                false,
+                                (terminal.cursor.pos.x, terminal.cursor.pos.y),
+                                size,
+                                (char_width, char_height),
+                            );
                         }
                     }
                 }
@@ -507,7 +860,53 @@ impl Gui for Gtk4Impl {
 
             // Clone the terminal_gui_input for the key handlers
             let terminal_gui_input_pressed = terminal_gui_input.clone();
+            let turm_keys = turm_clone.clone();
+            let window_keys = window.clone();
+            let selection_keys = selection.clone();
+            let da = drawing_area.clone();
             key_controller.connect_key_pressed(move |_controller, key, _keycode, state| {
+                // Shortcuts for the prompts marked by the shell, copying and new
+                // windows
+                let shortcut =
+                    gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::SHIFT_MASK;
+                if state.contains(shortcut) {
+                    let mut terminal = turm_keys.lock().unwrap();
+                    match key {
+                        gtk::gdk::Key::Up | gtk::gdk::Key::Down => {
+                            terminal.scroll_to_prompt(key == gtk::gdk::Key::Up);
+                            da.queue_draw();
+                            return glib::Propagation::Stop;
+                        }
+                        gtk::gdk::Key::O => {
+                            if let Some(output) = terminal.last_command_output() {
+                                window_keys.clipboard().set_text(&output);
+                            }
+                            return glib::Propagation::Stop;
+                        }
+                        gtk::gdk::Key::N => {
+                            crate::gui::new_window(&terminal);
+                            return glib::Propagation::Stop;
+                        }
+                        // Copied as plain text and as HTML with the links
+                        gtk::gdk::Key::C => {
+                            let selection = selection_keys.lock().unwrap();
+                            let text = selection.text(&terminal);
+                            if !text.is_empty() {
+                                let html = glib::Bytes::from_owned(selection.html(&terminal));
+                                let content = gtk::gdk::ContentProvider::new_union(&[
+                                    gtk::gdk::ContentProvider::for_value(&text.to_value()),
+                                    gtk::gdk::ContentProvider::for_bytes("text/html", &html),
+                                ]);
+                                let _ = window_keys.clipboard().set_content(Some(&content));
+                            }
+                            return glib::Propagation::Stop;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let modes = turm_keys.lock().unwrap().modes;
+
                 // Convert GTK key event to egui InputState
                 let modifiers = egui::Modifiers {
                     alt: state.contains(gtk::gdk::ModifierType::ALT_MASK),
@@ -538,7 +937,7 @@ impl Gui for Gtk4Impl {
                             input.events = events;
                             input.modifiers = modifiers;
 
-                            terminal_gui_input_pressed.write_input_to_terminal(&input);
+                            terminal_gui_input_pressed.write_input_to_terminal(&input, &modes);
                             return glib::Propagation::Stop;
                         } else {
                             return glib::Propagation::Proceed;
@@ -562,13 +961,212 @@ impl Gui for Gtk4Impl {
                 input_state.modifiers = modifiers;
 
                 // Pass the input to terminal_gui_input
-                terminal_gui_input_pressed.write_input_to_terminal(&input_state);
+                terminal_gui_input_pressed.write_input_to_terminal(&input_state, &modes);
 
                 glib::Propagation::Stop
             });
 
             window.add_controller(key_controller);
 
+            // Track the pointer to underline hovered hyperlinks
+            let motion_controller = gtk::EventControllerMotion::new();
+            let pointer_motion = pointer.clone();
+            let cell_size_motion = cell_size.clone();
+            let da = drawing_area.clone();
+            let terminal_gui_input_motion = terminal_gui_input.clone();
+            let turm_motion = turm_clone.clone();
+            motion_controller.connect_motion(move |controller, x, y| {
+                let (width, height) = cell_size_motion.get();
+                let position = Position {
+                    x: (x / width) as usize,
+                    y: (y / height) as usize,
+                };
+                if pointer_motion.get() != Some(position) {
+                    pointer_motion.set(Some(position));
+                    da.queue_draw();
+
+                    let state = controller.current_event_state();
+                    let button = if state.contains(gtk::gdk::ModifierType::BUTTON1_MASK) {
+                        Some(MouseButton::Left)
+                    } else if state.contains(gtk::gdk::ModifierType::BUTTON2_MASK) {
+                        Some(MouseButton::Middle)
+                    } else if state.contains(gtk::gdk::ModifierType::BUTTON3_MASK) {
+                        Some(MouseButton::Right)
+                    } else {
+                        None
+                    };
+                    report_mouse(
+                        &terminal_gui_input_motion,
+                        &turm_motion,
+                        MouseAction::Motion,
+                        button,
+                        position,
+                        state,
+                    );
+                }
+            });
+            let pointer_leave = pointer.clone();
+            let da = drawing_area.clone();
+            motion_controller.connect_leave(move |_| {
+                pointer_leave.set(None);
+                da.queue_draw();
+            });
+            drawing_area.add_controller(motion_controller);
+
+            // Ctrl+click opens hyperlinks
+            let click = gtk::GestureClick::new();
+            let turm_click = turm_clone.clone();
+            let cell_size_click = cell_size.clone();
+            click.connect_pressed(move |gesture, _, x, y| {
+                let state = gesture.current_event_state();
+                if !state.contains(gtk::gdk::ModifierType::CONTROL_MASK) {
+                    return;
+                }
+
+                let (width, height) = cell_size_click.get();
+                let terminal = turm_click.lock().unwrap();
+                let (x, y) = ((x / width) as usize, (y / height) as usize);
+                if let Some(link) = crate::gui::hyperlink_at(&terminal, x, y) {
+                    crate::gui::open_hyperlink(&terminal.grid.hyperlink(link).uri);
+                }
+            });
+            drawing_area.add_controller(click);
+
+            // Presses and releases of all buttons for applications that track
+            // the mouse
+            let buttons = gtk::GestureClick::new();
+            buttons.set_button(0);
+            let mouse_button = |gesture: &gtk::GestureClick| match gesture.current_button() {
+                1 => Some(MouseButton::Left),
+                2 => Some(MouseButton::Middle),
+                3 => Some(MouseButton::Right),
+                _ => None,
+            };
+            for action in [MouseAction::Press, MouseAction::Release] {
+                let terminal_gui_input_button = terminal_gui_input.clone();
+                let turm_button = turm_clone.clone();
+                let cell_size_button = cell_size.clone();
+                let handler = move |gesture: &gtk::GestureClick, _: i32, x: f64, y: f64| {
+                    let (width, height) = cell_size_button.get();
+                    let position = Position {
+                        x: (x / width) as usize,
+                        y: (y / height) as usize,
+                    };
+                    report_mouse(
+                        &terminal_gui_input_button,
+                        &turm_button,
+                        action,
+                        mouse_button(gesture),
+                        position,
+                        gesture.current_event_state(),
+                    );
+                };
+                if action == MouseAction::Press {
+                    buttons.connect_pressed(handler);
+                } else {
+                    buttons.connect_released(handler);
+                }
+            }
+            drawing_area.add_controller(buttons);
+
+            // Dragging selects text, unless the application tracks the mouse
+            // and shift isn't held. The selection is also the primary one.
+            let drag = gtk::GestureDrag::new();
+            let selecting = |gesture: &gtk::GestureDrag, turm: &Mutex<Turm>| {
+                let tracking = turm.lock().unwrap().modes.mouse_tracking != MouseTracking::None;
+                let shift = gesture
+                    .current_event_state()
+                    .contains(gtk::gdk::ModifierType::SHIFT_MASK);
+                !tracking || shift
+            };
+            let boundary = |(width, height): (f64, f64), x: f64, y: f64| Position {
+                x: (x / width).round().max(0.0) as usize,
+                y: (y / height).max(0.0) as usize,
+            };
+            let selection_begin = selection.clone();
+            let turm_begin = turm_clone.clone();
+            let cell_size_begin = cell_size.clone();
+            let da = drawing_area.clone();
+            drag.connect_drag_begin(move |gesture, x, y| {
+                if !selecting(gesture, &turm_begin) {
+                    return;
+                }
+                let mut selection = selection_begin.lock().unwrap();
+                selection.clear();
+                selection.start = boundary(cell_size_begin.get(), x, y);
+                selection.end = selection.start;
+                da.queue_draw();
+            });
+            let selection_update = selection.clone();
+            let turm_update = turm_clone.clone();
+            let cell_size_update = cell_size.clone();
+            let da = drawing_area.clone();
+            drag.connect_drag_update(move |gesture, dx, dy| {
+                let Some((x, y)) = gesture.start_point() else {
+                    return;
+                };
+                if !selecting(gesture, &turm_update) {
+                    return;
+                }
+                let mut selection = selection_update.lock().unwrap();
+                selection.end = boundary(cell_size_update.get(), x + dx, y + dy);
+                selection.active = selection.start != selection.end;
+                da.queue_draw();
+            });
+            let selection_end = selection.clone();
+            let turm_end = turm_clone.clone();
+            let da = drawing_area.clone();
+            drag.connect_drag_end(move |_, _, _| {
+                let terminal = turm_end.lock().unwrap();
+                let text = selection_end.lock().unwrap().text(&terminal);
+                if !text.is_empty() {
+                    da.primary_clipboard().set_text(&text);
+                }
+            });
+            drawing_area.add_controller(drag);
+
+            // The wheel is reported as buttons 4 and 5
+            let scroll = gtk::EventControllerScroll::new(
+                gtk::EventControllerScrollFlags::VERTICAL
+                    | gtk::EventControllerScrollFlags::DISCRETE,
+            );
+            let terminal_gui_input_scroll = terminal_gui_input.clone();
+            let turm_scroll = turm_clone.clone();
+            scroll.connect_scroll(move |controller, _, dy| {
+                let Some(position) = pointer.get() else {
+                    return glib::Propagation::Proceed;
+                };
+                let button = if dy < 0.0 {
+                    MouseButton::WheelUp
+                } else {
+                    MouseButton::WheelDown
+                };
+                let tracked = report_mouse(
+                    &terminal_gui_input_scroll,
+                    &turm_scroll,
+                    MouseAction::Press,
+                    Some(button),
+                    position,
+                    controller.current_event_state(),
+                );
+                if tracked {
+                    glib::Propagation::Stop
+                } else {
+                    glib::Propagation::Proceed
+                }
+            });
+            drawing_area.add_controller(scroll);
+
+            // Report focus changes to applications that asked for them
+            let terminal_gui_input_focus = terminal_gui_input.clone();
+            let turm_focus = turm_clone.clone();
+            window.connect_is_active_notify(move |window| {
+                let modes = turm_focus.lock().unwrap().modes;
+                let mut input_state = egui::InputState::default();
+                input_state.events = vec![egui::Event::WindowFocused(window.is_active())];
+                terminal_gui_input_focus.write_input_to_terminal(&input_state, &modes);
+            });
+
             // Show all widgets
             window.show();
 
@@ -1,7 +1,17 @@
 pub mod egui;
 pub mod gtk4;
+pub mod selection;
 
-use crate::turm::Turm;
+use crate::{
+    color::{Color, PaletteColor},
+    grid::{
+        cell::{Cell, UnderlineStyle},
+        row::LineSize,
+    },
+    image::Placement,
+    notification::Notification,
+    turm::{CommandResult, Turm},
+};
 use std::{os::fd::OwnedFd, sync::Arc, sync::Mutex};
 
 /// GuiTrait defines the interface for different GUI implementations
@@ -17,6 +27,117 @@ pub trait Gui {
         Self: Sized;
 }
 
+/// Default template of the window title, with the title and the status of
+/// the last command
+const TITLE_TEMPLATE: &str = "💩 {title} 💩 {status}";
+
+/// Returns the window title from `Turm::title_template`, or the default one.
+/// `{title}` is replaced with the title set by the application, or the
+/// working directory without one, `{cwd}` with the working directory of the
+/// shell and `{status}` with the exit status and duration of the last command.
+pub fn window_title(turm: &mut Turm) -> String {
+    // The working directory may be read from /proc, only when it is shown
+    let shows_cwd = turm
+        .title_template
+        .as_deref()
+        .unwrap_or(TITLE_TEMPLATE)
+        .contains("{cwd}");
+    let cwd = if shows_cwd || turm.title.is_empty() {
+        turm.cached_current_dir()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let template = turm.title_template.as_deref().unwrap_or(TITLE_TEMPLATE);
+    let title = match (turm.title.as_str(), cwd.as_str()) {
+        ("", "") => "Turm",
+        ("", cwd) => cwd,
+        (title, _) => title,
+    };
+
+    let status = match turm.last_command {
+        Some(CommandResult {
+            exit_status: Some(0),
+            duration,
+        }) => format!("✓ {:.1}s", duration.as_secs_f32()),
+        Some(CommandResult {
+            exit_status: Some(status),
+            duration,
+        }) => format!("✗ {status} {:.1}s", duration.as_secs_f32()),
+        Some(CommandResult { duration, .. }) => format!("{:.1}s", duration.as_secs_f32()),
+        None => String::new(),
+    };
+
+    template
+        .replace("{title}", title)
+        .replace("{cwd}", &cwd)
+        .replace("{status}", &status)
+        .trim_end()
+        .to_string()
+}
+
+/// Returns the cells of the visible screen drawn on their own with their row,
+/// column and grapheme cluster, they are left out of the text sections so
+/// that the other characters stay aligned to their cells
+pub fn lone_cells(turm: &Turm) -> Vec<(usize, usize, Cell, String)> {
+    let mut cells = vec![];
+
+    for row in 0..turm.lines {
+        // Scaled rows draw all their characters themselves
+        if turm.grid[row].size != LineSize::Single {
+            continue;
+        }
+        for column in 0..turm.columns {
+            let cell = turm.grid[row][column];
+            if !cell.is_drawn_alone() {
+                continue;
+            }
+            if let Some(text) = turm.grid.cell_text(&cell) {
+                cells.push((row, column, cell, text.into_owned()));
+            }
+        }
+    }
+
+    cells
+}
+
+/// Returns the rows of the visible screen whose characters are drawn scaled,
+/// over the text drawn for them at the normal size
+pub fn scaled_rows(turm: &Turm) -> Vec<(usize, LineSize)> {
+    (0..turm.lines)
+        .map(|y| (y, turm.grid[y].size))
+        .filter(|(_, size)| *size != LineSize::Single)
+        .collect()
+}
+
+/// Returns the horizontal and vertical scale of the characters of a row, and
+/// how many rows they are moved up so that only their bottom half shows
+pub fn line_scale(size: LineSize) -> (f32, f32, f32) {
+    match size {
+        LineSize::Single => (1.0, 1.0, 0.0),
+        LineSize::DoubleWidth => (2.0, 1.0, 0.0),
+        LineSize::DoubleHeightTop => (2.0, 2.0, 0.0),
+        LineSize::DoubleHeightBottom => (2.0, 2.0, 1.0),
+    }
+}
+
+/// Returns the rows of the visible screen where a prompt starts, with the
+/// color of their gutter mark showing the exit status of the command
+pub fn prompt_marks(turm: &Turm) -> Vec<(usize, Color)> {
+    (0..turm.lines)
+        .filter_map(|y| {
+            let prompt = turm.grid[y].prompt?;
+            let color = match prompt.exit_status {
+                Some(0) => 2,
+                Some(_) => 1,
+                None => 8,
+            };
+            Some((y, turm.palette.get(PaletteColor::Indexed(color))))
+        })
+        .collect()
+}
+
 /// Helper function to resize a terminal
 pub fn resize(fd: impl std::os::fd::AsRawFd, cols: usize, rows: usize, font_size: f32, width: f32) {
     use nix::pty::Winsize;
@@ -35,3 +156,217 @@ pub fn resize(fd: impl std::os::fd::AsRawFd, cols: usize, rows: usize, font_size
         std::process::exit(1);
     }
 }
+
+/// A horizontal run of cells sharing the same underline
+pub struct UnderlineRun {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+    pub style: UnderlineStyle,
+    pub color: Color,
+}
+
+/// Returns the underlines to draw on the visible screen. Underlines are drawn
+/// by hand in both backends since neither supports all the styles.
+pub fn underline_runs(turm: &Turm) -> Vec<UnderlineRun> {
+    let mut runs: Vec<UnderlineRun> = vec![];
+
+    for y in 0..turm.lines {
+        for x in 0..turm.columns {
+            let style = turm.grid[y][x].style;
+            if style.underline == UnderlineStyle::None || style.hidden {
+                continue;
+            }
+
+            let color = match style.underline_color {
+                Some(color) => turm.palette.resolve(color, PaletteColor::Foreground),
+                None => style.colors(&turm.palette, turm.modes.reverse_video).0,
+            };
+            match runs.last_mut() {
+                Some(run)
+                    if run.row == y
+                        && run.end == x
+                        && run.style == style.underline
+                        && run.color == color =>
+                {
+                    run.end += 1;
+                }
+                _ => runs.push(UnderlineRun {
+                    row: y,
+                    start: x,
+                    end: x + 1,
+                    style: style.underline,
+                    color,
+                }),
+            }
+        }
+    }
+
+    runs
+}
+
+/// A cell of the visible screen covered by a piece of an image
+pub struct ImagePiece<'a> {
+    pub row: usize,
+    pub column: usize,
+    pub id: u32,
+    pub placement: &'a Placement,
+    /// Part of the image shown in the cell, in image pixels. It is smaller
+    /// than a cell on the right and bottom edges of the image.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Returns the pieces of images to draw on the visible screen
+pub fn image_pieces(turm: &Turm) -> Vec<ImagePiece<'_>> {
+    let mut pieces = vec![];
+
+    for row in 0..turm.lines {
+        for column in 0..turm.columns {
+            let Some(cell) = turm.grid[row][column].image else {
+                continue;
+            };
+            let Some(placement) = turm.grid.image(cell.id) else {
+                continue;
+            };
+
+            let (cell_width, cell_height) = placement.cell_size;
+            let x = cell.x as usize * cell_width;
+            let y = cell.y as usize * cell_height;
+            if x >= placement.image.width || y >= placement.image.height {
+                continue;
+            }
+            pieces.push(ImagePiece {
+                row,
+                column,
+                id: cell.id,
+                placement,
+                x,
+                y,
+                width: cell_width.min(placement.image.width - x),
+                height: cell_height.min(placement.image.height - y),
+            });
+        }
+    }
+
+    pieces
+}
+
+/// Returns the hyperlink of a cell of the visible screen
+pub fn hyperlink_at(turm: &Turm, x: usize, y: usize) -> Option<u32> {
+    if x >= turm.columns || y >= turm.lines {
+        return None;
+    }
+
+    turm.grid[y][x].hyperlink
+}
+
+/// Returns the underlines to draw under the visible cells of a hovered
+/// hyperlink
+pub fn hyperlink_runs(turm: &Turm, link: u32) -> Vec<UnderlineRun> {
+    let mut runs: Vec<UnderlineRun> = vec![];
+
+    for y in 0..turm.lines {
+        for x in 0..turm.columns {
+            let cell = &turm.grid[y][x];
+            if cell.hyperlink != Some(link) {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some(run) if run.row == y && run.end == x => run.end += 1,
+                _ => runs.push(UnderlineRun {
+                    row: y,
+                    start: x,
+                    end: x + 1,
+                    style: UnderlineStyle::Single,
+                    color: cell.style.colors(&turm.palette, turm.modes.reverse_video).0,
+                }),
+            }
+        }
+    }
+
+    runs
+}
+
+/// Opens a hyperlink with the program in `TURM_OPENER`, `xdg-open` by default
+pub fn open_hyperlink(uri: &str) {
+    let opener = std::env::var("TURM_OPENER").unwrap_or_else(|_| String::from("xdg-open"));
+    spawn(std::process::Command::new(opener).arg(uri));
+}
+
+/// Starts another turm in the working directory of the shell, where its own
+/// shell then starts
+pub fn new_window(turm: &Turm) {
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let mut command = std::process::Command::new(exe);
+    if let Some(dir) = turm.current_dir().filter(|dir| dir.is_dir()) {
+        command.current_dir(dir);
+    }
+    spawn(&mut command);
+}
+
+/// Shows a notification with `notify-send`, for when the GUI can't send it
+/// itself
+pub fn notify_send(notification: &Notification) {
+    spawn(
+        std::process::Command::new("notify-send")
+            .arg("--app-name=Turm")
+            .arg(notification.summary())
+            .arg(&notification.body),
+    );
+}
+
+/// Runs a program without waiting for it
+fn spawn(command: &mut std::process::Command) {
+    match command.spawn() {
+        // Reap the child once it exits
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("Failed to run {:?}: {e}", command.get_program()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ansi::Ansi;
+
+    #[test]
+    fn test_window_title() {
+        let mut t = Turm::new(10, 3);
+        t.parse(Ansi::new().push(b"\x1b]2;vim\x07"));
+        assert_eq!(window_title(&mut t), "💩 vim 💩");
+
+        // The default template shows how the last command ended
+        t.parse(Ansi::new().push(b"\x1b]133;C\x07\x1b]133;D;2\x07"));
+        assert!(window_title(&mut t).starts_with("💩 vim 💩 ✗ 2 "));
+
+        t.title_template = Some(String::from("{title} in {cwd}"));
+        t.parse(Ansi::new().push(b"\x1b]7;file:///tmp\x07"));
+        assert_eq!(window_title(&mut t), "vim in /tmp");
+    }
+
+    #[test]
+    fn test_lone_cells() {
+        let mut t = Turm::new(6, 1);
+        t.parse(Ansi::new().push("e\u{301}x中y".as_bytes()));
+
+        // Each cell is one character of the text, clusters and double width
+        // characters are drawn over it
+        assert!(t.grid.sections().text.starts_with(" x  y \n"));
+        let cells: Vec<_> = lone_cells(&t)
+            .into_iter()
+            .map(|(row, column, _, text)| (row, column, text))
+            .collect();
+        assert_eq!(
+            cells,
+            [(0, 0, String::from("e\u{301}")), (0, 2, String::from("中"))]
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    grid::cell::{Cell, CellWidth},
+    turm::Turm,
+};
+
+/// Boundary between cells, a selection ends before the cell at `end`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Text selected with the mouse
+#[derive(Clone, Debug)]
+pub struct Selection {
+    pub start: Position,
+    pub end: Position,
+    pub active: bool,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self {
+            start: Position { x: 0, y: 0 },
+            end: Position { x: 0, y: 0 },
+            active: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_position_selected(&self, x: usize, y: usize) -> bool {
+        if !self.active {
+            return false;
+        }
+
+        let (start, end) = self.normalized();
+
+        if y < start.y || y > end.y {
+            return false;
+        }
+
+        if y == start.y && y == end.y {
+            return x >= start.x && x < end.x;
+        } else if y == start.y {
+            return x >= start.x;
+        } else if y == end.y {
+            return x < end.x;
+        }
+
+        true
+    }
+
+    fn normalized(&self) -> (Position, Position) {
+        if self.start.y > self.end.y || (self.start.y == self.end.y && self.start.x > self.end.x) {
+            (self.end, self.start)
+        } else {
+            (self.start, self.end)
+        }
+    }
+
+    /// Whether the cell is selected, double width characters are selected
+    /// with either of their halves
+    fn selects_cell(&self, terminal: &Turm, x: usize, y: usize) -> bool {
+        match terminal.grid[y][x].width {
+            CellWidth::Normal => self.is_position_selected(x, y),
+            CellWidth::Wide => {
+                self.is_position_selected(x, y) || self.is_position_selected(x + 1, y)
+            }
+            CellWidth::Spacer => false,
+        }
+    }
+
+    /// Returns the selected cells of each selected row, without the trailing
+    /// blanks
+    fn rows<'a>(&self, terminal: &'a Turm) -> Vec<Vec<&'a Cell>> {
+        let (start, end) = self.normalized();
+
+        (start.y..=end.y.min(terminal.lines.saturating_sub(1)))
+            .map(|y| {
+                let mut cells: Vec<_> = (0..terminal.columns)
+                    .filter(|&x| self.selects_cell(terminal, x, y))
+                    .map(|x| &terminal.grid[y][x])
+                    .collect();
+                let len = cells
+                    .iter()
+                    .rposition(|cell| cell.c.is_some_and(|c| c != ' ') || cell.tab)
+                    .map_or(0, |i| i + 1);
+                cells.truncate(len);
+                cells
+            })
+            .collect()
+    }
+
+    /// Returns the selected text, tabs are copied as tabs
+    pub fn text(&self, terminal: &Turm) -> String {
+        if !self.active {
+            return String::new();
+        }
+
+        let lines: Vec<String> = self
+            .rows(terminal)
+            .into_iter()
+            .map(|cells| {
+                let mut line = String::new();
+                let mut in_tab = false;
+                for cell in cells {
+                    if cell.tab {
+                        line.push('\t');
+                        in_tab = true;
+                    } else if let Some(text) = terminal.grid.cell_text(cell) {
+                        line.push_str(&text);
+                        in_tab = false;
+                    } else if !in_tab {
+                        // The cells skipped by a tab are part of it
+                        line.push(' ');
+                    }
+                }
+                line
+            })
+            .collect();
+
+        lines.join("\n")
+    }
+
+    /// Returns the selected text as HTML, hyperlinks become links
+    pub fn html(&self, terminal: &Turm) -> String {
+        if !self.active {
+            return String::new();
+        }
+
+        let mut html = String::from("<pre>");
+        for (i, cells) in self.rows(terminal).into_iter().enumerate() {
+            if i > 0 {
+                html.push('\n');
+            }
+
+            let mut current_link = None;
+            let mut in_tab = false;
+            for cell in cells {
+                if cell.hyperlink != current_link {
+                    if current_link.is_some() {
+                        html.push_str("</a>");
+                    }
+                    if let Some(link) = cell.hyperlink {
+                        let uri = escape_html(&terminal.grid.hyperlink(link).uri);
+                        html.push_str(&format!("<a href=\"{uri}\">"));
+                    }
+                    current_link = cell.hyperlink;
+                }
+
+                if cell.tab {
+                    html.push('\t');
+                    in_tab = true;
+                } else if let Some(text) = terminal.grid.cell_text(cell) {
+                    html.push_str(&escape_html(&text));
+                    in_tab = false;
+                } else if !in_tab {
+                    html.push(' ');
+                }
+            }
+            if current_link.is_some() {
+                html.push_str("</a>");
+            }
+        }
+        html.push_str("</pre>");
+
+        html
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ansi::Ansi;
+
+    fn select(start: (usize, usize), end: (usize, usize)) -> Selection {
+        Selection {
+            start: Position {
+                x: start.0,
+                y: start.1,
+            },
+            end: Position { x: end.0, y: end.1 },
+            active: true,
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let mut t = Turm::new(20, 3);
+        t.parse(Ansi::new().push(b"a\tb\x1b[2;3Hc  \r\n"));
+
+        // Tabs stay tabs, the trailing blanks of a row are left out
+        assert_eq!(select((0, 0), (20, 1)).text(&t), "a\tb\n  c");
+        assert_eq!(select((6, 1), (1, 0)).text(&t), "\tb\n  c");
+        assert_eq!(Selection::new().text(&t), "");
+    }
+
+    #[test]
+    fn test_html() {
+        let mut t = Turm::new(20, 2);
+        t.parse(Ansi::new().push(b"a<\x1b]8;;http://x?a&b\x1b\\link\x1b]8;;\x1b\\ b"));
+        assert_eq!(
+            select((0, 0), (20, 0)).html(&t),
+            "<pre>a&lt;<a href=\"http://x?a&amp;b\">link</a> b</pre>"
+        );
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut t = Turm::new(10, 2);
+        t.parse(Ansi::new().push("中a文".as_bytes()));
+
+        // Either half selects the whole character
+        assert_eq!(select((1, 0), (3, 0)).text(&t), "中a");
+        assert_eq!(select((0, 0), (1, 0)).text(&t), "中");
+        assert_eq!(select((3, 0), (4, 0)).text(&t), "文");
+    }
+}
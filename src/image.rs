@@ -0,0 +1,91 @@
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use crate::graphics;
+
+/// An image shown in the terminal, decoded from Sixel or another graphics
+/// protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Non premultiplied RGBA pixels, row by row
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Decodes an image file, the format is guessed from its content. Images
+    /// larger than `graphics::MAX_SIZE` or needing more than `max_alloc`
+    /// bytes are refused.
+    pub fn decode(data: &[u8], max_alloc: usize) -> image::ImageResult<Self> {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(graphics::MAX_SIZE as u32);
+        limits.max_image_height = Some(graphics::MAX_SIZE as u32);
+        limits.max_alloc = Some(max_alloc as u64);
+
+        let mut reader = image::ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        reader.limits(limits);
+        let image = reader.decode()?.to_rgba8();
+
+        Ok(Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            rgba: image.into_raw(),
+        })
+    }
+
+    /// Returns the part of the image in a rectangle, clipped to the image
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+
+        let rgba = (y..y + height)
+            .flat_map(|row| {
+                let start = (row * self.width + x) * 4;
+                &self.rgba[start..start + width * 4]
+            })
+            .copied()
+            .collect();
+
+        Self {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    /// Returns the number of columns and rows of cells covered by the image
+    /// when a cell is `cell_size` pixels
+    pub fn cells(&self, cell_size: (usize, usize)) -> (usize, usize) {
+        (
+            self.width.div_ceil(cell_size.0.max(1)),
+            self.height.div_ceil(cell_size.1.max(1)),
+        )
+    }
+}
+
+/// An image placed on the grid, with the size in image pixels of the cells it
+/// covers
+#[derive(Debug)]
+pub struct Placement {
+    pub image: Arc<Image>,
+    pub cell_size: (usize, usize),
+    /// Negative values are drawn below the text
+    pub z: i32,
+    /// Image and placement ids of a kitty graphics protocol placement
+    pub kitty: Option<(u32, u32)>,
+}
+
+/// Returns a new image id, unique across both screens so the GUIs can cache
+/// their textures by id
+pub fn next_id() -> u32 {
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
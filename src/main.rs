@@ -6,12 +6,20 @@ use gui::Gui;
 use turm::Turm;
 
 mod ansi;
+mod charset;
+mod clipboard;
 mod color;
 mod font;
+mod graphics;
 mod grid;
 mod gui;
+mod image;
+mod modes;
+mod mouse;
+mod notification;
 mod terminal_gui_input;
 mod terminal_io;
+mod terminfo;
 mod turm;
 
 fn main() {
@@ -34,7 +42,17 @@ fn main() {
             let cols: usize = 92;
             let rows: usize = 34;
 
-            let turm_arc = Arc::new(Mutex::new(Turm::new(cols, rows)));
+            let mut turm = Turm::new(cols, rows);
+            turm.shell_pid = Some(child.as_raw());
+            // Letting applications read the clipboard is opt-in
+            turm.allow_clipboard_read = std::env::var_os("TURM_ALLOW_CLIPBOARD_READ").is_some();
+            turm.notify_only_unfocused = std::env::var_os("TURM_NOTIFY_UNFOCUSED").is_some();
+            turm.ambiguous_wide = std::env::var_os("TURM_AMBIGUOUS_WIDE").is_some();
+            turm.scrollback_on_clear = std::env::var_os("TURM_SCROLLBACK_ON_CLEAR").is_some();
+            turm.answerback = std::env::var("TURM_ANSWERBACK").unwrap_or_default();
+            turm.title_template = std::env::var("TURM_TITLE").ok();
+
+            let turm_arc = Arc::new(Mutex::new(turm));
 
             // Create and run the GUI implementation
             // let gui = EguiImpl::new(fd, turm_arc, cols, rows);
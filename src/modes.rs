@@ -0,0 +1,250 @@
+/// ANSI modes, set with `CSI Pm h` and reset with `CSI Pm l`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiMode {
+    /// IRM
+    Insert,
+    /// LNM
+    LineFeedNewLine,
+}
+
+/// DEC private modes, set with `CSI ? Pm h` and reset with `CSI ? Pm l`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecMode {
+    /// DECCKM
+    CursorKeys,
+    /// DECSCNM
+    ReverseVideo,
+    /// DECOM
+    Origin,
+    /// DECAWM
+    AutoWrap,
+    X10Mouse,
+    /// DECTCEM
+    ShowCursor,
+    /// DECNRCM, allows the national replacement character sets
+    NationalCharsets,
+    NormalMouse,
+    ButtonEventMouse,
+    AnyEventMouse,
+    FocusEvents,
+    Utf8Mouse,
+    SgrMouse,
+    AlternateScroll,
+    UrxvtMouse,
+    /// Alternate screen buffer
+    AltScreen,
+    /// Alternate screen buffer, cleared when leaving it
+    AltScreenClear,
+    /// Saves the cursor on set and restores it on reset
+    SaveCursor,
+    /// Saves the cursor and switches to a cleared alternate screen
+    AltScreenSaveCursor,
+    BracketedPaste,
+    SynchronizedOutput,
+    /// Characters joined into a grapheme cluster share cells, with the
+    /// width of the cluster
+    GraphemeClusters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Ansi(AnsiMode),
+    Dec(DecMode),
+}
+
+impl Mode {
+    /// Maps a mode number, `private` is true for DEC private modes.
+    pub fn from_param(private: bool, param: usize) -> Option<Self> {
+        if !private {
+            return match param {
+                4 => Some(Self::Ansi(AnsiMode::Insert)),
+                20 => Some(Self::Ansi(AnsiMode::LineFeedNewLine)),
+                _ => None,
+            };
+        }
+
+        let mode = match param {
+            1 => DecMode::CursorKeys,
+            5 => DecMode::ReverseVideo,
+            6 => DecMode::Origin,
+            7 => DecMode::AutoWrap,
+            9 => DecMode::X10Mouse,
+            25 => DecMode::ShowCursor,
+            42 => DecMode::NationalCharsets,
+            47 => DecMode::AltScreen,
+            1000 => DecMode::NormalMouse,
+            1002 => DecMode::ButtonEventMouse,
+            1003 => DecMode::AnyEventMouse,
+            1004 => DecMode::FocusEvents,
+            1005 => DecMode::Utf8Mouse,
+            1006 => DecMode::SgrMouse,
+            1007 => DecMode::AlternateScroll,
+            1015 => DecMode::UrxvtMouse,
+            1047 => DecMode::AltScreenClear,
+            1048 => DecMode::SaveCursor,
+            1049 => DecMode::AltScreenSaveCursor,
+            2004 => DecMode::BracketedPaste,
+            2026 => DecMode::SynchronizedOutput,
+            2027 => DecMode::GraphemeClusters,
+            _ => return None,
+        };
+
+        Some(Self::Dec(mode))
+    }
+}
+
+/// Which mouse events are reported to the application
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    None,
+    /// Button presses only
+    X10,
+    /// Button presses and releases
+    Normal,
+    /// Presses, releases and motion while a button is pressed
+    ButtonEvent,
+    /// Presses, releases and all motion
+    AnyEvent,
+}
+
+/// How mouse events are encoded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEncoding {
+    #[default]
+    Default,
+    Utf8,
+    Sgr,
+    Urxvt,
+}
+
+/// State of the terminal modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modes {
+    pub insert: bool,
+    pub line_feed_new_line: bool,
+    pub cursor_keys: bool,
+    pub reverse_video: bool,
+    pub origin: bool,
+    pub auto_wrap: bool,
+    pub show_cursor: bool,
+    pub national_charsets: bool,
+    pub mouse_tracking: MouseTracking,
+    pub mouse_encoding: MouseEncoding,
+    pub focus_events: bool,
+    pub alternate_scroll: bool,
+    pub alt_screen: bool,
+    pub bracketed_paste: bool,
+    pub synchronized_output: bool,
+    pub grapheme_clusters: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            insert: false,
+            line_feed_new_line: false,
+            cursor_keys: false,
+            reverse_video: false,
+            origin: false,
+            auto_wrap: true,
+            show_cursor: true,
+            national_charsets: false,
+            mouse_tracking: MouseTracking::None,
+            mouse_encoding: MouseEncoding::Default,
+            focus_events: false,
+            alternate_scroll: false,
+            alt_screen: false,
+            bracketed_paste: false,
+            synchronized_output: false,
+            grapheme_clusters: false,
+        }
+    }
+}
+
+impl Modes {
+    pub fn set(&mut self, mode: Mode, value: bool) {
+        let tracking = |current, tracking| match value {
+            true => tracking,
+            false if current == tracking => MouseTracking::None,
+            false => current,
+        };
+        let encoding = |current, encoding| match value {
+            true => encoding,
+            false if current == encoding => MouseEncoding::Default,
+            false => current,
+        };
+
+        match mode {
+            Mode::Ansi(AnsiMode::Insert) => self.insert = value,
+            Mode::Ansi(AnsiMode::LineFeedNewLine) => self.line_feed_new_line = value,
+            Mode::Dec(DecMode::CursorKeys) => self.cursor_keys = value,
+            Mode::Dec(DecMode::ReverseVideo) => self.reverse_video = value,
+            Mode::Dec(DecMode::Origin) => self.origin = value,
+            Mode::Dec(DecMode::AutoWrap) => self.auto_wrap = value,
+            Mode::Dec(DecMode::ShowCursor) => self.show_cursor = value,
+            Mode::Dec(DecMode::NationalCharsets) => self.national_charsets = value,
+            Mode::Dec(DecMode::X10Mouse) => {
+                self.mouse_tracking = tracking(self.mouse_tracking, MouseTracking::X10)
+            }
+            Mode::Dec(DecMode::NormalMouse) => {
+                self.mouse_tracking = tracking(self.mouse_tracking, MouseTracking::Normal)
+            }
+            Mode::Dec(DecMode::ButtonEventMouse) => {
+                self.mouse_tracking = tracking(self.mouse_tracking, MouseTracking::ButtonEvent)
+            }
+            Mode::Dec(DecMode::AnyEventMouse) => {
+                self.mouse_tracking = tracking(self.mouse_tracking, MouseTracking::AnyEvent)
+            }
+            Mode::Dec(DecMode::Utf8Mouse) => {
+                self.mouse_encoding = encoding(self.mouse_encoding, MouseEncoding::Utf8)
+            }
+            Mode::Dec(DecMode::SgrMouse) => {
+                self.mouse_encoding = encoding(self.mouse_encoding, MouseEncoding::Sgr)
+            }
+            Mode::Dec(DecMode::UrxvtMouse) => {
+                self.mouse_encoding = encoding(self.mouse_encoding, MouseEncoding::Urxvt)
+            }
+            Mode::Dec(DecMode::FocusEvents) => self.focus_events = value,
+            Mode::Dec(DecMode::AlternateScroll) => self.alternate_scroll = value,
+            Mode::Dec(
+                DecMode::AltScreen | DecMode::AltScreenClear | DecMode::AltScreenSaveCursor,
+            ) => self.alt_screen = value,
+            Mode::Dec(DecMode::SaveCursor) => {}
+            Mode::Dec(DecMode::BracketedPaste) => self.bracketed_paste = value,
+            Mode::Dec(DecMode::SynchronizedOutput) => self.synchronized_output = value,
+            Mode::Dec(DecMode::GraphemeClusters) => self.grapheme_clusters = value,
+        }
+    }
+
+    pub fn get(&self, mode: Mode) -> bool {
+        match mode {
+            Mode::Ansi(AnsiMode::Insert) => self.insert,
+            Mode::Ansi(AnsiMode::LineFeedNewLine) => self.line_feed_new_line,
+            Mode::Dec(DecMode::CursorKeys) => self.cursor_keys,
+            Mode::Dec(DecMode::ReverseVideo) => self.reverse_video,
+            Mode::Dec(DecMode::Origin) => self.origin,
+            Mode::Dec(DecMode::AutoWrap) => self.auto_wrap,
+            Mode::Dec(DecMode::ShowCursor) => self.show_cursor,
+            Mode::Dec(DecMode::NationalCharsets) => self.national_charsets,
+            Mode::Dec(DecMode::X10Mouse) => self.mouse_tracking == MouseTracking::X10,
+            Mode::Dec(DecMode::NormalMouse) => self.mouse_tracking == MouseTracking::Normal,
+            Mode::Dec(DecMode::ButtonEventMouse) => {
+                self.mouse_tracking == MouseTracking::ButtonEvent
+            }
+            Mode::Dec(DecMode::AnyEventMouse) => self.mouse_tracking == MouseTracking::AnyEvent,
+            Mode::Dec(DecMode::Utf8Mouse) => self.mouse_encoding == MouseEncoding::Utf8,
+            Mode::Dec(DecMode::SgrMouse) => self.mouse_encoding == MouseEncoding::Sgr,
+            Mode::Dec(DecMode::UrxvtMouse) => self.mouse_encoding == MouseEncoding::Urxvt,
+            Mode::Dec(DecMode::FocusEvents) => self.focus_events,
+            Mode::Dec(DecMode::AlternateScroll) => self.alternate_scroll,
+            Mode::Dec(
+                DecMode::AltScreen | DecMode::AltScreenClear | DecMode::AltScreenSaveCursor,
+            ) => self.alt_screen,
+            Mode::Dec(DecMode::SaveCursor) => false,
+            Mode::Dec(DecMode::BracketedPaste) => self.bracketed_paste,
+            Mode::Dec(DecMode::SynchronizedOutput) => self.synchronized_output,
+            Mode::Dec(DecMode::GraphemeClusters) => self.grapheme_clusters,
+        }
+    }
+}
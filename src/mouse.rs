@@ -0,0 +1,173 @@
+//! Encoding of the mouse events reported to applications that enabled mouse
+//! tracking. https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+
+use crate::modes::{Modes, MouseEncoding, MouseTracking};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+}
+
+impl MouseButton {
+    fn code(self) -> u32 {
+        match self {
+            Self::Left => 0,
+            Self::Middle => 1,
+            Self::Right => 2,
+            Self::WheelUp => 64,
+            Self::WheelDown => 65,
+        }
+    }
+
+    fn is_wheel(self) -> bool {
+        matches!(self, Self::WheelUp | Self::WheelDown)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    /// The pointer moved to another cell, with the button held if any
+    Motion,
+}
+
+/// A mouse event on the cell at `column` and `line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    pub button: Option<MouseButton>,
+    pub column: usize,
+    pub line: usize,
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl MouseEvent {
+    /// Returns the report of the event for the tracking and encoding modes,
+    /// `None` if the application didn't ask for it
+    pub fn encode(&self, modes: &Modes) -> Option<Vec<u8>> {
+        let reported = match (modes.mouse_tracking, self.action) {
+            (MouseTracking::None, _) => false,
+            (MouseTracking::X10, action) => action == MouseAction::Press,
+            // Wheels have no release
+            (_, MouseAction::Release) => self.button.is_some_and(|b| !b.is_wheel()),
+            (_, MouseAction::Press) => self.button.is_some(),
+            (MouseTracking::Normal, MouseAction::Motion) => false,
+            (MouseTracking::ButtonEvent, MouseAction::Motion) => self.button.is_some(),
+            (MouseTracking::AnyEvent, MouseAction::Motion) => true,
+        };
+        if !reported {
+            return None;
+        }
+
+        // Motion without a button reports the release code
+        let mut code = match self.button {
+            Some(button) if self.action != MouseAction::Release => button.code(),
+            Some(button) if modes.mouse_encoding == MouseEncoding::Sgr => button.code(),
+            _ => 3,
+        };
+        if self.action == MouseAction::Motion {
+            code += 32;
+        }
+        // X10 reports no modifiers
+        if modes.mouse_tracking != MouseTracking::X10 {
+            code += 4 * self.shift as u32 + 8 * self.alt as u32 + 16 * self.ctrl as u32;
+        }
+        let (x, y) = (self.column + 1, self.line + 1);
+
+        match modes.mouse_encoding {
+            MouseEncoding::Sgr => {
+                let end = if self.action == MouseAction::Release {
+                    'm'
+                } else {
+                    'M'
+                };
+                Some(format!("\x1b[<{code};{x};{y}{end}").into_bytes())
+            }
+            MouseEncoding::Urxvt => Some(format!("\x1b[{};{x};{y}M", code + 32).into_bytes()),
+            MouseEncoding::Utf8 => {
+                let mut report = String::from("\x1b[M");
+                for value in [code, x as u32, y as u32] {
+                    report.push(char::from_u32(value + 32).filter(|c| c.len_utf8() <= 2)?);
+                }
+                Some(report.into_bytes())
+            }
+            // Coordinates past 223 don't fit in a byte
+            MouseEncoding::Default => {
+                let byte = |value: usize| u8::try_from(value + 32).ok();
+                Some(vec![
+                    0x1b,
+                    b'[',
+                    b'M',
+                    byte(code as usize)?,
+                    byte(x)?,
+                    byte(y)?,
+                ])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(action: MouseAction, button: Option<MouseButton>) -> MouseEvent {
+        MouseEvent {
+            action,
+            button,
+            column: 2,
+            line: 0,
+            shift: false,
+            alt: false,
+            ctrl: true,
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut modes = Modes::default();
+        let press = event(MouseAction::Press, Some(MouseButton::Left));
+        let release = event(MouseAction::Release, Some(MouseButton::Left));
+        let drag = event(MouseAction::Motion, Some(MouseButton::Left));
+        let motion = event(MouseAction::Motion, None);
+        assert_eq!(press.encode(&modes), None);
+
+        modes.mouse_tracking = MouseTracking::X10;
+        assert_eq!(press.encode(&modes), Some(b"\x1b[M #!".to_vec()));
+        assert_eq!(release.encode(&modes), None);
+
+        modes.mouse_tracking = MouseTracking::Normal;
+        assert_eq!(release.encode(&modes), Some(b"\x1b[M3#!".to_vec()));
+        assert_eq!(drag.encode(&modes), None);
+
+        modes.mouse_tracking = MouseTracking::ButtonEvent;
+        modes.mouse_encoding = MouseEncoding::Sgr;
+        assert_eq!(drag.encode(&modes), Some(b"\x1b[<48;3;1M".to_vec()));
+        assert_eq!(release.encode(&modes), Some(b"\x1b[<16;3;1m".to_vec()));
+        assert_eq!(motion.encode(&modes), None);
+
+        modes.mouse_tracking = MouseTracking::AnyEvent;
+        modes.mouse_encoding = MouseEncoding::Urxvt;
+        assert_eq!(motion.encode(&modes), Some(b"\x1b[83;3;1M".to_vec()));
+
+        // Columns past 223 need the UTF-8 encoding
+        let far = MouseEvent {
+            column: 300,
+            ..press
+        };
+        modes.mouse_encoding = MouseEncoding::Default;
+        assert_eq!(far.encode(&modes), None);
+        modes.mouse_encoding = MouseEncoding::Utf8;
+        assert_eq!(
+            far.encode(&modes),
+            Some("\x1b[M0\u{14d}!".as_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// A desktop notification sent by an application, shown by the GUI
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Empty when the application only sent a message
+    pub title: String,
+    pub body: String,
+}
+
+impl Notification {
+    /// Returns the title to show, with a fallback for notifications that only
+    /// have a body
+    pub fn summary(&self) -> &str {
+        if self.title.is_empty() {
+            "Turm"
+        } else {
+            &self.title
+        }
+    }
+}
+
+/// Which part of an OSC 99 notification a chunk carries
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    #[default]
+    Title,
+    Body,
+}
+
+/// A part of an OSC 99 notification, the chunks of a notification are added
+/// up until one of them is done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationChunk {
+    pub id: Option<String>,
+    pub done: bool,
+    pub payload: Payload,
+    pub text: String,
+}
+
+impl NotificationChunk {
+    /// Parses the `key=value` metadata of OSC 99, separated by ':', and its
+    /// payload. Unsupported payload types like queries are ignored.
+    pub fn parse(metadata: &[u8], payload: &[u8]) -> Option<Self> {
+        let mut chunk = Self {
+            id: None,
+            done: true,
+            payload: Payload::Title,
+            text: String::new(),
+        };
+        let mut base64 = false;
+
+        for param in metadata.split(|b| *b == b':') {
+            let Some(eq) = param.iter().position(|b| *b == b'=') else {
+                continue;
+            };
+            let (key, value) = (&param[..eq], &param[eq + 1..]);
+            match key {
+                b"i" => chunk.id = Some(String::from_utf8_lossy(value).into_owned()),
+                b"d" => chunk.done = value != b"0",
+                b"e" => base64 = value == b"1",
+                b"p" => {
+                    chunk.payload = match value {
+                        b"title" => Payload::Title,
+                        b"body" => Payload::Body,
+                        _ => return None,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        chunk.text = if base64 {
+            String::from_utf8_lossy(&STANDARD.decode(payload).ok()?).into_owned()
+        } else {
+            String::from_utf8_lossy(payload).into_owned()
+        };
+
+        Some(chunk)
+    }
+}
@@ -9,7 +9,11 @@ use std::{
 
 use egui::{Event, InputState, Key, Modifiers};
 
-use crate::turm::Turm;
+use crate::{
+    modes::{Modes, MouseTracking},
+    mouse::MouseEvent,
+    turm::Turm,
+};
 
 pub enum TerminalGuiInputMessage {
     Text(Vec<u8>),
@@ -52,9 +56,7 @@ impl TerminalGuiInput {
         thread::spawn(move || loop {
             if let Ok(input) = rx.recv() {
                 match input {
-                    TerminalGuiInputMessage::Text(text) => {
-                        let _ = nix::unistd::write(write_fd_raw, &text);
-                    }
+                    TerminalGuiInputMessage::Text(text) => write_all(write_fd_raw, &text),
                     TerminalGuiInputMessage::ScrollUp(delta) => {
                         let mut t = turm.lock().unwrap();
                         t.scroll_up(delta, false);
@@ -68,7 +70,9 @@ impl TerminalGuiInput {
         });
     }
 
-    pub fn write_input_to_terminal(&self, input: &InputState) {
+    /// Encodes the GUI events and sends them to the terminal, `modes` are the
+    /// current terminal modes that change how keys are encoded.
+    pub fn write_input_to_terminal(&self, input: &InputState, modes: &Modes) {
         for event in &input.events {
             let text = match event {
                 Event::Text(text) => Some(text.as_str()),
@@ -81,27 +85,47 @@ impl TerminalGuiInput {
                     key: Key::Enter,
                     pressed: true,
                     ..
-                } => Some("\r"),
+                } => Some(if modes.line_feed_new_line {
+                    "\r\n"
+                } else {
+                    "\r"
+                }),
                 Event::Key {
                     key: Key::ArrowUp,
                     pressed: true,
                     ..
-                } => Some("\x1bOA"),
+                } => Some(if modes.cursor_keys {
+                    "\x1bOA"
+                } else {
+                    "\x1b[A"
+                }),
                 Event::Key {
                     key: Key::ArrowDown,
                     pressed: true,
                     ..
-                } => Some("\x1bOB"),
+                } => Some(if modes.cursor_keys {
+                    "\x1bOB"
+                } else {
+                    "\x1b[B"
+                }),
                 Event::Key {
                     key: Key::ArrowRight,
                     pressed: true,
                     ..
-                } => Some("\x1bOC"),
+                } => Some(if modes.cursor_keys {
+                    "\x1bOC"
+                } else {
+                    "\x1b[C"
+                }),
                 Event::Key {
                     key: Key::ArrowLeft,
                     pressed: true,
                     ..
-                } => Some("\x1bOD"),
+                } => Some(if modes.cursor_keys {
+                    "\x1bOD"
+                } else {
+                    "\x1b[D"
+                }),
                 Event::Key {
                     key: Key::Tab,
                     pressed: true,
@@ -125,6 +149,39 @@ impl TerminalGuiInput {
                     let _ = self.tx.send(TerminalGuiInputMessage::Text(vec![m]));
                     None
                 }
+                Event::Paste(text) => {
+                    let mut bytes = Vec::with_capacity(text.len() + 12);
+                    if modes.bracketed_paste {
+                        // The application must not be able to stop the paste early
+                        bytes.extend_from_slice(b"\x1b[200~");
+                        bytes.extend(text.replace("\x1b[201~", "").bytes());
+                        bytes.extend_from_slice(b"\x1b[201~");
+                    } else {
+                        bytes.extend(text.bytes());
+                    }
+                    let _ = self.tx.send(TerminalGuiInputMessage::Text(bytes));
+                    None
+                }
+                Event::WindowFocused(focused) if modes.focus_events => {
+                    Some(if *focused { "\x1b[I" } else { "\x1b[O" })
+                }
+                // The GUI reports the wheel as mouse buttons
+                Event::MouseWheel { .. } if modes.mouse_tracking != MouseTracking::None => None,
+                // The alternate screen has no scrollback, applications get
+                // arrow keys instead if they asked for it
+                Event::MouseWheel { delta, .. } if modes.alt_screen && modes.alternate_scroll => {
+                    let key: &[u8] = match (delta.y > 0.0, modes.cursor_keys) {
+                        (true, true) => b"\x1bOA",
+                        (true, false) => b"\x1b[A",
+                        (false, true) => b"\x1bOB",
+                        (false, false) => b"\x1b[B",
+                    };
+                    let lines = (delta.y.abs() as usize).max(1);
+                    let _ = self
+                        .tx
+                        .send(TerminalGuiInputMessage::Text(key.repeat(lines)));
+                    None
+                }
                 Event::MouseWheel {
                     unit: _,
                     delta,
@@ -149,4 +206,38 @@ impl TerminalGuiInput {
             }
         }
     }
+
+    /// Queues bytes to write to the terminal, such as the replies to the
+    /// queries of the application
+    pub fn write(&self, bytes: Vec<u8>) {
+        let _ = self.tx.send(TerminalGuiInputMessage::Text(bytes));
+    }
+
+    /// Reports a mouse event to the application, if its mouse tracking mode
+    /// asks for it
+    pub fn write_mouse_to_terminal(&self, event: &MouseEvent, modes: &Modes) {
+        if let Some(report) = event.encode(modes) {
+            let _ = self.tx.send(TerminalGuiInputMessage::Text(report));
+        }
+    }
+}
+
+/// Writes all of `data` to the non-blocking `fd`, waiting for the terminal
+/// to take more whenever it is full
+fn write_all(fd: i32, mut data: &[u8]) {
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data) {
+            Ok(written) => data = &data[written..],
+            Err(nix::errno::Errno::EAGAIN) => {
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLOUT,
+                    revents: 0,
+                };
+                unsafe { libc::poll(&mut pollfd, 1, -1) };
+            }
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(_) => return,
+        }
+    }
 }
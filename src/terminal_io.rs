@@ -2,10 +2,14 @@ use std::{
     ops::DerefMut,
     os::fd::{AsRawFd, OwnedFd},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::{ansi::Ansi, turm::Turm};
+use crate::{ansi::Ansi, terminal_gui_input::TerminalGuiInput, turm::Turm};
+
+/// Time after which the screen is drawn even though the application didn't
+/// end its synchronized update, in case it died or forgot to
+const SYNCHRONIZED_OUTPUT_TIMEOUT: Duration = Duration::from_millis(150);
 
 unsafe fn set_nonblocking(fd: i32) {
     use libc::{fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
@@ -16,11 +20,19 @@ pub struct TerminalIO {
     ansi: Ansi,
     fd: OwnedFd,
     turm: Arc<Mutex<Turm>>,
+    /// Writes the replies to the application, so that this thread keeps
+    /// reading while the application doesn't read them
+    input: TerminalGuiInput,
 }
 
 impl TerminalIO {
-    pub fn new(ansi: Ansi, fd: OwnedFd, turm: Arc<Mutex<Turm>>) -> Self {
-        Self { ansi, fd, turm }
+    pub fn new(ansi: Ansi, fd: OwnedFd, turm: Arc<Mutex<Turm>>, input: TerminalGuiInput) -> Self {
+        Self {
+            ansi,
+            fd,
+            turm,
+            input,
+        }
     }
 
     pub fn start_io<F>(&mut self, repaint: F)
@@ -39,6 +51,7 @@ impl TerminalIO {
 
         let mut events = polling::Events::new();
         let timeout = Duration::new(0, 10_000_000); // 10ms
+        let mut synchronized_since = None;
 
         loop {
             let mut buf = vec![0u8; 1024];
@@ -53,15 +66,20 @@ impl TerminalIO {
             let mut turm1 = self.turm.lock().unwrap();
             let turm = turm1.deref_mut();
 
+            let mut updated = false;
             for _ in events.iter() {
+                updated = true;
                 loop {
                     let ret = nix::unistd::read(self.fd.as_raw_fd(), &mut buf);
                     if let Ok(s) = ret {
                         if s != 0 {
-                            let n: Vec<char> =
-                                String::from_utf8_lossy(&buf[0..s]).chars().collect();
-                            let ansi_res = self.ansi.push(&n);
+                            let ansi_res = self.ansi.push(&buf[0..s]);
                             turm.parse(ansi_res);
+
+                            let responses = turm.take_responses();
+                            if !responses.is_empty() {
+                                self.input.write(responses);
+                            }
                         } else {
                             break;
                         }
@@ -69,8 +87,22 @@ impl TerminalIO {
                         break;
                     }
                 }
+            }
 
-                repaint();
+            // Applications ask us not to draw while they update the screen
+            // with synchronized output, but only for so long
+            if turm.modes.synchronized_output {
+                let since = *synchronized_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= SYNCHRONIZED_OUTPUT_TIMEOUT {
+                    turm.modes.synchronized_output = false;
+                    updated = true;
+                }
+            }
+            if !turm.modes.synchronized_output {
+                synchronized_since = None;
+                if updated {
+                    repaint();
+                }
             }
 
             drop(turm1);
@@ -0,0 +1,140 @@
+//! Capabilities of the `turm` terminfo entry, used to answer XTGETTCAP
+//! queries. The source in `res/turm.ti` is parsed once, so the answers always
+//! match what `tic` compiles.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+const SOURCE: &str = include_str!("../res/turm.ti");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capability {
+    Flag,
+    Number(u32),
+    String(Vec<u8>),
+}
+
+/// Returns a capability by its terminfo name. `TN` is the terminal name and
+/// `Co` an alias of `colors`, as in xterm.
+pub fn capability(name: &str) -> Option<Capability> {
+    static CAPABILITIES: OnceLock<HashMap<String, Capability>> = OnceLock::new();
+    let capabilities = CAPABILITIES.get_or_init(|| parse(SOURCE));
+
+    match name {
+        "TN" => Some(Capability::String(b"turm".to_vec())),
+        "Co" => capabilities.get("colors").cloned(),
+        name => capabilities.get(name).cloned(),
+    }
+}
+
+/// Parses the capabilities of a terminfo source with a single entry
+fn parse(source: &str) -> HashMap<String, Capability> {
+    let entry: String = source
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // The first field holds the names of the terminal
+    split_fields(&entry)
+        .into_iter()
+        .skip(1)
+        .filter_map(|field| {
+            if let Some((name, value)) = field.split_once('=') {
+                Some((name.to_string(), Capability::String(unescape(value))))
+            } else if let Some((name, value)) = field.split_once('#') {
+                Some((name.to_string(), Capability::Number(value.parse().ok()?)))
+            } else if field.ends_with('@') {
+                // Cancelled capability
+                None
+            } else {
+                Some((field.to_string(), Capability::Flag))
+            }
+        })
+        .collect()
+}
+
+/// Splits an entry on its unescaped commas
+fn split_fields(entry: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in entry.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                fields.push(entry[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    fields.into_iter().filter(|f| !f.is_empty()).collect()
+}
+
+/// Turns the escapes of a string capability into the bytes they stand for
+fn unescape(value: &str) -> Vec<u8> {
+    let mut res = vec![];
+    let mut bytes = value.bytes().peekable();
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => match bytes.next() {
+                Some(b'E' | b'e') => res.push(0x1b),
+                Some(b'n' | b'l') => res.push(b'\n'),
+                Some(b'r') => res.push(b'\r'),
+                Some(b't') => res.push(b'\t'),
+                Some(b'b') => res.push(0x08),
+                Some(b'f') => res.push(0x0c),
+                Some(b's') => res.push(b' '),
+                Some(d @ b'0'..=b'7') => {
+                    let mut n = (d - b'0') as u32;
+                    for _ in 0..2 {
+                        match bytes.peek() {
+                            Some(d @ b'0'..=b'7') => {
+                                n = n * 8 + (d - b'0') as u32;
+                                bytes.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    // \0 stands for a NUL, which can't be in a C string
+                    res.push(if n == 0 { 0x80 } else { n as u8 });
+                }
+                Some(b) => res.push(b),
+                None => {}
+            },
+            b'^' => match bytes.next() {
+                Some(b'?') => res.push(0x7f),
+                Some(b) => res.push(b & 0x1f),
+                None => res.push(b'^'),
+            },
+            b => res.push(b),
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        assert_eq!(capability("am"), Some(Capability::Flag));
+        assert_eq!(capability("Co"), Some(Capability::Number(256)));
+        assert_eq!(capability("bel"), Some(Capability::String(vec![0x07])));
+        assert_eq!(
+            capability("clear"),
+            Some(Capability::String(b"\x1b[H\x1b[J".to_vec()))
+        );
+        assert_eq!(
+            capability("Ms"),
+            Some(Capability::String(b"\x1b]52;%p1%s;%p2%s\x07".to_vec()))
+        );
+        assert_eq!(capability("nope"), None);
+    }
+}
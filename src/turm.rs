@@ -1,31 +1,152 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
 use crate::{
-    ansi::{AnsiOutput, GraphicRendition},
-    grid::cell::Style,
+    ansi::{
+        iterm::InlineImage, kitty::GraphicsCommand, sixel, AnsiOutput, ClearMode, CursorStyle,
+        GraphicRendition, SemanticMark,
+    },
+    charset::Charsets,
+    clipboard::ClipboardEvent,
+    color::{Color, Palette, PaletteColor, TermColor},
+    graphics::{self, KittyImages},
+    grid::cell::{Cell, CellWidth, ImageCell, Style, UnderlineStyle},
+    grid::row::{LineSize, PromptMark, Zone},
     grid::Grid,
+    image::{Image, Placement},
+    modes::{DecMode, Mode, Modes},
+    notification::{Notification, NotificationChunk, Payload},
+    terminfo::{self, Capability},
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct CursorPos {
     pub x: usize,
     pub y: usize,
 }
 
+/// State saved by DECSC and restored by DECRC
+#[derive(Debug, Default, Clone, Copy)]
+struct SavedCursor {
+    /// A column past the last one keeps the pending wrap
+    pos: CursorPos,
+    style: Style,
+    origin: bool,
+    charsets: Charsets,
+}
+
+/// A command run from a prompt marked by the shell
+#[derive(Debug, Clone, Copy)]
+pub struct CommandResult {
+    pub exit_status: Option<i32>,
+    pub duration: Duration,
+}
+
+/// The last printed kitty placeholder, the diacritics that follow it give its
+/// row, column and the high byte of the image id
+#[derive(Debug, Clone, Copy)]
+struct Placeholder {
+    y: usize,
+    x: usize,
+    row: Option<u16>,
+    column: Option<u16>,
+    high_byte: u32,
+    /// Number of diacritics read so far
+    diacritics: usize,
+}
+
 #[derive(Debug, Default)]
 pub struct Cursor {
     pub pos: CursorPos,
+    /// Shape of the cursor, set with DECSCUSR
+    pub style: CursorStyle,
 }
 
 #[derive(Debug)]
 pub struct Turm {
     pub cursor: Cursor,
-    pub show_cursor: bool,
+    pub modes: Modes,
+    /// Colors used by the renderers, applications can change them
+    pub palette: Palette,
+    /// The grid currently displayed, either the primary or the alternate one
     pub grid: Grid,
+    /// The grid that is not displayed
+    inactive_grid: Grid,
+    /// Cursor saved on the displayed screen
+    saved_cursor: SavedCursor,
+    /// Cursor saved on the screen that is not displayed
+    inactive_saved_cursor: SavedCursor,
 
     current_style: Style,
+    /// Character sets translating the printed characters
+    charsets: Charsets,
+    /// Whether each column has a tab stop
+    tab_stops: Vec<bool>,
+    /// Semantic zone of the rows the shell writes to
+    zone: Zone,
+    /// When the output of the running command started
+    command_started: Option<Instant>,
+    /// The last command that finished, as marked by the shell
+    pub last_command: Option<CommandResult>,
+    /// Hyperlink of the printed characters, interned in `grid`
+    hyperlink: Option<u32>,
+    /// Last printed character, repeated by REP
+    last_char: Option<char>,
     pub lines: usize,
     pub columns: usize,
-    needs_wrap: bool,
+    /// Top margin of the scrolling region
+    scroll_top: usize,
+    /// Bottom margin of the scrolling region, inclusive
+    scroll_bottom: usize,
     pub title: String,
+    /// Working directory reported by the shell with OSC 7
+    working_directory: Option<PathBuf>,
+    /// Working directory of the shell last read from `/proc`, and when
+    proc_directory: Option<(Instant, Option<PathBuf>)>,
+    /// Process id of the shell, used to find its working directory when it
+    /// doesn't report it
+    pub shell_pid: Option<i32>,
+    /// Whether characters of ambiguous East Asian width take two columns, as
+    /// in CJK locales
+    pub ambiguous_wide: bool,
+    /// Whether `CSI 2J` moves the screen contents into the scrollback instead
+    /// of discarding them
+    pub scrollback_on_clear: bool,
+    /// Whether applications can read the clipboard with OSC 52
+    pub allow_clipboard_read: bool,
+    /// Clipboard requests waiting for the GUI
+    clipboard_events: Vec<ClipboardEvent>,
+    /// Notifications waiting for the GUI
+    notifications: Vec<Notification>,
+    /// OSC 99 notification whose chunks are still arriving, with its id
+    pending_notification: Option<(Option<String>, Notification)>,
+    /// Whether notifications are only shown while the window is unfocused
+    pub notify_only_unfocused: bool,
+    /// Whether the bell rang since the GUI last checked
+    bell: bool,
+    /// Size in pixels of a cell, set by the GUI
+    pub cell_size: (usize, usize),
+    /// Images transmitted with the kitty graphics protocol
+    kitty_images: KittyImages,
+    /// Maximum size in bytes of the stored kitty images
+    pub image_quota: usize,
+    /// Placeholder that following diacritics apply to
+    placeholder: Option<Placeholder>,
+    /// Grid images shown by placeholders, by kitty image id
+    placeholder_images: HashMap<u32, u32>,
+    /// Message sent back when the application sends ENQ
+    pub answerback: String,
+    /// Template of the window title, the GUI has a default one
+    pub title_template: Option<String>,
+    /// Bytes to send back to the application, e.g. replies to queries
+    responses: Vec<u8>,
 }
 
 impl Turm {
@@ -33,123 +154,1234 @@ impl Turm {
         Self {
             cursor: Cursor::default(),
             grid: Grid::new(columns, lines),
+            inactive_grid: Grid::new_alternate(columns, lines),
+            saved_cursor: SavedCursor::default(),
+            inactive_saved_cursor: SavedCursor::default(),
             current_style: Style::default(),
+            charsets: Charsets::default(),
+            tab_stops: default_tab_stops(0, columns).collect(),
+            zone: Zone::Unknown,
+            command_started: None,
+            last_command: None,
+            hyperlink: None,
+            last_char: None,
             lines,
             columns,
-            needs_wrap: false,
-            show_cursor: true,
+            scroll_top: 0,
+            scroll_bottom: lines - 1,
+            modes: Modes::default(),
+            palette: Palette::default(),
             title: String::new(),
+            working_directory: None,
+            proc_directory: None,
+            shell_pid: None,
+            ambiguous_wide: false,
+            scrollback_on_clear: false,
+            allow_clipboard_read: false,
+            clipboard_events: Vec::new(),
+            notifications: Vec::new(),
+            pending_notification: None,
+            notify_only_unfocused: false,
+            bell: false,
+            cell_size: (8, 16),
+            kitty_images: KittyImages::default(),
+            image_quota: graphics::DEFAULT_QUOTA,
+            placeholder: None,
+            placeholder_images: HashMap::new(),
+            answerback: String::new(),
+            title_template: None,
+            responses: Vec::new(),
         }
     }
 
     pub fn parse(&mut self, ansi: Vec<AnsiOutput>) {
-        for q in &ansi {
+        for q in ansi {
             match q {
                 AnsiOutput::Text(str) => {
                     for c in str {
-                        self.input(*c);
+                        self.input(c);
                     }
                 }
                 AnsiOutput::Title(title) => {
                     if !title.is_empty() {
-                        self.title = title.clone();
+                        self.title = title;
                     }
                 }
-                AnsiOutput::ClearToEndOfLine(_mode) => self.clear_to_end_of_line(),
-                AnsiOutput::ClearToEOS => self.clear_to_eos(),
-                AnsiOutput::MoveCursor(x, y) => self.move_cursor(*x, *y),
-                AnsiOutput::MoveCursorHorizontal(x) => self.move_cursor(*x, self.cursor.pos.y),
+                AnsiOutput::EraseInLine(mode) => self.erase_in_line(mode),
+                AnsiOutput::EraseInDisplay(mode) => self.erase_in_display(mode),
+                AnsiOutput::MoveCursor(x, y) => self.goto(x, y),
+                AnsiOutput::MoveCursorHorizontal(x) => {
+                    self.move_cursor(x.min(self.columns - 1), self.cursor.pos.y);
+                    self.clamp_to_line();
+                }
                 AnsiOutput::CursorUp(amount) => {
-                    if self.cursor.pos.y >= *amount {
-                        self.move_cursor(self.cursor.pos.x, self.cursor.pos.y - amount);
-                    }
+                    // The cursor stops at the top margin if it is below it
+                    let top = if self.cursor.pos.y >= self.scroll_top {
+                        self.scroll_top
+                    } else {
+                        0
+                    };
+                    let y = self.cursor.pos.y.saturating_sub(amount).max(top);
+                    self.move_cursor(self.cursor.pos.x.min(self.columns - 1), y);
+                    self.clamp_to_line();
                 }
                 AnsiOutput::CursorDown(amount) => {
-                    self.move_cursor(self.cursor.pos.x, self.cursor.pos.y + amount)
+                    // The cursor stops at the bottom margin if it is above it
+                    let bottom = if self.cursor.pos.y <= self.scroll_bottom {
+                        self.scroll_bottom
+                    } else {
+                        self.lines - 1
+                    };
+                    let y = (self.cursor.pos.y + amount).min(bottom);
+                    self.move_cursor(self.cursor.pos.x.min(self.columns - 1), y);
+                    self.clamp_to_line();
                 }
                 AnsiOutput::CursorForward(amount) => {
-                    self.move_cursor(self.cursor.pos.x + amount, self.cursor.pos.y)
+                    let x = (self.cursor.pos.x + amount).min(self.columns - 1);
+                    self.move_cursor(x, self.cursor.pos.y);
+                    self.clamp_to_line();
                 }
                 AnsiOutput::CursorBackward(amount) => {
-                    if amount <= &self.cursor.pos.x {
-                        self.move_cursor(self.cursor.pos.x - amount, self.cursor.pos.y);
-                    }
+                    let x = self.cursor.pos.x.min(self.columns - 1);
+                    self.move_cursor(x.saturating_sub(amount), self.cursor.pos.y);
                 }
-                AnsiOutput::HideCursor => self.show_cursor = false,
-                AnsiOutput::ShowCursor => self.show_cursor = true,
-                AnsiOutput::ScrollDown => self.scroll_down(1, false),
+                AnsiOutput::SetScrollingRegion(top, bottom) => {
+                    self.set_scrolling_region(top, bottom)
+                }
+                AnsiOutput::Index => self.linefeed(),
+                AnsiOutput::ReverseIndex => self.reverse_index(),
+                AnsiOutput::InsertLines(n) => self.insert_lines(n),
+                AnsiOutput::DeleteLines(n) => self.delete_lines(n),
+                AnsiOutput::ScrollUp(n) => self
+                    .grid
+                    .scroll_region_up(self.scroll_top..=self.scroll_bottom, n),
+                AnsiOutput::ScrollDown(n) => self
+                    .grid
+                    .scroll_region_down(self.scroll_top..=self.scroll_bottom, n),
+                AnsiOutput::SetMode(mode) => self.set_mode(mode, true),
+                AnsiOutput::ResetMode(mode) => self.set_mode(mode, false),
+                AnsiOutput::RequestMode { private, mode } => self.report_mode(private, mode),
                 AnsiOutput::Backspace => self.backspace(),
-                AnsiOutput::Sgr(c) => self.color(*c),
-                AnsiOutput::Bell => println!("DING DONG"),
+                AnsiOutput::Sgr(c) => self.color(c),
+                AnsiOutput::Bell => self.bell = true,
                 AnsiOutput::FillWithE => self.fill_with_e(),
+                AnsiOutput::LineSize(size) => self.set_line_size(size),
                 AnsiOutput::NextLine => self.next_line(),
-                AnsiOutput::DeleteCharacters(n) => self.delete_characters(*n),
+                AnsiOutput::DeleteCharacters(n) => self.delete_characters(n),
+                AnsiOutput::InsertCharacters(n) => self.insert_characters(n),
+                AnsiOutput::EraseCharacters(n) => self.erase_characters(n),
+                AnsiOutput::SetColor(color, value) => self.palette.set(color, value),
+                AnsiOutput::QueryColor {
+                    color,
+                    bell_terminated,
+                } => self.report_color(color, bell_terminated),
+                AnsiOutput::ResetColor(color) => self.palette.reset(color),
+                AnsiOutput::ResetPalette => {
+                    for i in 0..=255 {
+                        self.palette.reset(PaletteColor::Indexed(i));
+                    }
+                }
+                AnsiOutput::WorkingDirectory(path) => self.working_directory = Some(path),
+                AnsiOutput::SemanticPrompt(mark) => self.semantic_mark(mark),
+                AnsiOutput::Hyperlink(link) => {
+                    self.hyperlink = link.map(|link| self.grid.intern_hyperlink(link));
+                }
+                AnsiOutput::Notification(notification) => self.notifications.push(notification),
+                AnsiOutput::NotificationChunk(chunk) => self.notification_chunk(&chunk),
+                AnsiOutput::ClipboardStore(clipboard, text) => self
+                    .clipboard_events
+                    .push(ClipboardEvent::Store(clipboard, text)),
+                AnsiOutput::ClipboardLoad {
+                    clipboard,
+                    bell_terminated,
+                } => {
+                    if self.allow_clipboard_read {
+                        self.clipboard_events.push(ClipboardEvent::Load {
+                            clipboard,
+                            bell_terminated,
+                        });
+                    }
+                }
+                AnsiOutput::Enquiry => self.respond(&self.answerback.clone()),
+                // VT220 with Sixel and ANSI color
+                AnsiOutput::PrimaryDeviceAttributes => self.respond("\x1b[?62;4;22c"),
+                AnsiOutput::SecondaryDeviceAttributes => self.respond("\x1b[>1;10;0c"),
+                AnsiOutput::TertiaryDeviceAttributes => self.respond("\x1bP!|00000000\x1b\\"),
+                AnsiOutput::StatusReport => self.respond("\x1b[0n"),
+                AnsiOutput::CursorPositionReport { private } => self.report_cursor(private),
+                AnsiOutput::TerminalVersion => {
+                    self.respond(&format!("\x1bP>|turm {}\x1b\\", env!("CARGO_PKG_VERSION")))
+                }
+                AnsiOutput::RequestStatusString(setting) => self.report_status_string(&setting),
+                AnsiOutput::RequestTermcap(names) => {
+                    for name in names {
+                        self.report_termcap(&name);
+                    }
+                }
+                AnsiOutput::SetCursorStyle(style) => self.cursor.style = style,
+                AnsiOutput::Sixel(image) => self.place_sixel(image),
+                AnsiOutput::Graphics(cmd) => self.graphics(cmd),
+                AnsiOutput::InlineImage(image) => self.place_inline_image(image),
+                AnsiOutput::GraphicsAttributes { item, action } => {
+                    self.report_graphics_attribute(item, action)
+                }
+                AnsiOutput::DesignateCharset { index, charset } => {
+                    // National sets are only used in DECNRCM
+                    if !charset.is_national() || self.modes.national_charsets {
+                        self.charsets.sets[index] = charset;
+                    }
+                }
+                AnsiOutput::LockingShift(index) => self.charsets.gl = index,
+                AnsiOutput::SingleShift(index) => self.charsets.single_shift = Some(index),
+                AnsiOutput::SaveCursor => self.save_cursor(),
+                AnsiOutput::RestoreCursor => self.restore_cursor(),
+                AnsiOutput::SetTabStop => {
+                    let x = self.cursor.pos.x.min(self.columns - 1);
+                    self.tab_stops[x] = true;
+                }
+                AnsiOutput::ClearTabStop { all: false } => {
+                    let x = self.cursor.pos.x.min(self.columns - 1);
+                    self.tab_stops[x] = false;
+                }
+                AnsiOutput::ClearTabStop { all: true } => self.tab_stops.fill(false),
+                AnsiOutput::CursorForwardTab(n) => {
+                    for _ in 0..n {
+                        self.tab_forward();
+                    }
+                }
+                AnsiOutput::CursorBackwardTab(n) => {
+                    for _ in 0..n {
+                        self.tab_backward();
+                    }
+                }
+                AnsiOutput::RepeatCharacter(n) => {
+                    if let Some(c) = self.last_char {
+                        for _ in 0..n {
+                            self.input(c);
+                        }
+                    }
+                }
             }
         }
     }
 
-    fn delete_characters(&mut self, n: usize) {
-        let x = self.cursor.pos.x;
-        let mut y = self.cursor.pos.y;
-        for _ in 0..n {
-            self.grid[x][y].c = Some(' ');
-            y += 1;
-            if y == self.lines {
-                self.next_line();
+    /// Returns the bytes that need to be written back to the application
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Returns the working directory of the shell, as reported with OSC 7 or
+    /// read from `/proc`
+    pub fn current_dir(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.working_directory {
+            return Some(path.clone());
+        }
+
+        let pid = self.shell_pid?;
+        std::fs::read_link(format!("/proc/{pid}/cwd")).ok()
+    }
+
+    /// Returns the working directory of the shell like `current_dir`, but
+    /// reads `/proc` at most once a second, for the window title
+    pub fn cached_current_dir(&mut self) -> Option<PathBuf> {
+        if let Some(path) = &self.working_directory {
+            return Some(path.clone());
+        }
+
+        match &self.proc_directory {
+            Some((read, path)) if read.elapsed() < Duration::from_secs(1) => path.clone(),
+            _ => {
+                let path = self.current_dir();
+                self.proc_directory = Some((Instant::now(), path.clone()));
+                path
             }
         }
     }
 
+    /// Returns the clipboard requests the GUI needs to handle
+    pub fn take_clipboard_events(&mut self) -> Vec<ClipboardEvent> {
+        std::mem::take(&mut self.clipboard_events)
+    }
+
+    /// Returns the notifications the GUI needs to show
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// Returns whether the bell rang since the last call
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    /// Adds an OSC 99 chunk to the pending notification, a chunk with another
+    /// id starts a new notification
+    fn notification_chunk(&mut self, chunk: &NotificationChunk) {
+        if !matches!(&self.pending_notification, Some((id, _)) if *id == chunk.id) {
+            self.pending_notification = Some((chunk.id.clone(), Notification::default()));
+        }
+        let Some((_, notification)) = &mut self.pending_notification else {
+            return;
+        };
+
+        match chunk.payload {
+            Payload::Title => notification.title.push_str(&chunk.text),
+            Payload::Body => notification.body.push_str(&chunk.text),
+        }
+
+        if chunk.done {
+            if let Some((_, notification)) = self.pending_notification.take() {
+                self.notifications.push(notification);
+            }
+        }
+    }
+
+    /// Resizes the terminal and both of its screens
+    pub fn resize(&mut self, columns: usize, lines: usize) {
+        self.grid.resize(columns, lines);
+        self.inactive_grid.resize(columns, lines);
+        self.tab_stops.truncate(columns);
+        let old_columns = self.tab_stops.len();
+        self.tab_stops
+            .extend(default_tab_stops(old_columns, columns));
+        self.columns = columns;
+        self.lines = lines;
+
+        self.cursor.pos.x = self.cursor.pos.x.min(columns.saturating_sub(1));
+        self.cursor.pos.y = self.cursor.pos.y.min(lines.saturating_sub(1));
+        self.scroll_top = 0;
+        self.scroll_bottom = lines.saturating_sub(1);
+    }
+
+    fn set_mode(&mut self, mode: Mode, value: bool) {
+        match mode {
+            Mode::Dec(DecMode::Origin) => {
+                self.modes.origin = value;
+                self.goto(0, 0);
+            }
+            Mode::Dec(DecMode::AltScreen) => self.switch_screen(value),
+            Mode::Dec(DecMode::AltScreenClear) => {
+                if !value && self.modes.alt_screen {
+                    self.grid.clear();
+                }
+                self.switch_screen(value);
+            }
+            Mode::Dec(DecMode::SaveCursor) => {
+                if value {
+                    self.save_cursor();
+                } else {
+                    self.restore_cursor();
+                }
+            }
+            Mode::Dec(DecMode::AltScreenSaveCursor) => {
+                if value {
+                    self.save_cursor();
+                    self.switch_screen(true);
+                    self.grid.clear();
+                } else {
+                    self.switch_screen(false);
+                    self.restore_cursor();
+                }
+            }
+            _ => {}
+        }
+
+        self.modes.set(mode, value);
+    }
+
+    /// Switches between the primary and the alternate screen
+    fn switch_screen(&mut self, alternate: bool) {
+        if alternate != self.modes.alt_screen {
+            std::mem::swap(&mut self.grid, &mut self.inactive_grid);
+            std::mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+            self.modes.alt_screen = alternate;
+
+            // Hyperlinks are interned per screen
+            if let Some(id) = self.hyperlink {
+                let link = self.inactive_grid.hyperlink(id).clone();
+                self.hyperlink = Some(self.grid.intern_hyperlink(link));
+            }
+        }
+    }
+
+    /// Saves the cursor of the displayed screen, also known as DECSC
+    fn save_cursor(&mut self) {
+        self.saved_cursor = SavedCursor {
+            pos: self.cursor.pos,
+            style: self.current_style,
+            origin: self.modes.origin,
+            charsets: self.charsets,
+        };
+    }
+
+    /// Restores the cursor of the displayed screen, also known as DECRC. The
+    /// cursor goes home with the default style if nothing was saved.
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor;
+        self.cursor.pos.x = saved.pos.x.min(self.columns);
+        self.cursor.pos.y = saved.pos.y.min(self.lines - 1);
+        self.current_style = saved.style;
+        self.modes.origin = saved.origin;
+        self.charsets = saved.charsets;
+
+        // The region may have changed since, the cursor stays inside it
+        if self.modes.origin {
+            self.cursor.pos.y = self.cursor.pos.y.clamp(self.scroll_top, self.scroll_bottom);
+        }
+    }
+
+    fn respond(&mut self, response: &str) {
+        self.responses.extend_from_slice(response.as_bytes());
+    }
+
+    /// Answers an OSC color query
+    fn report_color(&mut self, color: PaletteColor, bell_terminated: bool) {
+        let spec = self.palette.get(color).to_spec();
+        let command = match color {
+            PaletteColor::Indexed(i) => format!("4;{i}"),
+            PaletteColor::Foreground => String::from("10"),
+            PaletteColor::Background => String::from("11"),
+            PaletteColor::Cursor => String::from("12"),
+            PaletteColor::SelectionBackground => String::from("17"),
+            PaletteColor::SelectionForeground => String::from("19"),
+        };
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+
+        self.respond(&format!("\x1b]{command};{spec}{terminator}"));
+    }
+
+    /// Answers a CPR, the position is relative to the scrolling region in
+    /// origin mode
+    fn report_cursor(&mut self, private: bool) {
+        let top = if self.modes.origin {
+            self.scroll_top
+        } else {
+            0
+        };
+        let x = self.cursor.pos.x.min(self.columns - 1) + 1;
+        let y = self.cursor.pos.y.saturating_sub(top) + 1;
+
+        let report = if private {
+            format!("\x1b[?{y};{x};1R")
+        } else {
+            format!("\x1b[{y};{x}R")
+        };
+        self.respond(&report);
+    }
+
+    /// Answers a DECRQM query
+    /// https://vt100.net/docs/vt510-rm/DECRQM.html
+    fn report_mode(&mut self, private: bool, mode: usize) {
+        let value = match Mode::from_param(private, mode) {
+            Some(m) if self.modes.get(m) => 1,
+            Some(_) => 2,
+            None => 0,
+        };
+        let prefix = if private { "?" } else { "" };
+        self.respond(&format!("\x1b[{prefix}{mode};{value}$y"));
+    }
+
+    /// Answers a DECRQSS query with the current value of a setting
+    /// https://vt100.net/docs/vt510-rm/DECRQSS.html
+    fn report_status_string(&mut self, setting: &[u8]) {
+        let value = match setting {
+            b"m" => Some(format!("{}m", self.current_style.sgr())),
+            b"r" => Some(format!(
+                "{};{}r",
+                self.scroll_top + 1,
+                self.scroll_bottom + 1
+            )),
+            b" q" => Some(format!("{} q", self.cursor.style.param())),
+            // VT220 level with 7-bit controls
+            b"\"p" => Some(String::from("62;1\"p")),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => self.respond(&format!("\x1bP1$r{value}\x1b\\")),
+            None => self.respond("\x1bP0$r\x1b\\"),
+        }
+    }
+
+    /// Answers a XTGETTCAP query for a capability of the terminfo entry, the
+    /// name and value are hex encoded
+    fn report_termcap(&mut self, name: &str) {
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02X}")).collect::<String>();
+
+        let response = match terminfo::capability(name) {
+            Some(Capability::Flag) => format!("\x1bP1+r{}\x1b\\", hex(name.as_bytes())),
+            Some(Capability::Number(n)) => format!(
+                "\x1bP1+r{}={}\x1b\\",
+                hex(name.as_bytes()),
+                hex(n.to_string().as_bytes())
+            ),
+            Some(Capability::String(value)) => {
+                format!("\x1bP1+r{}={}\x1b\\", hex(name.as_bytes()), hex(&value))
+            }
+            None => format!("\x1bP0+r{}\x1b\\", hex(name.as_bytes())),
+        };
+        self.respond(&response);
+    }
+
+    /// Draws an image at the cursor, one cell covering `cell_size` pixels of
+    /// the placed image. The cursor ends up on the last row of the image,
+    /// scrolling if needed. Returns the column of the image and its width in
+    /// cells.
+    fn place_image(&mut self, placement: Placement) -> (usize, usize) {
+        let (columns, rows) = placement.image.cells(placement.cell_size);
+        let id = self.grid.add_image(placement);
+        let left = self.cursor.pos.x.min(self.columns - 1);
+
+        for y in 0..rows {
+            if y > 0 {
+                self.linefeed();
+            }
+            for x in 0..columns.min(self.columns - left) {
+                self.grid[self.cursor.pos.y][left + x] = Cell {
+                    image: Some(ImageCell {
+                        id,
+                        x: x as u16,
+                        y: y as u16,
+                    }),
+                    ..Cell::new()
+                };
+            }
+        }
+
+        (left, columns)
+    }
+
+    /// Draws a Sixel image, the cursor moves to the line below it
+    fn place_sixel(&mut self, image: Image) {
+        let (left, _) = self.place_image(Placement {
+            image: Arc::new(image),
+            cell_size: self.cell_size,
+            z: 0,
+            kitty: None,
+        });
+        self.linefeed();
+        self.cursor.pos.x = left;
+    }
+
+    /// Draws an iTerm2 inline image at its requested size, the cursor moves
+    /// after its last row
+    fn place_inline_image(&mut self, image: InlineImage) {
+        let cell_size = image.cell_size(self.cell_size, self.columns, self.lines);
+        let (left, columns) = self.place_image(Placement {
+            image: Arc::new(image.image),
+            cell_size,
+            z: 0,
+            kitty: None,
+        });
+        self.cursor.pos.x = (left + columns).min(self.columns);
+    }
+
+    /// Handles a kitty graphics protocol command, once all its chunks arrived
+    fn graphics(&mut self, cmd: GraphicsCommand) {
+        let Some(cmd) = self.kitty_images.chunk(cmd, self.image_quota) else {
+            return;
+        };
+
+        let result = match cmd.action {
+            b't' | b'T' | b'q' => self.transmit_image(&cmd),
+            b'p' => self.put_image(cmd.id, &cmd),
+            b'd' => {
+                self.delete_images(&cmd);
+                return;
+            }
+            _ => Err("EINVAL:unsupported action"),
+        };
+
+        // Only commands with an id or a number get a response
+        if cmd.id == 0 && cmd.number == 0 {
+            return;
+        }
+        let (id, message) = match result {
+            Ok(_) if cmd.quiet >= 1 => return,
+            Err(_) if cmd.quiet >= 2 => return,
+            Ok(id) => (id, "OK"),
+            Err(e) => (cmd.id, e),
+        };
+
+        let mut keys = vec![];
+        if id != 0 {
+            keys.push(format!("i={id}"));
+        }
+        if cmd.number != 0 {
+            keys.push(format!("I={}", cmd.number));
+        }
+        if cmd.placement_id != 0 {
+            keys.push(format!("p={}", cmd.placement_id));
+        }
+        self.respond(&format!("\x1b_G{};{message}\x1b\\", keys.join(",")));
+    }
+
+    /// Loads the image of a transmission and stores it, queries only check
+    /// that it can be loaded. Returns the id of the image.
+    fn transmit_image(&mut self, cmd: &GraphicsCommand) -> Result<u32, &'static str> {
+        let image = graphics::load(cmd, self.image_quota)?;
+        if cmd.action == b'q' {
+            return Ok(cmd.id);
+        }
+
+        let id = self
+            .kitty_images
+            .insert(cmd.id, cmd.number, image, self.image_quota);
+        self.placeholder_images.remove(&id);
+        if cmd.action == b'T' {
+            self.put_image(id, cmd)?;
+        }
+        Ok(id)
+    }
+
+    /// Places a stored image at the cursor, or records how to show it for
+    /// placeholders. Returns the id of the image.
+    fn put_image(&mut self, id: u32, cmd: &GraphicsCommand) -> Result<u32, &'static str> {
+        let Some((id, image)) = self.kitty_images.get(id, cmd.number) else {
+            return Err("ENOENT:image not found");
+        };
+
+        if cmd.unicode_placeholder {
+            self.kitty_images
+                .set_virtual_placement(id, cmd.columns, cmd.rows);
+            self.placeholder_images.remove(&id);
+            return Ok(id);
+        }
+
+        let image = match cmd.source {
+            (0, 0, 0, 0) => image,
+            (x, y, w, h) => {
+                let w = if w == 0 { image.width } else { w as usize };
+                let h = if h == 0 { image.height } else { h as usize };
+                Arc::new(image.crop(x as usize, y as usize, w, h))
+            }
+        };
+        if image.width == 0 || image.height == 0 {
+            return Err("EINVAL:empty source rectangle");
+        }
+
+        // A placement with the same ids is replaced
+        if cmd.placement_id != 0 {
+            let old = self
+                .grid
+                .image_ids(|p| p.kitty == Some((id, cmd.placement_id)));
+            self.grid.remove_images(&old);
+        }
+
+        let cell_size = self.image_cell_size(&image, cmd.columns, cmd.rows);
+        let pos = self.cursor.pos;
+        let (left, columns) = self.place_image(Placement {
+            image,
+            cell_size,
+            z: cmd.z,
+            kitty: Some((id, cmd.placement_id)),
+        });
+        if cmd.keep_cursor {
+            self.cursor.pos = pos;
+        } else {
+            self.cursor.pos.x = (left + columns).min(self.columns);
+        }
+
+        Ok(id)
+    }
+
+    /// Returns the size in image pixels of a cell for an image shown in
+    /// `columns` and `rows` cells, 0 keeps the aspect ratio of the cells
+    fn image_cell_size(&self, image: &Image, columns: u32, rows: u32) -> (usize, usize) {
+        let (cell_width, cell_height) = (self.cell_size.0.max(1), self.cell_size.1.max(1));
+        let (columns, rows) = (columns as usize, rows as usize);
+
+        match (columns, rows) {
+            (0, 0) => (cell_width, cell_height),
+            (columns, 0) => {
+                let width = image.width.div_ceil(columns);
+                (width, (width * cell_height / cell_width).max(1))
+            }
+            (0, rows) => {
+                let height = image.height.div_ceil(rows);
+                ((height * cell_width / cell_height).max(1), height)
+            }
+            (columns, rows) => (image.width.div_ceil(columns), image.height.div_ceil(rows)),
+        }
+    }
+
+    /// Deletes kitty placements, an uppercase `d` also frees their images
+    fn delete_images(&mut self, cmd: &GraphicsCommand) {
+        let cursor = (self.cursor.pos.y, self.cursor.pos.x.min(self.columns - 1));
+        // Cell positions are 1 based
+        let (x, y) = (
+            (cmd.source.0 as usize).wrapping_sub(1),
+            (cmd.source.1 as usize).wrapping_sub(1),
+        );
+        let id = match cmd.delete.to_ascii_lowercase() {
+            b'n' => self.kitty_images.id_of_number(cmd.number),
+            _ => Some(cmd.id),
+        };
+        let by_id = |p: &Placement| {
+            matches!(p.kitty, Some((i, placement)) if Some(i) == id
+                && (cmd.placement_id == 0 || placement == cmd.placement_id))
+        };
+
+        let ids = match cmd.delete.to_ascii_lowercase() {
+            b'a' => self.grid.screen_image_ids(|_, _, p| p.kitty.is_some()),
+            b'i' | b'n' => self.grid.image_ids(by_id),
+            b'c' => self
+                .grid
+                .screen_image_ids(|row, col, p| p.kitty.is_some() && (row, col) == cursor),
+            b'p' => self
+                .grid
+                .screen_image_ids(|row, col, p| p.kitty.is_some() && (row, col) == (y, x)),
+            b'q' => self.grid.screen_image_ids(|row, col, p| {
+                p.kitty.is_some() && (row, col) == (y, x) && p.z == cmd.z
+            }),
+            b'x' => self
+                .grid
+                .screen_image_ids(|_, col, p| p.kitty.is_some() && col == x),
+            b'y' => self
+                .grid
+                .screen_image_ids(|row, _, p| p.kitty.is_some() && row == y),
+            b'z' => self.grid.image_ids(|p| p.kitty.is_some() && p.z == cmd.z),
+            _ => return,
+        };
+
+        if cmd.delete.is_ascii_uppercase() {
+            let mut images: HashSet<u32> = ids
+                .iter()
+                .filter_map(|id| Some(self.grid.image(*id)?.kitty?.0))
+                .collect();
+            if matches!(cmd.delete, b'I' | b'N') {
+                images.extend(id);
+            }
+            for image in images {
+                self.kitty_images.remove(image);
+            }
+        }
+        self.grid.remove_images(&ids);
+    }
+
+    /// Shows the piece of a kitty image at the last printed placeholder. The
+    /// image id is in the foreground color, the row and column in the
+    /// diacritics that follow or, when missing, continue the cell on the
+    /// left.
+    fn resolve_placeholder(&mut self) {
+        let Some(placeholder) = self.placeholder else {
+            return;
+        };
+        let (y, x) = (placeholder.y, placeholder.x);
+        let cell = self.grid[y][x];
+
+        let id = match cell.style.fg {
+            TermColor::Rgb(Color([r, g, b])) => u32::from_be_bytes([0, r, g, b]),
+            TermColor::Indexed(i) => i as u32,
+            TermColor::Default => 0,
+        } | placeholder.high_byte << 24;
+
+        let left = x
+            .checked_sub(1)
+            .map(|x| self.grid[y][x])
+            .filter(|left| left.c == Some(graphics::PLACEHOLDER) && left.style.fg == cell.style.fg)
+            .and_then(|left| left.image);
+        let (row, column) = match (placeholder.row, placeholder.column, left) {
+            (Some(row), Some(column), _) => (row, column),
+            (Some(row), None, Some(left)) if left.y == row => (row, left.x + 1),
+            (Some(row), None, _) => (row, 0),
+            (None, _, Some(left)) => (left.y, left.x + 1),
+            (None, _, None) => (0, 0),
+        };
+
+        self.grid[y][x].image = self.placeholder_image(id).map(|id| ImageCell {
+            id,
+            x: column,
+            y: row,
+        });
+    }
+
+    /// Returns the grid image showing a kitty image with placeholders
+    fn placeholder_image(&mut self, id: u32) -> Option<u32> {
+        if let Some(grid_id) = self.placeholder_images.get(&id) {
+            if self.grid.image(*grid_id).is_some() {
+                return Some(*grid_id);
+            }
+        }
+
+        let (columns, rows) = self.kitty_images.virtual_placement(id)?;
+        let (_, image) = self.kitty_images.get(id, 0)?;
+        let (columns, rows) = match (columns, rows) {
+            (0, 0) => {
+                let (columns, rows) = image.cells(self.cell_size);
+                (columns as u32, rows as u32)
+            }
+            size => size,
+        };
+        let cell_size = self.image_cell_size(&image, columns, rows);
+
+        let grid_id = self.grid.add_image(Placement {
+            image,
+            cell_size,
+            z: 0,
+            kitty: Some((id, 0)),
+        });
+        self.placeholder_images.insert(id, grid_id);
+        Some(grid_id)
+    }
+
+    /// Answers a XTSMGRAPHICS query, setting the attributes is not supported
+    /// so only reads succeed
+    fn report_graphics_attribute(&mut self, item: usize, action: usize) {
+        let value = match (item, action) {
+            // Read and read maximum
+            (1, 1 | 4) => Some(sixel::COLOR_REGISTERS.to_string()),
+            (2, 1 | 4) => {
+                let width = (self.columns * self.cell_size.0).min(sixel::MAX_SIZE);
+                let height = (self.lines * self.cell_size.1).min(sixel::MAX_SIZE);
+                Some(format!("{width};{height}"))
+            }
+            _ => None,
+        };
+
+        match value {
+            Some(value) => self.respond(&format!("\x1b[?{item};0;{value}S")),
+            // Unknown item, or failure for the actions we don't support
+            None if item > 2 || item == 0 => self.respond(&format!("\x1b[?{item};1;0S")),
+            None => self.respond(&format!("\x1b[?{item};3;0S")),
+        }
+    }
+
+    /// Returns an empty cell with the current background color, used for the
+    /// cells cleared by editing functions
+    fn blank_cell(&self) -> Cell {
+        Cell {
+            style: Style {
+                bg: self.current_style.bg,
+                ..Style::default()
+            },
+            ..Cell::new()
+        }
+    }
+
+    /// Deletes characters at the cursor, the rest of the line shifts left.
+    /// Also known as DCH.
+    fn delete_characters(&mut self, n: usize) {
+        let columns = self.line_columns(self.cursor.pos.y);
+        let x = self.cursor.pos.x.min(columns - 1);
+        let n = n.min(columns - x);
+        let blank = self.blank_cell();
+
+        let row = &mut self.grid[self.cursor.pos.y].inner[x..columns];
+        row.rotate_left(n);
+        let len = row.len();
+        row[len - n..].fill(blank);
+        self.grid[self.cursor.pos.y].fix_wide_chars();
+    }
+
+    /// Inserts blank characters at the cursor, the rest of the line shifts
+    /// right and characters past the right margin are lost. Also known as ICH.
+    fn insert_characters(&mut self, n: usize) {
+        let columns = self.line_columns(self.cursor.pos.y);
+        let x = self.cursor.pos.x.min(columns - 1);
+        let n = n.min(columns - x);
+        let blank = self.blank_cell();
+
+        let row = &mut self.grid[self.cursor.pos.y].inner[x..columns];
+        row.rotate_right(n);
+        row[..n].fill(blank);
+        self.grid[self.cursor.pos.y].fix_wide_chars();
+    }
+
+    /// Erases characters from the cursor without moving the rest of the line.
+    /// Also known as ECH.
+    fn erase_characters(&mut self, n: usize) {
+        let columns = self.line_columns(self.cursor.pos.y);
+        let x = self.cursor.pos.x.min(columns - 1);
+        let end = (x + n).min(columns);
+        let blank = self.blank_cell();
+
+        self.grid[self.cursor.pos.y].inner[x..end].fill(blank);
+        self.grid[self.cursor.pos.y].fix_wide_chars();
+    }
+
     /// Fills the entier screen with 'E's, also known as DECALN
     /// https://www.vt100.net/docs/vt510-rm/DECALN.html
     fn fill_with_e(&mut self) {
         for i in 0..self.columns {
             for j in 0..self.lines {
                 self.grid[j][i].c = Some('E');
+                self.grid[j][i].grapheme = None;
                 self.grid[j][i].style = Style::default();
+                self.grid[j][i].width = CellWidth::Normal;
             }
         }
+        for j in 0..self.lines {
+            self.grid[j].size = LineSize::Single;
+        }
         self.move_cursor(0, 0);
     }
 
+    /// Records an OSC 133 mark of the shell
+    fn semantic_mark(&mut self, mark: SemanticMark) {
+        let row = &mut self.grid[self.cursor.pos.y];
+        match mark {
+            SemanticMark::PromptStart => {
+                row.prompt = Some(PromptMark::default());
+                row.zone = Zone::Prompt;
+                self.zone = Zone::Prompt;
+            }
+            SemanticMark::CommandStart => self.zone = Zone::Command,
+            SemanticMark::OutputStart => {
+                // The shell marks the output once the cursor is on its first row
+                if row.zone != Zone::Prompt {
+                    row.zone = Zone::Output;
+                }
+                self.zone = Zone::Output;
+                self.command_started = Some(Instant::now());
+            }
+            SemanticMark::CommandEnd(exit_status) => {
+                self.zone = Zone::Unknown;
+                if let Some(started) = self.command_started.take() {
+                    self.last_command = Some(CommandResult {
+                        exit_status,
+                        duration: started.elapsed(),
+                    });
+                }
+                if let Some(prompt) = self.grid.last_prompt_mut(self.cursor.pos.y) {
+                    prompt.exit_status = exit_status;
+                }
+            }
+        }
+    }
+
+    /// Returns the output of the last command, if the shell marks prompts
+    pub fn last_command_output(&self) -> Option<String> {
+        self.grid.last_command_output()
+    }
+
+    /// Scrolls the viewport to the previous or next prompt
+    pub fn scroll_to_prompt(&mut self, previous: bool) {
+        self.grid.scroll_to_prompt(previous);
+    }
+
     fn next_line(&mut self) {
-        self.move_cursor(0, self.cursor.pos.y + 1);
-        if self.cursor.pos.y == self.lines {
-            self.move_cursor(0, self.lines - 1);
-            self.scroll_up(1, true);
+        self.cursor.pos.x = 0;
+        self.linefeed();
+    }
+
+    /// Moves the cursor down, scrolling the scrolling region when the cursor
+    /// is at the bottom margin. Also known as IND.
+    fn linefeed(&mut self) {
+        self.cursor.pos.x = self.cursor.pos.x.min(self.columns - 1);
+        if self.cursor.pos.y == self.scroll_bottom {
+            self.grid
+                .scroll_region_up(self.scroll_top..=self.scroll_bottom, 1);
+        } else if self.cursor.pos.y + 1 < self.lines {
+            self.cursor.pos.y += 1;
+        }
+
+        // A new row belongs to the current zone of the shell, rows that were
+        // already marked keep their marks. Full screen applications are no
+        // part of the zones.
+        let row = &mut self.grid[self.cursor.pos.y];
+        if row.zone == Zone::Unknown && !self.modes.alt_screen {
+            row.zone = self.zone;
+        }
+        self.clamp_to_line();
+    }
+
+    /// Moves the cursor up, scrolling the scrolling region down when the
+    /// cursor is at the top margin. Also known as RI.
+    fn reverse_index(&mut self) {
+        self.cursor.pos.x = self.cursor.pos.x.min(self.columns - 1);
+        if self.cursor.pos.y == self.scroll_top {
+            self.grid
+                .scroll_region_down(self.scroll_top..=self.scroll_bottom, 1);
+        } else if self.cursor.pos.y > 0 {
+            self.cursor.pos.y -= 1;
+        }
+    }
+
+    /// Sets the top and bottom margins, also known as DECSTBM. The margins are
+    /// 1-based and 0 means the default.
+    /// https://vt100.net/docs/vt510-rm/DECSTBM.html
+    fn set_scrolling_region(&mut self, top: usize, bottom: usize) {
+        let top = top.max(1) - 1;
+        let bottom = if bottom == 0 {
+            self.lines - 1
+        } else {
+            bottom.min(self.lines) - 1
+        };
+
+        if top >= bottom {
+            return;
+        }
+
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+        self.goto(0, 0);
+    }
+
+    /// Inserts blank lines at the cursor, the lines below are pushed down
+    /// inside the scrolling region. Also known as IL.
+    fn insert_lines(&mut self, n: usize) {
+        let y = self.cursor.pos.y;
+        if y < self.scroll_top || y > self.scroll_bottom {
+            return;
+        }
+
+        self.grid.scroll_region_down(y..=self.scroll_bottom, n);
+        self.cursor.pos.x = 0;
+    }
+
+    /// Deletes lines at the cursor, the lines below are pulled up inside the
+    /// scrolling region. Also known as DL.
+    fn delete_lines(&mut self, n: usize) {
+        let y = self.cursor.pos.y;
+        if y < self.scroll_top || y > self.scroll_bottom {
+            return;
+        }
+
+        self.grid.scroll_region_up(y..=self.scroll_bottom, n);
+        self.cursor.pos.x = 0;
+    }
+
+    /// Moves the cursor to `x`, `y`, relative to the scrolling region in
+    /// origin mode. Also known as CUP.
+    fn goto(&mut self, x: usize, y: usize) {
+        let (top, bottom) = if self.modes.origin {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.lines - 1)
+        };
+
+        self.cursor.pos.x = x.min(self.columns - 1);
+        self.cursor.pos.y = (top + y).min(bottom);
+        self.clamp_to_line();
+    }
+
+    /// Returns the number of characters that fit on a row, double width rows
+    /// only use half of the columns
+    fn line_columns(&self, y: usize) -> usize {
+        if self.grid[y].size.is_double_width() {
+            (self.columns / 2).max(1)
+        } else {
+            self.columns
+        }
+    }
+
+    /// Keeps the cursor in the used half of a double width row
+    fn clamp_to_line(&mut self) {
+        let last = self.line_columns(self.cursor.pos.y) - 1;
+        self.cursor.pos.x = self.cursor.pos.x.min(last);
+    }
+
+    /// Changes the size of the characters of the cursor row, the characters
+    /// of the right half are lost when it becomes double width
+    fn set_line_size(&mut self, size: LineSize) {
+        let y = self.cursor.pos.y;
+        self.grid[y].size = size;
+        if size.is_double_width() {
+            let blank = self.blank_cell();
+            let columns = self.line_columns(y);
+            self.grid[y].inner[columns..].fill(blank);
+            self.clamp_to_line();
         }
     }
 
     pub fn input(&mut self, c: char) {
+        // Diacritics after a placeholder give its row, column and the high
+        // byte of the image id
+        if let Some(placeholder) = &mut self.placeholder {
+            if let Some(index) = graphics::diacritic_index(c) {
+                match placeholder.diacritics {
+                    0 => placeholder.row = Some(index as u16),
+                    1 => placeholder.column = Some(index as u16),
+                    2 => placeholder.high_byte = index,
+                    _ => {}
+                }
+                placeholder.diacritics += 1;
+                self.resolve_placeholder();
+                return;
+            }
+        }
+        self.placeholder = None;
+
         if c == '\n' {
-            self.move_cursor(self.cursor.pos.x, self.cursor.pos.y + 1);
-            if self.cursor.pos.y == self.lines {
-                self.needs_wrap = true;
+            if self.modes.line_feed_new_line {
+                self.cursor.pos.x = 0;
             }
+            self.linefeed();
         } else if c == '\r' {
             self.move_cursor(0, self.cursor.pos.y);
         } else if c == '\t' {
-            self.move_cursor(self.cursor.pos.x + 4, self.cursor.pos.y);
+            let x = self.cursor.pos.x.min(self.columns - 1);
+            let cell = &mut self.grid[self.cursor.pos.y][x];
+            if cell.c.is_none() {
+                cell.tab = true;
+            }
+            self.tab_forward();
         } else {
-            if self.needs_wrap {
-                self.move_cursor(0, self.lines - 1);
-                self.scroll_up(1, true);
-                self.needs_wrap = false;
+            let c = self.charsets.translate(c);
+            if self.join_grapheme(c) {
+                return;
             }
+            let width = self.char_width(c);
 
-            if self.cursor.pos.x + 1 > self.columns {
-                self.move_cursor(0, self.cursor.pos.y + 1);
-                if self.cursor.pos.y == self.lines {
-                    self.move_cursor(0, self.lines - 1);
-                    self.scroll_up(1, true);
+            // The cursor sits past the last column after writing to it, the
+            // next character wraps. So does a double width character that
+            // doesn't fit in the last column.
+            let columns = self.line_columns(self.cursor.pos.y);
+            if self.cursor.pos.x + width > columns {
+                if self.modes.auto_wrap {
+                    self.next_line();
+                } else {
+                    // Without auto wrap the last columns get overwritten
+                    self.cursor.pos.x = columns - width;
                 }
             }
 
-            self.grid[self.cursor.pos.y][self.cursor.pos.x].c = Some(c);
-            self.grid[self.cursor.pos.y][self.cursor.pos.x].style = self.current_style;
+            if self.modes.insert {
+                self.insert_characters(width);
+            }
+
+            // Images drawn below the text stay under it
+            let (y, x) = (self.cursor.pos.y, self.cursor.pos.x);
+            let image = self.grid[y][x]
+                .image
+                .filter(|image| self.grid.image(image.id).is_some_and(|p| p.z < 0));
+            self.clear_wide_char(y, x);
+            self.grid[y][x] = Cell {
+                c: Some(c),
+                grapheme: None,
+                style: self.current_style,
+                tab: false,
+                hyperlink: self.hyperlink,
+                image,
+                width: CellWidth::Normal,
+            };
+            if width == 2 {
+                self.clear_wide_char(y, x + 1);
+                self.grid[y][x].width = CellWidth::Wide;
+                self.grid[y][x + 1] = Cell {
+                    style: self.current_style,
+                    hyperlink: self.hyperlink,
+                    width: CellWidth::Spacer,
+                    ..Cell::new()
+                };
+            }
+
+            if c == graphics::PLACEHOLDER {
+                self.placeholder = Some(Placeholder {
+                    y,
+                    x,
+                    row: None,
+                    column: None,
+                    high_byte: 0,
+                    diacritics: 0,
+                });
+                self.resolve_placeholder();
+            }
+
+            self.move_cursor(x + width, y);
+            self.last_char = Some(c);
+        }
+    }
+
+    /// Appends `c` to the grapheme cluster of the cell left of the cursor,
+    /// returns false if it starts a cluster of its own. Zero width
+    /// characters are always appended, in the grapheme cluster mode (2027)
+    /// so is anything Unicode joins into the cluster, which may then become
+    /// double width.
+    /// https://github.com/contour-terminal/terminal-unicode-core
+    fn join_grapheme(&mut self, c: char) -> bool {
+        let (y, mut x) = (self.cursor.pos.y, self.cursor.pos.x.min(self.columns));
+        if x == 0 {
+            return false;
+        }
+        x -= 1;
+        if self.grid[y][x].width == CellWidth::Spacer && x > 0 {
+            x -= 1;
+        }
+
+        let Some(mut grapheme) = self
+            .grid
+            .cell_text(&self.grid[y][x])
+            .map(|g| g.into_owned())
+        else {
+            return false;
+        };
+        grapheme.push(c);
+        let zero_width = c.width() == Some(0);
+        let joined = self.modes.grapheme_clusters && grapheme.graphemes(true).count() == 1;
+        if !zero_width && !joined {
+            return false;
+        }
+
+        let id = self.grid.intern_grapheme(grapheme);
+        self.grid[y][x].grapheme = Some(id);
 
-            self.move_cursor(self.cursor.pos.x + 1, self.cursor.pos.y);
+        // An emoji presentation selector makes the cluster double width
+        let columns = self.line_columns(y);
+        if self.modes.grapheme_clusters
+            && c == '\u{fe0f}'
+            && self.grid[y][x].width == CellWidth::Normal
+            && x + 1 < columns
+        {
+            self.clear_wide_char(y, x + 1);
+            self.grid[y][x].width = CellWidth::Wide;
+            self.grid[y][x + 1] = Cell {
+                style: self.grid[y][x].style,
+                hyperlink: self.grid[y][x].hyperlink,
+                width: CellWidth::Spacer,
+                ..Cell::new()
+            };
+            self.move_cursor(x + 2, y);
         }
+        true
+    }
+
+    /// Returns the number of columns taken by a printed character. Zero
+    /// width characters that don't join a cluster take a cell of their own.
+    fn char_width(&self, c: char) -> usize {
+        let width = if self.ambiguous_wide {
+            c.width_cjk()
+        } else {
+            c.width()
+        };
+
+        // Double width characters don't fit on a row of one character
+        match width {
+            Some(2) if self.line_columns(self.cursor.pos.y) >= 2 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Blanks the other half of the double width character at `x`, before
+    /// one of its halves gets overwritten
+    fn clear_wide_char(&mut self, y: usize, x: usize) {
+        let other = match self.grid[y][x].width {
+            CellWidth::Normal => return,
+            CellWidth::Wide => x + 1,
+            CellWidth::Spacer => x - 1,
+        };
+
+        if let Some(cell) = self.grid[y].inner.get_mut(other) {
+            cell.c = None;
+            cell.grapheme = None;
+            cell.width = CellWidth::Normal;
+        }
+    }
+
+    /// Moves the cursor to the next tab stop, or the last column. Also known
+    /// as HT.
+    fn tab_forward(&mut self) {
+        let columns = self.line_columns(self.cursor.pos.y);
+        let x = self.cursor.pos.x.min(columns - 1);
+        self.cursor.pos.x = (x + 1..columns)
+            .find(|&x| self.tab_stops[x])
+            .unwrap_or(columns - 1);
+    }
+
+    /// Moves the cursor to the previous tab stop, or the first column
+    fn tab_backward(&mut self) {
+        let x = self.cursor.pos.x.min(self.columns - 1);
+        self.cursor.pos.x = (0..x).rev().find(|&x| self.tab_stops[x]).unwrap_or(0);
     }
 
     pub fn backspace(&mut self) {
@@ -159,49 +1391,85 @@ impl Turm {
     }
 
     pub fn color(&mut self, c: GraphicRendition) {
-        if self.cursor.pos.x == self.columns || self.cursor.pos.y == self.lines {
-            return;
-        }
-
+        let style = &mut self.current_style;
         match c {
-            GraphicRendition::ForegroundColor(c) => {
-                self.grid[self.cursor.pos.y][self.cursor.pos.x].style.fg = c;
-                self.current_style.fg = c;
+            GraphicRendition::Reset => *style = Style::default(),
+            GraphicRendition::ForegroundColor(c) => style.fg = c,
+            GraphicRendition::BackgroundColor(c) => style.bg = c,
+            GraphicRendition::DefaultForeground => style.fg = TermColor::Default,
+            GraphicRendition::DefaultBackground => style.bg = TermColor::Default,
+            GraphicRendition::Bold => style.bold = true,
+            GraphicRendition::Dim => style.dim = true,
+            GraphicRendition::Italic => style.italics = true,
+            GraphicRendition::Underline(u) => style.underline = u,
+            GraphicRendition::UnderlineColor(c) => style.underline_color = Some(c),
+            GraphicRendition::DefaultUnderlineColor => style.underline_color = None,
+            GraphicRendition::Blink => style.blink = true,
+            GraphicRendition::Reverse => style.reverse = true,
+            GraphicRendition::Hidden => style.hidden = true,
+            GraphicRendition::Strikethrough => style.strikethrough = true,
+            GraphicRendition::Overline => style.overline = true,
+            GraphicRendition::NormalIntensity => {
+                style.bold = false;
+                style.dim = false;
             }
-            GraphicRendition::BackgroundColor(c) => {
-                self.grid[self.cursor.pos.y][self.cursor.pos.x].style.bg = c;
-                self.current_style.bg = c;
-            }
-            GraphicRendition::Bold => self.current_style.bold = true,
-            GraphicRendition::Reset => {
-                self.current_style = Style::default();
-            }
-            GraphicRendition::Underline => self.current_style.underline = true,
-            GraphicRendition::Italic => self.current_style.italics = true,
+            GraphicRendition::NotItalic => style.italics = false,
+            GraphicRendition::NotUnderlined => style.underline = UnderlineStyle::None,
+            GraphicRendition::NotBlinking => style.blink = false,
+            GraphicRendition::NotReversed => style.reverse = false,
+            GraphicRendition::NotHidden => style.hidden = false,
+            GraphicRendition::NotStrikethrough => style.strikethrough = false,
+            GraphicRendition::NotOverlined => style.overline = false,
         };
     }
 
-    pub fn clear_to_end_of_line(&mut self) {
-        for i in self.cursor.pos.x..self.columns {
-            self.grid[self.cursor.pos.y][i].c = None;
-            self.grid[self.cursor.pos.y][i].style = Style::default();
-        }
+    /// Erases part of the cursor line with the current background color.
+    /// Also known as EL.
+    fn erase_in_line(&mut self, mode: ClearMode) {
+        let x = self.cursor.pos.x.min(self.columns - 1);
+        let range = match mode {
+            ClearMode::ToEnd => x..self.columns,
+            ClearMode::ToBeginning => 0..x + 1,
+            ClearMode::Both => 0..self.columns,
+            ClearMode::Scrollback => return,
+        };
+
+        let blank = self.blank_cell();
+        self.grid[self.cursor.pos.y].inner[range].fill(blank);
+        self.grid[self.cursor.pos.y].fix_wide_chars();
     }
 
-    pub fn clear_to_eos(&mut self) {
-        let mut i = self.cursor.pos.x;
-        let mut j = self.cursor.pos.y;
-        loop {
-            self.grid[j][i].c = None;
-            self.grid[j][i].style = Style::default();
-            i += 1;
-            if i == self.columns {
-                i = 0;
-                j += 1;
+    /// Erases part of the screen with the current background color. Also
+    /// known as ED.
+    fn erase_in_display(&mut self, mode: ClearMode) {
+        let blank = self.blank_cell();
+        let rows = match mode {
+            ClearMode::ToEnd => {
+                self.erase_in_line(ClearMode::ToEnd);
+                self.cursor.pos.y + 1..self.lines
             }
-            if j == self.lines {
-                break;
+            ClearMode::ToBeginning => {
+                self.erase_in_line(ClearMode::ToBeginning);
+                0..self.cursor.pos.y
+            }
+            ClearMode::Both => {
+                if self.scrollback_on_clear {
+                    self.grid.push_to_scrollback();
+                }
+                0..self.lines
             }
+            ClearMode::Scrollback => {
+                self.grid.clear_scrollback();
+                return;
+            }
+        };
+
+        for y in rows {
+            let row = &mut self.grid[y];
+            row.inner.fill(blank);
+            row.zone = Zone::Unknown;
+            row.prompt = None;
+            row.size = LineSize::Single;
         }
     }
 
@@ -221,3 +1489,490 @@ impl Turm {
         self.grid.scroll_down(delta, force);
     }
 }
+
+/// Tab stops every 8 columns for the columns in `start..end`
+fn default_tab_stops(start: usize, end: usize) -> impl Iterator<Item = bool> {
+    (start..end).map(|x| x % 8 == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ansi::Ansi, clipboard::Clipboard, color::Color};
+
+    fn turm_with(columns: usize, lines: usize, input: &[u8]) -> Turm {
+        let mut turm = Turm::new(columns, lines);
+        turm.parse(Ansi::new().push(input));
+        turm
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut t = turm_with(10, 5, b"\x1b[?25l\x1b[?1049h\x1b[4h");
+        assert!(!t.modes.show_cursor);
+        assert!(t.modes.insert);
+
+        t.parse(Ansi::new().push(b"\x1b[?25$p\x1b[4$p\x1b[?1234$p\x1b[?7$p"));
+        assert_eq!(
+            t.take_responses(),
+            b"\x1b[?25;2$y\x1b[4;1$y\x1b[?1234;0$y\x1b[?7;1$y"
+        );
+    }
+
+    #[test]
+    fn test_alt_screen() {
+        let mut t = turm_with(10, 3, b"abc\r\n1\r\n2\r\n3");
+        t.parse(Ansi::new().push(b"\x1b[?1049hxyz\r\n1\r\n2\r\n3"));
+        assert_eq!(t.grid[0][0].c, Some('1'));
+        assert_eq!(t.grid[1][0].c, Some('2'));
+
+        t.parse(Ansi::new().push(b"\x1b[?1049l"));
+        assert_eq!(t.grid[0][0].c, Some('1'));
+        assert_eq!(t.grid[2][0].c, Some('3'));
+        assert_eq!((t.cursor.pos.x, t.cursor.pos.y), (1, 2));
+    }
+
+    #[test]
+    fn test_scrolling_region() {
+        let mut t = turm_with(5, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r");
+        assert_eq!((t.cursor.pos.x, t.cursor.pos.y), (0, 0));
+
+        // Line feeds at the bottom margin only scroll the region
+        t.parse(Ansi::new().push(b"\x1b[4;1H\nx"));
+        let column: Vec<_> = (0..5).map(|y| t.grid[y][0].c).collect();
+        assert_eq!(
+            column,
+            [Some('1'), Some('3'), Some('4'), Some('x'), Some('5')]
+        );
+
+        t.parse(Ansi::new().push(b"\x1b[2H\x1b[L\x1b[4H\x1b[M\x1b[T"));
+        let column: Vec<_> = (0..5).map(|y| t.grid[y][0].c).collect();
+        assert_eq!(column, [Some('1'), None, None, Some('3'), Some('5')]);
+
+        // Lines outside the region are left alone
+        t.parse(Ansi::new().push(b"\x1b[5H\x1b[L"));
+        assert_eq!(t.grid[4][0].c, Some('5'));
+
+        // Resetting the region homes the cursor, RI then scrolls everything
+        t.parse(Ansi::new().push(b"\x1b[r\x1bM"));
+        assert!(t.grid[0][0].c.is_none());
+        assert_eq!(t.grid[1][0].c, Some('1'));
+    }
+
+    #[test]
+    fn test_character_editing() {
+        let line = |t: &Turm| {
+            (0..8)
+                .map(|x| t.grid[0][x].c.unwrap_or('.'))
+                .collect::<String>()
+        };
+
+        let mut t = turm_with(8, 2, b"abcdef\x1b[3G");
+        t.parse(Ansi::new().push(b"\x1b[2@"));
+        assert_eq!(line(&t), "ab..cdef");
+
+        t.parse(Ansi::new().push(b"\x1b[3P"));
+        assert_eq!(line(&t), "abdef...");
+
+        t.parse(Ansi::new().push(b"\x1b[4h12\x1b[4l"));
+        assert_eq!(line(&t), "ab12def.");
+
+        t.parse(Ansi::new().push(b"\x1b[9X"));
+        assert_eq!(line(&t), "ab12....");
+
+        t.parse(Ansi::new().push(b"x\x1b[2b"));
+        assert_eq!(line(&t), "ab12xxx.");
+    }
+
+    #[test]
+    fn test_erase() {
+        let mut t = turm_with(3, 3, b"abc\r\ndef\r\nghi\x1b[2;2H\x1b[41m\x1b[1K");
+        assert_eq!(t.grid[1][0].c, None);
+        assert_eq!(t.grid[1][1].c, None);
+        assert_eq!(t.grid[1][2].c, Some('f'));
+        assert_eq!(t.grid[1][0].style.bg, t.current_style.bg);
+
+        t.parse(Ansi::new().push(b"\x1b[J"));
+        assert_eq!(t.grid[0][2].c, Some('c'));
+        assert!((1..3).all(|y| (0..3).all(|x| t.grid[y][x].c.is_none())));
+
+        t.parse(Ansi::new().push(b"\x1b[2J"));
+        assert!(t.grid[0][0].c.is_none());
+        t.grid.scroll_down(1, false);
+        assert!(t.grid[0][0].c.is_none());
+
+        // Clearing only keeps the screen in the scrollback when asked to
+        t.scrollback_on_clear = true;
+        t.parse(Ansi::new().push(b"\x1b[Hxyz\x1b[2J"));
+        t.grid.scroll_down(1, false);
+        assert_eq!(t.grid[0][0].c, Some('x'));
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut t = turm_with(20, 2, b"a\tb");
+        assert_eq!(t.grid[0][8].c, Some('b'));
+        assert!(t.grid[0][1].tab);
+
+        // Clear the stop at 8 and add one at 4
+        t.parse(Ansi::new().push(b"\x1b[9G\x1b[g\x1b[5G\x1bH\r\tx\t"));
+        assert_eq!(t.grid[0][4].c, Some('x'));
+        assert_eq!(t.cursor.pos.x, 16);
+
+        t.parse(Ansi::new().push(b"\x1b[2Z"));
+        assert_eq!(t.cursor.pos.x, 0);
+        t.parse(Ansi::new().push(b"\x1b[3g\x1b[I"));
+        assert_eq!(t.cursor.pos.x, 19);
+    }
+
+    #[test]
+    fn test_save_restore_cursor() {
+        // Pending wrap and style survive a save and restore
+        let mut t = turm_with(3, 3, b"abc\x1b[1m\x1b7\x1b[H\x1b[0m\x1b8d");
+        assert_eq!(t.grid[1][0].c, Some('d'));
+        assert!(t.grid[1][0].style.bold);
+
+        // The alternate screen has its own saved cursor
+        t.parse(Ansi::new().push(b"\x1b[3;3H\x1b[s\x1b[?1049h\x1b[2;2H\x1b[s"));
+        t.parse(Ansi::new().push(b"\x1b[H\x1b[u"));
+        assert_eq!((t.cursor.pos.x, t.cursor.pos.y), (1, 1));
+        t.parse(Ansi::new().push(b"\x1b[?1049l"));
+        assert_eq!((t.cursor.pos.x, t.cursor.pos.y), (2, 2));
+
+        // With DECOM the restored cursor is moved into the current region
+        t.parse(Ansi::new().push(b"\x1b[?6h\x1b7\x1b[2;3r\x1b8"));
+        assert_eq!(t.cursor.pos.y, 1);
+    }
+
+    #[test]
+    fn test_reports() {
+        let mut t = turm_with(10, 5, b"\x1b[3;4H\x1b[6n\x1b[?6n\x1b[5n\x1b[c\x05");
+        assert_eq!(
+            t.take_responses(),
+            b"\x1b[3;4R\x1b[?3;4;1R\x1b[0n\x1b[?62;4;22c"
+        );
+
+        t.answerback = String::from("turm");
+        t.parse(Ansi::new().push(b"\x1b[2;5r\x1b[?6h\x1b[2;1H\x1b[6n\x05"));
+        assert_eq!(t.take_responses(), b"\x1b[2;1Rturm");
+
+        // A cursor saved above the region is reported from its top
+        t.parse(Ansi::new().push(b"\x1b[r\x1b[H\x1b7\x1b[3;5r\x1b8\x1b[6n"));
+        assert_eq!(t.take_responses(), b"\x1b[1;1R");
+    }
+
+    #[test]
+    fn test_color_queries() {
+        let mut t = turm_with(
+            5,
+            2,
+            b"\x1b]4;1;rgb:12/34/56\x07\x1b]4;1;?\x07\x1b]11;?\x1b\\",
+        );
+        assert_eq!(
+            t.take_responses(),
+            b"\x1b]4;1;rgb:1212/3434/5656\x07\x1b]11;rgb:2e2e/3333/3f3f\x1b\\"
+        );
+
+        t.parse(Ansi::new().push(b"\x1b]104;1\x07\x1b]10;#ffffff\x07"));
+        assert_eq!(t.palette.get(PaletteColor::Indexed(1)), Color::RED);
+        assert_eq!(
+            t.palette.get(PaletteColor::Foreground),
+            Color::from_rgb(255, 255, 255)
+        );
+    }
+
+    #[test]
+    fn test_clipboard() {
+        let mut t = turm_with(5, 2, b"\x1b]52;c;aGVsbG8=\x07\x1b]52;p;?\x07");
+        assert_eq!(
+            t.take_clipboard_events(),
+            [ClipboardEvent::Store(
+                Clipboard::Clipboard,
+                String::from("hello")
+            )]
+        );
+
+        t.allow_clipboard_read = true;
+        t.parse(Ansi::new().push(b"\x1b]52;;?\x1b\\"));
+        let events = t.take_clipboard_events();
+        assert_eq!(
+            events[0].reply("hello"),
+            Some(b"\x1b]52;c;aGVsbG8=\x1b\\".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut t = turm_with(
+            5,
+            2,
+            b"\x07\x1b]99;i=1:d=0;Build\x07\x1b]99;i=2:d=0;x\x07\x1b]9;hi\x07\x1b]99;i=2:p=body;ok\x07",
+        );
+        assert!(t.take_bell());
+        assert!(!t.take_bell());
+        assert_eq!(
+            t.take_notifications(),
+            [
+                Notification {
+                    title: String::new(),
+                    body: String::from("hi"),
+                },
+                Notification {
+                    title: String::from("x"),
+                    body: String::from("ok"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dcs_queries() {
+        let mut t = turm_with(5, 4, b"\x1b[1;4:3;31;48:2::1:2:3m\x1b[2;3r\x1b[5 q");
+        t.take_responses();
+        t.parse(Ansi::new().push(b"\x1bP$qm\x1b\\\x1bP$qr\x1b\\\x1bP$q q\x1b\\\x1bP$qx\x1b\\"));
+        assert_eq!(
+            t.take_responses(),
+            b"\x1bP1$r0;1;4:3;31;48:2::1:2:3m\x1b\\\x1bP1$r2;3r\x1b\\\x1bP1$r5 q\x1b\\\x1bP0$r\x1b\\"
+        );
+
+        // colors, bel and an unknown name, through tmux
+        t.parse(Ansi::new().push(b"\x1bPtmux;\x1b\x1bP+q636F6C6F7273;62656C;7A7A\x1b\x1b\\\x1b\\"));
+        assert_eq!(
+            t.take_responses(),
+            b"\x1bP1+r636F6C6F7273=323536\x1b\\\x1bP1+r62656C=07\x1b\\\x1bP0+r7A7A\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_sixel() {
+        let mut t = turm_with(4, 3, b"ab\x1bP0;1q#1;2;100;0;0!10~-~~\x1b\\c\x1b[?2;1S");
+        assert!(t.grid[0][1].image.is_none());
+        assert_eq!(t.grid[0][2].image.map(|i| (i.x, i.y)), Some((0, 0)),);
+        assert_eq!(t.grid[0][3].image.map(|i| (i.x, i.y)), Some((1, 0)));
+        let id = t.grid[0][2].image.unwrap().id;
+        assert_eq!(t.grid.image(id).unwrap().image.height, 12);
+        // The text goes below the image
+        assert_eq!(t.grid[1][2].c, Some('c'));
+        assert_eq!(t.take_responses(), b"\x1b[?2;0;32;48S");
+    }
+
+    #[test]
+    fn test_kitty_graphics() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        // A 16x16 image shown in 2x1 cells, the cursor moves after it
+        let pixels = STANDARD.encode([0xff; 16 * 16 * 4]);
+        let mut t = turm_with(
+            5,
+            3,
+            format!("\x1b_Ga=T,s=16,v=16,i=5,c=2,r=1;{pixels}\x1b\\").as_bytes(),
+        );
+        assert_eq!(t.take_responses(), b"\x1b_Gi=5;OK\x1b\\");
+        assert_eq!(t.grid[0][1].image.map(|i| (i.x, i.y)), Some((1, 0)));
+        assert_eq!(t.cursor.pos.x, 2);
+        let id = t.grid[0][0].image.unwrap().id;
+        assert_eq!(t.grid.image(id).unwrap().cell_size, (8, 16));
+
+        // Placed again under the text, the cursor stays
+        t.parse(Ansi::new().push(b"\x1b_Ga=p,i=5,p=2,z=-1,C=1,q=1\x1b\\x"));
+        assert!(t.take_responses().is_empty());
+        assert_eq!(t.grid[0][2].c, Some('x'));
+        assert!(t.grid[0][2].image.is_some());
+
+        t.parse(Ansi::new().push(b"\x1b_Ga=p,i=9\x1b\\\x1b_Ga=d,d=i,i=5,p=2\x1b\\"));
+        assert_eq!(
+            t.take_responses(),
+            b"\x1b_Gi=9;ENOENT:image not found\x1b\\"
+        );
+        assert!(t.grid[0][2].image.is_none());
+        assert!(t.grid[0][0].image.is_some());
+        t.parse(Ansi::new().push(b"\x1b_Ga=d,d=I,i=5\x1b\\"));
+        assert!(t.grid[0][0].image.is_none());
+        assert!(t.kitty_images.get(5, 0).is_none());
+    }
+
+    #[test]
+    fn test_kitty_placeholders() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let pixels = STANDARD.encode([0xff; 16 * 16 * 4]);
+        let mut t = turm_with(
+            5,
+            3,
+            format!("\x1b_Gs=16,v=16,i=6,q=2;{pixels}\x1b\\\x1b_Ga=p,U=1,i=6,c=2,r=2,q=2\x1b\\")
+                .as_bytes(),
+        );
+        // The image id is in the color, the second cell continues the first
+        t.parse(Ansi::new().push(
+            "\x1b[38;5;6m\u{10EEEE}\u{305}\u{30D}\u{10EEEE}\r\n\u{10EEEE}\u{30D}".as_bytes(),
+        ));
+        assert!(t.take_responses().is_empty());
+        let cell = |y: usize, x: usize| t.grid[y][x].image.map(|i| (i.x, i.y));
+        assert_eq!(cell(0, 0), Some((1, 0)));
+        assert_eq!(cell(0, 1), Some((2, 0)));
+        assert_eq!(cell(1, 0), Some((0, 1)));
+        assert_eq!(t.grid[0][1].c, Some(graphics::PLACEHOLDER));
+        let id = t.grid[0][0].image.unwrap().id;
+        assert_eq!(t.grid.image(id).unwrap().cell_size, (8, 8));
+    }
+
+    #[test]
+    fn test_inline_image() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let mut png = vec![];
+        image::RgbaImage::new(16, 16)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let file = STANDARD.encode(png);
+        // Shown in 4 columns, the cursor goes after its last row
+        let t = turm_with(
+            6,
+            4,
+            format!("a\x1b]1337;File=width=4;inline=1:{file}\x07b").as_bytes(),
+        );
+        assert_eq!(t.grid[0][1].image.map(|i| (i.x, i.y)), Some((0, 0)));
+        assert_eq!(t.grid[1][4].image.map(|i| (i.x, i.y)), Some((3, 1)));
+        assert!(t.grid[2][1].image.is_none());
+        assert_eq!(t.grid[1][5].c, Some('b'));
+    }
+
+    #[test]
+    fn test_line_size() {
+        // The right half of a double width row is lost, text wraps at its
+        // middle
+        let mut t = turm_with(6, 3, b"abcdef\r\x1b#6xyzw\x1b[9G");
+        assert_eq!(t.grid[0].size, LineSize::DoubleWidth);
+        assert_eq!(t.grid[0].text(&t.grid), "xyz");
+        assert_eq!(t.grid[1].text(&t.grid), "w");
+        assert_eq!(t.cursor.pos.x, 5);
+
+        // Moving onto a double height row keeps the cursor in its left half
+        t.parse(Ansi::new().push(b"\x1b[3;6H\x1b#3\x1b[1;6H\x1b#5\x1b[2B"));
+        assert_eq!(t.grid[2].size, LineSize::DoubleHeightTop);
+        assert_eq!(t.grid[0].size, LineSize::Single);
+        assert_eq!((t.cursor.pos.x, t.cursor.pos.y), (2, 2));
+
+        t.parse(Ansi::new().push(b"\x1b[2J"));
+        assert_eq!(t.grid[2].size, LineSize::Single);
+
+        // Character edits stay in the used half
+        let mut t = turm_with(6, 2, b"\x1b#6abc\x1b[G\x1b[@");
+        assert_eq!(t.grid[0].text(&t.grid), " ab");
+        assert_eq!(t.grid[0][3].c, None);
+        t.parse(Ansi::new().push(b"\x1b[P"));
+        assert_eq!(t.grid[0].text(&t.grid), "ab");
+        t.parse(Ansi::new().push(b"\x1b[9X"));
+        assert_eq!(t.grid[0].text(&t.grid), "");
+    }
+
+    #[test]
+    fn test_charsets() {
+        // Line drawing in G0, then shifted in from G1
+        let mut t = turm_with(8, 3, b"\x1b(0lqk\x1b(Bq\x1b)0\x0ex\x0fx");
+        let row: String = (0..6).filter_map(|x| t.grid[0][x].c).collect();
+        assert_eq!(row, "┌─┐q│x");
+
+        // National sets need DECNRCM, the sets are saved with the cursor
+        t.parse(Ansi::new().push(b"\r\n\x1b(K[\x1b[?42h\x1b(K\x1b7\x1b(B[\x1b8["));
+        let row: String = (0..3).filter_map(|x| t.grid[1][x].c).collect();
+        assert_eq!(row, "[Ä");
+
+        // A single shift applies to one character
+        t.parse(Ansi::new().push(b"\r\n\x1b*A\x1bN##"));
+        let row: String = (0..2).filter_map(|x| t.grid[2][x].c).collect();
+        assert_eq!(row, "£#");
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        // A wide character that does not fit wraps to the next line
+        let mut t = turm_with(5, 3, b"ab\xe4\xb8\xad\xe6\x96\x87");
+        assert_eq!(t.grid[0].text(&t.grid), "ab中");
+        assert_eq!(t.grid[1][0].width, CellWidth::Wide);
+        assert_eq!(t.grid[1][1].width, CellWidth::Spacer);
+        assert_eq!(t.cursor.pos.x, 2);
+
+        // Overwriting half of a wide character blanks the other half
+        t.parse(Ansi::new().push(b"\x1b[2;2Hx\x1b[1;4H\x1b[P"));
+        assert_eq!(t.grid[1].text(&t.grid), " x");
+        assert_eq!(t.grid[0].text(&t.grid), "ab");
+        assert_eq!(t.grid[0][2].width, CellWidth::Normal);
+
+        // Ambiguous characters are wide in CJK contexts
+        t.ambiguous_wide = true;
+        t.parse(Ansi::new().push(b"\x1b[3H\xc2\xa7a"));
+        assert_eq!(t.grid[2][2].c, Some('a'));
+    }
+
+    #[test]
+    fn test_grapheme_clusters() {
+        // Zero width characters join the previous cell, a ZWJ sequence is
+        // split without mode 2027
+        let mut t = turm_with(10, 3, "e\u{301}x\r\n👨\u{200d}👩".as_bytes());
+        assert_eq!(t.grid[0].text(&t.grid), "e\u{301}x");
+        assert_eq!(t.grid[0][1].c, Some('x'));
+        assert_eq!(t.grid[1][2].c, Some('👩'));
+        assert_eq!(t.cursor.pos.x, 4);
+
+        t.parse(Ansi::new().push("\x1b[?2027h\x1b[3H👨\u{200d}👩❤\u{fe0f}a".as_bytes()));
+        let cluster = t.grid.cell_text(&t.grid[2][0]).unwrap();
+        assert_eq!(cluster, "👨\u{200d}👩");
+        // The emoji presentation of the heart is double width
+        assert_eq!(t.grid[2][2].width, CellWidth::Wide);
+        assert_eq!(t.grid[2][4].c, Some('a'));
+
+        t.parse(Ansi::new().push(b"\x1b[?2027$p"));
+        assert_eq!(t.take_responses(), b"\x1b[?2027;1$y");
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let t = turm_with(
+            10,
+            2,
+            b"\x1b]8;id=1;http://a;b\x1b\\ab\x1b[?1049hc\x1b]8;;\x1b\\d",
+        );
+        // The alternate screen keeps the cursor position
+        let link = t.grid[0][2].hyperlink.unwrap();
+        assert_eq!(t.grid.hyperlink(link).uri, "http://a;b");
+        assert_eq!(t.grid.hyperlink(link).id.as_deref(), Some("1"));
+        assert_eq!(t.grid[0][3].hyperlink, None);
+        assert_eq!(
+            t.inactive_grid[0][1].hyperlink,
+            t.inactive_grid[0][0].hyperlink
+        );
+    }
+
+    #[test]
+    fn test_current_dir() {
+        let mut t = Turm::new(5, 2);
+        t.shell_pid = Some(std::process::id() as i32);
+        assert_eq!(t.current_dir(), std::env::current_dir().ok());
+
+        t.parse(Ansi::new().push(b"\x1b]7;file:///tmp\x07"));
+        assert_eq!(t.current_dir(), Some(PathBuf::from("/tmp")));
+    }
+
+    #[test]
+    fn test_semantic_prompt() {
+        let mut t = turm_with(
+            10,
+            5,
+            b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07a\r\nb\r\n\x1b]133;D;1\x07",
+        );
+        assert_eq!(t.last_command_output().as_deref(), Some("a\nb"));
+        assert_eq!(t.grid[0].prompt.unwrap().exit_status, Some(1));
+        assert_eq!(t.last_command.unwrap().exit_status, Some(1));
+
+        // The next prompt does not belong to the output
+        t.parse(Ansi::new().push(b"\x1b]133;A\x07$ "));
+        assert_eq!(t.grid[3].zone, Zone::Prompt);
+        assert_eq!(t.last_command_output().as_deref(), Some("a\nb"));
+
+        // Moving back onto a marked row keeps its mark
+        t.parse(Ansi::new().push(b"\x1b[3;1H\n"));
+        assert_eq!(t.grid[3].zone, Zone::Prompt);
+        assert!(t.grid[3].prompt.is_some());
+    }
+}